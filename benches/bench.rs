@@ -1,12 +1,13 @@
+use std::net::UdpSocket;
 use std::path::PathBuf;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 use primitive_types::U256;
 
-use kindelia::bits::ProtoSerialize;
+use kindelia::bits::{ProtoSerialize, BLOCK_FORMAT_VERSION};
 use kindelia::hvm;
-use kindelia::net;
+use kindelia::net::{self, ProtoComm};
 use kindelia::node;
 use kindelia::util;
 
@@ -25,7 +26,7 @@ pub fn temp_dir() -> PathBuf {
 pub fn init_runtime(path: PathBuf) -> hvm::Runtime {
   let genesis_stmts =
     hvm::parse_code(kindelia::constants::GENESIS_CODE).expect("Genesis code parses.");
-  hvm::init_runtime(path, &genesis_stmts)
+  hvm::init_runtime(path, &genesis_stmts).expect("Runtime initializes.")
 }
 
 fn khvm_benches(c: &mut Criterion) {
@@ -48,6 +49,7 @@ criterion_group!(khvm, khvm_benches);
 
 fn max_message() -> node::Message<net::Address> {
   let max_block = node::Block {
+    version: BLOCK_FORMAT_VERSION,
     body: node::Body { data: vec![u8::MAX; node::MAX_BODY_SIZE] },
     prev: U256::MAX,
     time: u128::MAX,
@@ -107,6 +109,7 @@ fn block_with_txs_deserialize(c: &mut Criterion) {
     let body = node::Body::fill_from(std::iter::repeat(transaction));
 
     let block = node::Block {
+      version: BLOCK_FORMAT_VERSION,
       body,
       prev: U256::MAX,
       time: u128::MAX,
@@ -136,4 +139,29 @@ criterion_group!(
   block_with_txs_deserialize
 );
 
-criterion_main!(khvm, serialization);
+// Networking
+// ==========
+
+fn udp_receive(c: &mut Criterion) {
+  c.bench_function("udp_receive_64_pings", |b| {
+    let mut sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_nonblocking(true).unwrap();
+    let port = receiver.local_addr().unwrap().port();
+    let addr =
+      net::Address::IPv4 { val0: 127, val1: 0, val2: 0, val3: 1, port };
+    let ping = node::Message::Ping { magic: 0 };
+    // Reused across iterations, as the node does
+    let mut buffer = vec![0; net::RECV_BUFFER_SIZE];
+    let mut messages = Vec::new();
+    b.iter(|| {
+      sender.proto_send(vec![addr; 64], &ping);
+      receiver.proto_recv(&mut buffer, &mut messages);
+      black_box(messages.drain(..).count());
+    })
+  });
+}
+
+criterion_group!(networking, udp_receive);
+
+criterion_main!(khvm, serialization, networking);
@@ -11,7 +11,9 @@ use crate::net::ProtoComm;
 use crate::node;
 
 use super::{
-  BlockInfo, CtrInfo, FuncInfo, Hash, HexStatement, Name, RegInfo, Stats,
+  BlockInfo, BlockWork, ChainParams, CtrInfo, DifficultyInfo, DryRunInfo,
+  FeeEstimate, FuncInfo, Hash, Health, HexStatement, Name, PeerCounts, RegInfo,
+  Stats, TargetInfo, TipInfo, TransactionProof, TxStatus, WorkInfo,
 };
 
 pub struct ApiClient {
@@ -90,10 +92,33 @@ impl ApiClient {
     self.get::<Stats>("/stats").await
   }
 
+  pub async fn get_health(&self) -> ApiResult<Health> {
+    self.get::<Health>("/health").await
+  }
+
+  pub async fn get_chain_params(&self) -> ApiResult<ChainParams> {
+    self.get::<ChainParams>("/chain/params").await
+  }
+
   pub async fn get_blocks(&self) -> ApiResult<Vec<BlockInfo>> {
     self.get::<Vec<BlockInfo>>("/blocks").await
   }
 
+  pub async fn get_tip(&self) -> ApiResult<TipInfo> {
+    self.get::<TipInfo>("/tip").await
+  }
+
+  /// Gets the block `offset` blocks behind the tip.
+  pub async fn get_block_from_tip(&self, offset: u64) -> ApiResult<BlockInfo> {
+    self.get::<BlockInfo>(&format!("/tip/{}", offset)).await
+  }
+
+  /// Gets the mana spent by the blocks with heights from `start` to `end`,
+  /// inclusive.
+  pub async fn get_mana_usage(&self, start: u64, end: u64) -> ApiResult<u64> {
+    self.get::<u64>(&format!("/mana/{}/{}", start, end)).await
+  }
+
   pub async fn get_block_hash(&self, index: u64) -> ApiResult<String> {
     self.get::<String>(&format!("/block-hash/{}", index)).await
   }
@@ -102,10 +127,93 @@ impl ApiClient {
     self.get::<Option<BlockInfo>>(&format!("/blocks/{}", id)).await
   }
 
+  pub async fn get_block_raw(&self, id: Hash) -> ApiResult<Vec<u8>> {
+    let hex = self.get::<String>(&format!("/blocks/{}/raw", id)).await?;
+    hex::decode(hex).map_err(|e| e.to_string())
+  }
+
+  /// Adds a block to the node as if it came from the network. Only served by
+  /// nodes built with the `test-api` feature.
+  #[cfg(feature = "test-api")]
+  pub async fn add_block(&self, block: &crate::node::Block) -> ApiResult<()> {
+    let block_hex = hex::encode(block.to_bytes());
+    self.req(Method::POST, "/blocks/add", Some(block_hex)).await
+  }
+
+  /// Peer that first sent a recently received block.
+  pub async fn get_block_source<C: ProtoComm>(
+    &self,
+    id: Hash,
+  ) -> ApiResult<C::Address>
+  where
+    C::Address: serde::de::DeserializeOwned,
+  {
+    self.get::<C::Address>(&format!("/blocks/{}/source", id)).await
+  }
+
+  pub async fn get_block_children(&self, id: Hash) -> ApiResult<Vec<Hash>> {
+    self.get::<Vec<Hash>>(&format!("/blocks/{}/children", id)).await
+  }
+
+  pub async fn get_block_work(&self, id: Hash) -> ApiResult<BlockWork> {
+    self.get::<BlockWork>(&format!("/blocks/{}/work", id)).await
+  }
+
+  /// Asks the node to request a block from its peers, e.g. a missing
+  /// ancestor it is stuck on.
+  pub async fn request_block(&self, id: Hash) -> ApiResult<()> {
+    self.req(Method::POST, &format!("/blocks/{}/request", id), None::<()>).await
+  }
+
+  /// Forces the node's tip to a known block. The node must allow it, with
+  /// `allow_set_tip`.
+  pub async fn set_tip(&self, id: Hash) -> ApiResult<()> {
+    self.req(Method::POST, &format!("/blocks/{}/set-tip", id), None::<()>).await
+  }
+
+  pub async fn get_transaction_status(&self, hash: Hash) -> ApiResult<TxStatus> {
+    self.get::<TxStatus>(&format!("/transactions/{}/status", hash)).await
+  }
+
+  /// Gets the bytes of a transaction on the chain or the mempool.
+  pub async fn get_transaction(&self, hash: Hash) -> ApiResult<Vec<u8>> {
+    let hex = self.get::<String>(&format!("/transactions/{}", hash)).await?;
+    hex::decode(hex).map_err(|e| e.to_string())
+  }
+
+  /// Gets a proof that a transaction is included in a main chain block.
+  pub async fn get_transaction_proof(
+    &self,
+    hash: Hash,
+  ) -> ApiResult<TransactionProof> {
+    self
+      .get::<TransactionProof>(&format!("/transactions/{}/proof", hash))
+      .await
+  }
+
+  pub async fn get_fee_estimate(&self) -> ApiResult<FeeEstimate> {
+    self.get::<FeeEstimate>("/mempool/fees").await
+  }
+
   pub async fn get_functions(&self) -> ApiResult<Vec<Name>> {
     self.get::<Vec<Name>>("/functions").await
   }
 
+  /// Gets a page of the function names starting with `prefix`, in
+  /// alphabetical order.
+  pub async fn get_functions_page(
+    &self,
+    offset: usize,
+    limit: usize,
+    prefix: Option<&str>,
+  ) -> ApiResult<Vec<Name>> {
+    let mut path = format!("/functions?offset={}&limit={}", offset, limit);
+    if let Some(prefix) = prefix {
+      path.push_str(&format!("&prefix={}", prefix));
+    }
+    self.get::<Vec<Name>>(&path).await
+  }
+
   pub async fn get_function(&self, name: Name) -> ApiResult<FuncInfo> {
     self.get::<FuncInfo>(&format!("/functions/{}", name)).await
   }
@@ -114,6 +222,14 @@ impl ApiClient {
     self.get::<Term>(&format!("/functions/{}/state", name)).await
   }
 
+  /// Reads the states of `names`, at most `node::MAX_STATES_PER_QUERY`.
+  pub async fn get_function_states(
+    &self,
+    names: Vec<Name>,
+  ) -> ApiResult<Vec<Option<Term>>> {
+    self.req(Method::POST, "/state/functions", Some(names)).await
+  }
+
   pub async fn get_constructor(&self, name: Name) -> ApiResult<CtrInfo> {
     self.get::<CtrInfo>(&format!("/constructor/{}", name)).await
   }
@@ -125,14 +241,77 @@ impl ApiClient {
     self.req(Method::POST, "/run", Some(code)).await
   }
 
+  /// Runs `code` without publishing it, returning the states it would change.
+  pub async fn dry_run(
+    &self,
+    code: Vec<HexStatement>,
+  ) -> ApiResult<DryRunInfo> {
+    self.req(Method::POST, "/run/diff", Some(code)).await
+  }
+
   // I'm not sure what the return type should be.
   pub async fn publish_code(
     &self,
     code: Vec<HexStatement>,
-  ) -> ApiResult<Vec<Result<(), ()>>> {
+  ) -> ApiResult<Vec<Result<(), String>>> {
     self.req(Method::POST, "/publish", Some(code)).await
   }
 
+  pub async fn set_mining(&self, enabled: bool) -> ApiResult<bool> {
+    self.req(Method::POST, "/mining", Some(enabled)).await
+  }
+
+  /// Stops the node from accepting blocks, so that its data directory can be
+  /// backed up. It resumes on its own after a while; see `MAX_PAUSE_TIME`.
+  pub async fn pause(&self) -> ApiResult<()> {
+    self.req::<(), ()>(Method::POST, "/pause", None).await
+  }
+
+  pub async fn resume(&self) -> ApiResult<()> {
+    self.req::<(), ()>(Method::POST, "/resume", None).await
+  }
+
+  /// Seconds a miner doing `hash_rate` hashes per second takes, on
+  /// average, to find the next block.
+  pub async fn get_expected_block_time(
+    &self,
+    hash_rate: u64,
+  ) -> ApiResult<f64> {
+    let path = format!("/mining/expected-time?hash_rate={}", hash_rate);
+    self.get::<f64>(&path).await
+  }
+
+  /// Gets a block to mine: its parent, body and target.
+  pub async fn get_work(&self) -> ApiResult<WorkInfo> {
+    self.get::<WorkInfo>("/mining/work").await
+  }
+
+  /// Sends a mined block to the node.
+  pub async fn submit_block(&self, block: &crate::node::Block) -> ApiResult<()> {
+    let block_hex = hex::encode(block.to_bytes());
+    self.req(Method::POST, "/mining/submit", Some(block_hex)).await
+  }
+
+  /// Gets the target a block mined on the tip must hit, for external miners.
+  pub async fn get_target(&self) -> ApiResult<TargetInfo> {
+    self.get::<TargetInfo>("/mining/target").await
+  }
+
+  /// Gets the difficulty of the last `count` retarget periods, oldest first,
+  /// at most `node::MAX_DIFFICULTY_HISTORY`.
+  pub async fn get_difficulty_history(
+    &self,
+    count: usize,
+  ) -> ApiResult<Vec<DifficultyInfo>> {
+    let path = format!("/mining/difficulty?count={}", count);
+    self.get::<Vec<DifficultyInfo>>(&path).await
+  }
+
+  /// Exports the node's runtime state to `name`, on its exports directory.
+  pub async fn export_state(&self, name: &str) -> ApiResult<()> {
+    self.req(Method::POST, "/state/export", Some(name)).await
+  }
+
   pub async fn get_peers<C: ProtoComm>(
     &self,
     all: bool,
@@ -147,6 +326,10 @@ impl ApiClient {
     }
   }
 
+  pub async fn get_peer_counts(&self) -> ApiResult<PeerCounts> {
+    self.get::<PeerCounts>("/peers/count").await
+  }
+
   pub async fn get_reg_info(&self, name: &str) -> ApiResult<RegInfo> {
     self.get::<RegInfo>(&format!("/reg/{}", name)).await
   }
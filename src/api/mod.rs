@@ -8,17 +8,18 @@
 pub mod client;
 pub mod server;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fmt::{self, Display};
 
 use primitive_types::U256;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 
 use crate::bits::ProtoSerialize;
+use crate::crypto::Keccakable;
 use crate::hvm;
-use crate::net::ProtoComm;
+use crate::net::{ProtoAddr, ProtoComm};
 use crate::node;
 use crate::util;
 
@@ -175,6 +176,98 @@ pub struct Stats {
   pub fun_count: u64,
   pub ctr_count: u64,
   pub reg_count: u64,
+  pub hash_rate: u64,
+  pub seconds_since_last_block: u64,
+  /// Whether the node is catching up with the network. It doesn't mine
+  /// meanwhile.
+  pub syncing: bool,
+  /// Whether peers have been reporting competing tips at the same height
+  /// for a while, hinting that the network is splitting.
+  pub chain_split: bool,
+  /// Whether some blocks couldn't be saved to disk, e.g. as it is full. The
+  /// node keeps validating, but doesn't mine until they are saved.
+  pub degraded: bool,
+  /// Messages handled since the node started, by type.
+  pub messages: MessageCounts,
+  /// Messages handled per second, over the last heartbeat interval.
+  pub message_rate: f64,
+  /// Slowest blocks to compute among the latest ones, slowest first.
+  pub slowest_blocks: Vec<BlockTiming>,
+}
+
+/// Internal consistency of the node's state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Health {
+  /// Runtime tick, i.e., how many blocks its state reflects.
+  pub tick: u64,
+  pub tip_height: u64,
+  /// Tick of an imported state, which the runtime can be ahead of the tip at.
+  pub state_floor: u64,
+  /// Whether the runtime state reflects exactly the main chain up to the
+  /// tip. If it doesn't, the node is diverging from the network.
+  pub consistent: bool,
+  /// Seconds since the tip last moved. A large value means the node stalled
+  /// or is partitioned from the network.
+  pub seconds_since_last_block: u64,
+}
+
+/// The chain a node follows, and how much of its history it can serve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainParams {
+  pub network_id: u32,
+  pub genesis_hash: Hash,
+  pub storage: crate::config::StorageMode,
+  /// How many blocks behind the tip the node keeps, if it prunes.
+  pub prune_depth: Option<u64>,
+  /// Height of the oldest block held. Blocks, results and replays below it
+  /// are not served.
+  pub prune_horizon: u64,
+}
+
+/// How long a block took to compute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockTiming {
+  pub hash: Hash,
+  pub height: u64,
+  /// Computation time, in milliseconds.
+  pub millis: u64,
+  /// Mana spent by the block's statements.
+  pub mana: u64,
+}
+
+/// How many messages of each type a node handled. An unusual mix, like a
+/// flood of block requests, can point to a misbehaving peer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MessageCounts {
+  pub give_me_that_block: u64,
+  pub notice_these_blocks: u64,
+  pub please_mine_this_transaction: u64,
+  pub ping: u64,
+  pub pong: u64,
+}
+
+impl MessageCounts {
+  pub fn count<A: ProtoAddr>(&mut self, msg: &node::Message<A>) {
+    let counter = match msg {
+      node::Message::GiveMeThatBlock { .. } => &mut self.give_me_that_block,
+      node::Message::NoticeTheseBlocks { .. } => &mut self.notice_these_blocks,
+      node::Message::PleaseMineThisTransaction { .. } => {
+        &mut self.please_mine_this_transaction
+      }
+      node::Message::Ping { .. } => &mut self.ping,
+      node::Message::Pong { .. } => &mut self.pong,
+    };
+    *counter += 1;
+  }
+
+  /// Messages handled, of every type.
+  pub fn total(&self) -> u64 {
+    self.give_me_that_block
+      + self.notice_these_blocks
+      + self.please_mine_this_transaction
+      + self.ping
+      + self.pong
+  }
 }
 
 impl From<&node::Transaction> for String {
@@ -184,7 +277,7 @@ impl From<&node::Transaction> for String {
 }
 
 #[serde_as]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockRepr {
   #[serde(with = "u128_time_ser")]
   pub time: u128, // block timestamp
@@ -262,12 +355,157 @@ impl From<&node::Block> for BlockRepr {
   }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockInfo {
   pub block: BlockRepr,
   pub hash: Hash,
   pub height: u64,
+  /// Results of running the block's statements. `None` for blocks that were
+  /// never on the main chain, as only those are computed.
   pub results: Option<Vec<hvm::StatementResult>>,
+  /// Runtime tick right after the block was computed, i.e., the first tick
+  /// whose state includes its effects. Usually its height, but lower when a
+  /// failing statement undid the tick of this block or of an ancestor;
+  /// `None` when never computed.
+  pub tick: Option<u64>,
+}
+
+/// A statement result streamed as its block is computed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultEvent {
+  pub block: Hash,
+  pub result: hvm::StatementResult,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum TxStatus {
+  /// Waiting on the mempool.
+  Pending,
+  /// Included on a block of the main chain.
+  Confirmed { block: Hash, height: u64 },
+  /// Not found on the mempool nor on the main chain.
+  Unknown,
+}
+
+/// Proof that a transaction is included in a block, for clients that don't
+/// download block bodies. Only version 1 blocks can have one, as older ones
+/// commit to their whole body.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionProof {
+  pub transaction: Hash,
+  pub block: Hash,
+  pub height: u64,
+  pub version: u64,
+  pub prev: Hash,
+  #[serde_as(as = "DisplayFromStr")]
+  pub time: u128,
+  #[serde_as(as = "DisplayFromStr")]
+  pub meta: u128,
+  pub transactions_root: Hash,
+  pub body_hash: Hash,
+  /// See `node::transaction_path`.
+  pub path: Vec<(Hash, bool)>,
+}
+
+impl TransactionProof {
+  /// Checks that the path leads to the transactions root, and that the block
+  /// hash commits to it. Whether the block has enough work and is on the main
+  /// chain is left for the client to check.
+  pub fn verify(&self) -> bool {
+    let path: Vec<_> =
+      self.path.iter().map(|(hash, left)| ((*hash).into(), *left)).collect();
+    let tx_hash = self.transaction.into();
+    let root = node::transactions_root_from_path(tx_hash, &path);
+    let header = node::BlockHeader {
+      version: self.version,
+      prev: self.prev.into(),
+      time: self.time,
+      meta: self.meta,
+      transactions_root: self.transactions_root.into(),
+      body_hash: self.body_hash.into(),
+    };
+    let bhash = U256::from(&header.keccak256());
+    root == U256::from(self.transactions_root)
+      && bhash == U256::from(self.block)
+  }
+}
+
+/// Distribution of the priority scores on the mempool.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeeEstimate {
+  pub pool_size: usize,
+  pub min: u64,
+  pub p25: u64,
+  pub median: u64,
+  pub p75: u64,
+  pub max: u64,
+  /// Lowest score that makes into the next block; 0 if it isn't full.
+  pub next_block: u64,
+  /// Lowest score that makes into the block after the next; 0 if it isn't full.
+  pub second_block: u64,
+}
+
+/// Hash and height of the node's current tip.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TipInfo {
+  pub hash: Hash,
+  pub height: u64,
+}
+
+/// Difficulty of a retarget period, starting at `height`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DifficultyInfo {
+  pub height: u64,
+  /// Expected hashes per block.
+  pub difficulty: Hash,
+}
+
+/// What a block mined on the tip must hit.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TargetInfo {
+  /// Lowest hash a block mined on the tip may have.
+  pub target: Hash,
+  /// Expected hashes per block at that target.
+  pub difficulty: Hash,
+}
+
+/// Work for an external miner: a block on `prev`, with `body`, whose hash
+/// hits `target`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkInfo {
+  pub prev: Hash,
+  /// Format version the block must be in.
+  pub version: u64,
+  /// Block body, hex-encoded.
+  pub body: String,
+  pub target: Hash,
+}
+
+/// Fork choice data of a block: its target, and the work accumulated by the
+/// chain up to it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockWork {
+  pub target: Hash,
+  pub work: Hash,
+}
+
+/// What running some statements would do, without publishing them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DryRunInfo {
+  pub results: Vec<hvm::StatementResult>,
+  /// Function states the statements would change, sorted by name.
+  pub diffs: Vec<hvm::StateDiff>,
+  /// Whether more states changed than the ones listed.
+  pub truncated: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerCounts {
+  pub total: usize,
+  pub active: usize,
+  pub inbound: usize,
+  pub outbound: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -292,26 +530,103 @@ pub struct RegInfo {
 pub type ReqAnsSend<T> = oneshot::Sender<T>;
 pub type ReqAnsRecv<T> = oneshot::Receiver<T>;
 
-type PublishResults = Vec<Result<(), ()>>;
+/// Stream of statement results, paired with their block's hash. Holds at
+/// most `node::RESULTS_STREAM_BUFFER` pending results.
+pub type ResultsSend = mpsc::Sender<(U256, hvm::StatementResult)>;
+pub type ResultsRecv = mpsc::Receiver<(U256, hvm::StatementResult)>;
+
+type PublishResults = Vec<Result<(), String>>;
 
 pub enum NodeRequest<C: ProtoComm> {
   GetStats {
     tx: ReqAnsSend<Stats>,
   },
+  GetHealth {
+    tx: ReqAnsSend<Health>,
+  },
+  GetChainParams {
+    tx: ReqAnsSend<ChainParams>,
+  },
   GetBlockHash {
     index: u64,
     tx: ReqAnsSend<Option<U256>>,
   },
+  /// Hash and height of the tip.
+  GetTip {
+    tx: ReqAnsSend<(U256, u64)>,
+  },
   GetBlock {
     hash: U256,
     tx: ReqAnsSend<Option<BlockInfo>>,
   },
+  /// Block `offset` blocks behind the tip, on the main chain.
+  GetBlockFromTip {
+    offset: u64,
+    tx: ReqAnsSend<Option<BlockInfo>>,
+  },
+  /// Mana spent by the statements of the main chain blocks with heights in
+  /// `range`, inclusive.
+  GetManaUsage {
+    range: (u64, u64),
+    tx: ReqAnsSend<u64>,
+  },
   GetBlocks {
     range: (i64, i64),
     tx: ReqAnsSend<Vec<BlockInfo>>,
   },
+  GetBlockRaw {
+    hash: U256,
+    tx: ReqAnsSend<Option<Vec<u8>>>,
+  },
+  GetChildren {
+    hash: U256,
+    tx: ReqAnsSend<Option<Vec<U256>>>,
+  },
+  /// Adds a block as if it had been received from the network. Meant for
+  /// tests building specific chain shapes.
+  #[cfg(any(test, feature = "test-api"))]
+  AddBlock {
+    block: node::Block,
+    tx: ReqAnsSend<()>,
+  },
+  /// Peer that first sent a block, if it was received recently.
+  GetBlockSource {
+    hash: U256,
+    tx: ReqAnsSend<Option<C::Address>>,
+  },
+  /// Asks a random peer for a block, to recover from a stalled sync.
+  RequestBlock {
+    bhash: U256,
+    tx: ReqAnsSend<Result<(), String>>,
+  },
+  /// Target and accumulated work of a block.
+  GetBlockWork {
+    hash: U256,
+    tx: ReqAnsSend<Option<(U256, U256)>>,
+  },
+  GetTransactionStatus {
+    hash: U256,
+    tx: ReqAnsSend<TxStatus>,
+  },
+  /// A transaction on the main chain or the mempool, by its hash.
+  GetTransaction {
+    hash: U256,
+    tx: ReqAnsSend<Option<node::Transaction>>,
+  },
+  /// Inclusion proof of a transaction on the main chain.
+  GetTransactionProof {
+    hash: U256,
+    tx: ReqAnsSend<Option<TransactionProof>>,
+  },
+  GetFeeEstimate {
+    tx: ReqAnsSend<FeeEstimate>,
+  },
+  /// A page of the deployed function names, see `Node::get_functions`.
   GetFunctions {
-    tx: ReqAnsSend<HashSet<u128>>,
+    offset: usize,
+    limit: Option<usize>,
+    prefix: Option<String>,
+    tx: ReqAnsSend<Vec<Name>>,
   },
   GetFunction {
     name: Name,
@@ -321,10 +636,19 @@ pub enum NodeRequest<C: ProtoComm> {
     name: Name,
     tx: ReqAnsSend<Option<hvm::Term>>,
   },
+  /// Reads the states of many functions at once, in the given order. Unknown
+  /// functions, or states too big to read, get `None`.
+  GetStates {
+    names: Vec<Name>,
+    tx: ReqAnsSend<Vec<Option<hvm::Term>>>,
+  },
   GetPeers {
     all: bool,
     tx: ReqAnsSend<Vec<node::Peer<C::Address>>>,
   },
+  GetPeerCounts {
+    tx: ReqAnsSend<PeerCounts>,
+  },
   GetConstructor {
     name: Name,
     tx: ReqAnsSend<Option<CtrInfo>>,
@@ -349,8 +673,72 @@ pub enum NodeRequest<C: ProtoComm> {
   },
   Publish {
     code: Vec<hvm::Statement>,
+    /// Mempool priority; see `Node::add_transaction_with_priority`.
+    priority: u64,
     tx: ReqAnsSend<PublishResults>,
   },
+  DryRun {
+    code: Vec<hvm::Statement>,
+    tx: ReqAnsSend<DryRunInfo>,
+  },
+  SetMining {
+    enabled: bool,
+    tx: ReqAnsSend<Result<(), String>>,
+  },
+  /// Seconds a miner with the given hash rate takes, on average, to find a
+  /// block on top of the current tip.
+  GetExpectedBlockTime {
+    hashes_per_second: u64,
+    tx: ReqAnsSend<f64>,
+  },
+  /// Streams the results of the statements of every block computed from now
+  /// on, including blocks computed again on a reorg. With a `filter`, only
+  /// statements that mention that name are streamed; see
+  /// `node::statement_mentions`. Ends when the receiver is dropped, or when
+  /// it falls behind; refused past `node::MAX_RESULTS_SUBSCRIBERS`.
+  SubscribeResults {
+    filter: Option<Name>,
+    tx: ReqAnsSend<Result<ResultsRecv, String>>,
+  },
+  /// Forces the tip to a known block. Only served when enabled with
+  /// `ApiConfig::allow_set_tip`.
+  SetTip {
+    bhash: U256,
+    tx: ReqAnsSend<Result<(), String>>,
+  },
+  /// Stops accepting blocks, so that the data directory can be backed up.
+  Pause {
+    tx: ReqAnsSend<Result<(), String>>,
+  },
+  Resume {
+    tx: ReqAnsSend<Result<(), String>>,
+  },
+  /// What the built-in miner would mine next: the tip, the format version
+  /// due, a body filled from the mempool and the target to hit.
+  GetWork {
+    tx: ReqAnsSend<(U256, u64, node::Body, U256)>,
+  },
+  /// Adds a block mined externally, after checking it extends an accepted
+  /// block and hits its target.
+  SubmitBlock {
+    block: node::Block,
+    tx: ReqAnsSend<Result<(), String>>,
+  },
+  /// Difficulty of the last `count` retarget periods of the main chain,
+  /// paired with the height each started at, oldest first.
+  GetDifficultyHistory {
+    count: usize,
+    tx: ReqAnsSend<Vec<(u64, U256)>>,
+  },
+  /// Target of the tip, which its children must hit.
+  GetTarget {
+    tx: ReqAnsSend<U256>,
+  },
+  /// Exports the runtime state to `name`, on the node's exports directory.
+  ExportState {
+    name: String,
+    tx: ReqAnsSend<Result<(), String>>,
+  },
 }
 
 impl<C: ProtoComm> NodeRequest<C> {
@@ -358,21 +746,98 @@ impl<C: ProtoComm> NodeRequest<C> {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetStats { tx }, rx)
   }
+  pub fn get_health() -> (Self, ReqAnsRecv<Health>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetHealth { tx }, rx)
+  }
+  pub fn get_chain_params() -> (Self, ReqAnsRecv<ChainParams>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetChainParams { tx }, rx)
+  }
   pub fn get_block_hash(index: u64) -> (Self, ReqAnsRecv<Option<U256>>) {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetBlockHash { index, tx }, rx)
   }
+  pub fn get_tip() -> (Self, ReqAnsRecv<(U256, u64)>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetTip { tx }, rx)
+  }
   pub fn get_block(hash: U256) -> (Self, ReqAnsRecv<Option<BlockInfo>>) {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetBlock { hash, tx }, rx)
   }
+  pub fn get_block_from_tip(
+    offset: u64,
+  ) -> (Self, ReqAnsRecv<Option<BlockInfo>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetBlockFromTip { offset, tx }, rx)
+  }
+  pub fn get_mana_usage(range: (u64, u64)) -> (Self, ReqAnsRecv<u64>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetManaUsage { range, tx }, rx)
+  }
   pub fn get_blocks(range: (i64, i64)) -> (Self, ReqAnsRecv<Vec<BlockInfo>>) {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetBlocks { range, tx }, rx)
   }
-  pub fn get_functions() -> (Self, ReqAnsRecv<HashSet<u128>>) {
+  pub fn get_block_raw(hash: U256) -> (Self, ReqAnsRecv<Option<Vec<u8>>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetBlockRaw { hash, tx }, rx)
+  }
+  pub fn get_children(hash: U256) -> (Self, ReqAnsRecv<Option<Vec<U256>>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetChildren { hash, tx }, rx)
+  }
+  #[cfg(any(test, feature = "test-api"))]
+  pub fn add_block(block: node::Block) -> (Self, ReqAnsRecv<()>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::AddBlock { block, tx }, rx)
+  }
+  pub fn get_block_source(
+    hash: U256,
+  ) -> (Self, ReqAnsRecv<Option<C::Address>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetBlockSource { hash, tx }, rx)
+  }
+  pub fn request_block(
+    bhash: U256,
+  ) -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::RequestBlock { bhash, tx }, rx)
+  }
+  pub fn get_block_work(
+    hash: U256,
+  ) -> (Self, ReqAnsRecv<Option<(U256, U256)>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetBlockWork { hash, tx }, rx)
+  }
+  pub fn get_transaction_status(hash: U256) -> (Self, ReqAnsRecv<TxStatus>) {
     let (tx, rx) = oneshot::channel();
-    (NodeRequest::GetFunctions { tx }, rx)
+    (NodeRequest::GetTransactionStatus { hash, tx }, rx)
+  }
+  pub fn get_transaction(
+    hash: U256,
+  ) -> (Self, ReqAnsRecv<Option<node::Transaction>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetTransaction { hash, tx }, rx)
+  }
+  pub fn get_transaction_proof(
+    hash: U256,
+  ) -> (Self, ReqAnsRecv<Option<TransactionProof>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetTransactionProof { hash, tx }, rx)
+  }
+  pub fn get_fee_estimate() -> (Self, ReqAnsRecv<FeeEstimate>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetFeeEstimate { tx }, rx)
+  }
+  pub fn get_functions(
+    offset: usize,
+    limit: Option<usize>,
+    prefix: Option<String>,
+  ) -> (Self, ReqAnsRecv<Vec<Name>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetFunctions { offset, limit, prefix, tx }, rx)
   }
   pub fn get_function(name: Name) -> (Self, ReqAnsRecv<Option<FuncInfo>>) {
     let (tx, rx) = oneshot::channel();
@@ -382,12 +847,22 @@ impl<C: ProtoComm> NodeRequest<C> {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetState { name, tx }, rx)
   }
+  pub fn get_states(
+    names: Vec<Name>,
+  ) -> (Self, ReqAnsRecv<Vec<Option<hvm::Term>>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetStates { names, tx }, rx)
+  }
   pub fn get_peers(
     all: bool,
   ) -> (Self, ReqAnsRecv<Vec<node::Peer<C::Address>>>) {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetPeers { all, tx }, rx)
   }
+  pub fn get_peer_counts() -> (Self, ReqAnsRecv<PeerCounts>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetPeerCounts { tx }, rx)
+  }
   pub fn get_constructor(name: Name) -> (Self, ReqAnsRecv<Option<CtrInfo>>) {
     let (tx, rx) = oneshot::channel();
     (NodeRequest::GetConstructor { name, tx }, rx)
@@ -416,8 +891,70 @@ impl<C: ProtoComm> NodeRequest<C> {
   }
   pub fn publish(
     code: Vec<hvm::Statement>,
-  ) -> (Self, ReqAnsRecv<Vec<Result<(), ()>>>) {
+  ) -> (Self, ReqAnsRecv<PublishResults>) {
+    NodeRequest::publish_with_priority(code, 0)
+  }
+  pub fn publish_with_priority(
+    code: Vec<hvm::Statement>,
+    priority: u64,
+  ) -> (Self, ReqAnsRecv<PublishResults>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::Publish { code, priority, tx }, rx)
+  }
+  pub fn dry_run(code: Vec<hvm::Statement>) -> (Self, ReqAnsRecv<DryRunInfo>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::DryRun { code, tx }, rx)
+  }
+  pub fn export_state(name: String) -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::ExportState { name, tx }, rx)
+  }
+  pub fn set_mining(enabled: bool) -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::SetMining { enabled, tx }, rx)
+  }
+  pub fn get_expected_block_time(
+    hashes_per_second: u64,
+  ) -> (Self, ReqAnsRecv<f64>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetExpectedBlockTime { hashes_per_second, tx }, rx)
+  }
+  pub fn subscribe_results(
+    filter: Option<Name>,
+  ) -> (Self, ReqAnsRecv<Result<ResultsRecv, String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::SubscribeResults { filter, tx }, rx)
+  }
+  pub fn set_tip(bhash: U256) -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::SetTip { bhash, tx }, rx)
+  }
+  pub fn pause() -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::Pause { tx }, rx)
+  }
+  pub fn resume() -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::Resume { tx }, rx)
+  }
+  pub fn get_work() -> (Self, ReqAnsRecv<(U256, u64, node::Body, U256)>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetWork { tx }, rx)
+  }
+  pub fn submit_block(
+    block: node::Block,
+  ) -> (Self, ReqAnsRecv<Result<(), String>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::SubmitBlock { block, tx }, rx)
+  }
+  pub fn get_difficulty_history(
+    count: usize,
+  ) -> (Self, ReqAnsRecv<Vec<(u64, U256)>>) {
+    let (tx, rx) = oneshot::channel();
+    (NodeRequest::GetDifficultyHistory { count, tx }, rx)
+  }
+  pub fn get_target() -> (Self, ReqAnsRecv<U256>) {
     let (tx, rx) = oneshot::channel();
-    (NodeRequest::Publish { code, tx }, rx)
+    (NodeRequest::GetTarget { tx }, rx)
   }
 }
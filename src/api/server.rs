@@ -7,7 +7,8 @@ use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde_json::json;
 use tokio::net::TcpListener;
-use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::StreamExt;
 use warp::body;
 use warp::hyper::StatusCode;
 use warp::query::query;
@@ -17,7 +18,10 @@ use warp::{reject, Rejection};
 
 use super::NodeRequest;
 use super::u256_to_hex;
-use crate::api::{HexStatement, ReqAnsRecv};
+use crate::api::{
+  BlockWork, DifficultyInfo, Hash, HexStatement, ReqAnsRecv, ResultEvent,
+  TargetInfo, TipInfo, WorkInfo,
+};
 use crate::bits::ProtoSerialize;
 use crate::config::{ApiConfig};
 use crate::common::Name;
@@ -43,6 +47,18 @@ pub fn hex_to_u256(hex: &str) -> Result<U256, String> {
   }
 }
 
+// Path segment holding a hash in hexadecimal, with or without the 0x prefix
+fn hash_param() -> impl Filter<Extract = (U256,), Error = Rejection> + Copy {
+  async fn parse_hash(hash_hex: String) -> Result<U256, Rejection> {
+    let hex = hash_hex.strip_prefix("0x").unwrap_or(&hash_hex);
+    hex_to_u256(hex).map_err(|message| {
+      let name = Some("hash".to_string());
+      reject::custom(InvalidParameter { name, message })
+    })
+  }
+  path::param::<String>().and_then(parse_hash)
+}
+
 fn ok_json<T>(data: T) -> warp::reply::Json
 where
   T: serde::Serialize,
@@ -64,18 +80,6 @@ where
   warp::body::json()
 }
 
-// HVM
-// ===
-
-fn u128_names_to_strings(names: &[u128]) -> Vec<String> {
-  names
-    .iter()
-    .copied()
-    .map(Name::new_unsafe)
-    .map(|n| n.to_string())
-    .collect::<Vec<_>>()
-}
-
 // Protocol Serialization
 // ======================
 
@@ -171,6 +175,7 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
     req: (NodeRequest<C>, ReqAnsRecv<T>),
   ) -> T {
     let (request, rx) = req;
+    // Blocks while the node's request queue is full (backpressure)
     node_query_tx.send(request).unwrap();
     let result = rx.await.expect("Node query channel closed");
     result
@@ -187,6 +192,24 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
     }
   });
 
+  let query_tx = node_query_sender.clone();
+  let get_health = path!("health").then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      let health = ask(query_tx, NodeRequest::get_health()).await;
+      ok_json(health)
+    }
+  });
+
+  let query_tx = node_query_sender.clone();
+  let get_chain_params = path!("chain" / "params").then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      let params = ask(query_tx, NodeRequest::get_chain_params()).await;
+      ok_json(params)
+    }
+  });
+
   // == Blocks ==
 
   let query_tx = node_query_sender.clone();
@@ -199,26 +222,19 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
     }
   });
 
+  let block_base = path!("blocks" / ..).and(hash_param());
+
   let get_block = || {
     let query_tx = node_query_sender.clone();
-    path!("blocks" / String / ..).and_then(move |hash_hex: String| {
+    block_base.and_then(move |hash: U256| {
       let query_tx = query_tx.clone();
       async move {
-        let hash_hex = hash_hex.strip_prefix("0x").unwrap_or(&hash_hex);
-        match hex_to_u256(hash_hex) {
-          Ok(hash) => {
-            let block = ask(query_tx, NodeRequest::get_block(hash)).await;
-            match block {
-              Some(block) => Ok(block),
-              None => {
-                let message = format!("Block '{}' not found", hash_hex);
-                Err(warp::reject::custom(NotFound::from(message)))
-              }
-            }
-          }
-          Err(err) => {
-            let msg = format!("Invalid block hash: {}", err);
-            Err(reject::custom(InvalidParameter::from(msg)))
+        let block = ask(query_tx, NodeRequest::get_block(hash)).await;
+        match block {
+          Some(block) => Ok(block),
+          None => {
+            let message = format!("Block '{}' not found", u256_to_hex(&hash));
+            Err(warp::reject::custom(NotFound::from(message)))
           }
         }
       }
@@ -240,26 +256,270 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
     }
   });
 
+  let query_tx = node_query_sender.clone();
+  let get_tip = path!("tip").then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      let (hash, height) = ask(query_tx, NodeRequest::get_tip()).await;
+      ok_json(TipInfo { hash: hash.into(), height })
+    }
+  });
+
+  let query_tx = node_query_sender.clone();
+  let get_block_from_tip = path!("tip" / u64).and_then(move |offset: u64| {
+    let query_tx = query_tx.clone();
+    async move {
+      let info = ask(query_tx, NodeRequest::get_block_from_tip(offset)).await;
+      match info {
+        None => {
+          let message = format!("No block {} blocks behind the tip", offset);
+          Err(Rejection::from(NotFound::from(message)))
+        }
+        Some(info) => Ok(ok_json(info)),
+      }
+    }
+  });
+
+  let query_tx = node_query_sender.clone();
+  let get_mana_usage =
+    path!("mana" / u64 / u64).then(move |start: u64, end: u64| {
+      let query_tx = query_tx.clone();
+      async move {
+        let range = (start, end);
+        let mana = ask(query_tx, NodeRequest::get_mana_usage(range)).await;
+        ok_json(mana)
+      }
+    });
+
   let get_block_go = get_block().and(path!()).map(ok_json);
 
+  let query_tx = node_query_sender.clone();
+  let get_block_children =
+    block_base.and(path!("children")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let children = ask(query_tx, NodeRequest::get_children(hash)).await;
+        match children {
+          Some(children) => {
+            let children: Vec<Hash> =
+              children.into_iter().map(Hash::from).collect();
+            Ok(ok_json(children))
+          }
+          None => {
+            let message = format!("Block '{}' not found", u256_to_hex(&hash));
+            Err(warp::reject::custom(NotFound::from(message)))
+          }
+        }
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let get_block_source =
+    block_base.and(path!("source")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let source = ask(query_tx, NodeRequest::get_block_source(hash));
+        match source.await {
+          Some(addr) => Ok(ok_json(addr)),
+          None => {
+            let hash = u256_to_hex(&hash);
+            let message =
+              format!("No recent sender known for block '{}'", hash);
+            Err(warp::reject::custom(NotFound::from(message)))
+          }
+        }
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let get_block_work =
+    block_base.and(path!("work")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let work = ask(query_tx, NodeRequest::get_block_work(hash)).await;
+        match work {
+          Some((target, work)) => {
+            Ok(ok_json(BlockWork { target: target.into(), work: work.into() }))
+          }
+          None => {
+            let message = format!("Block '{}' not found", u256_to_hex(&hash));
+            Err(warp::reject::custom(NotFound::from(message)))
+          }
+        }
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let request_block =
+    post().and(block_base).and(path!("request")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let res = ask(query_tx, NodeRequest::request_block(hash)).await;
+        match res {
+          Ok(()) => Ok(ok_json(())),
+          Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+        }
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let set_tip =
+    post().and(block_base).and(path!("set-tip")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let res = ask(query_tx, NodeRequest::set_tip(hash)).await;
+        match res {
+          Ok(()) => Ok(ok_json(())),
+          Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+        }
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let get_block_raw =
+    block_base.and(path!("raw")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let bytes = ask(query_tx, NodeRequest::get_block_raw(hash)).await;
+        match bytes {
+          Some(bytes) => Ok(ok_json(hex::encode(bytes))),
+          None => {
+            let message = format!("Block '{}' not found", u256_to_hex(&hash));
+            Err(warp::reject::custom(NotFound::from(message)))
+          }
+        }
+      }
+    });
+
+  // Adds a block given in the protocol format, hex-encoded
+  #[cfg(feature = "test-api")]
+  let add_block = {
+    let query_tx = node_query_sender.clone();
+    post().and(path!("blocks" / "add")).and(json_body()).and_then(
+      move |block_hex: String| {
+        let query_tx = query_tx.clone();
+        async move {
+          let block = hex::decode(&block_hex)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| crate::node::Block::from_bytes(&bytes));
+          match block {
+            Ok(block) => {
+              ask(query_tx, NodeRequest::add_block(block)).await;
+              Ok(ok_json(()))
+            }
+            Err(err) => {
+              let msg = format!("Invalid block: {}", err);
+              Err(reject::custom(InvalidParameter::from(msg)))
+            }
+          }
+        }
+      },
+    )
+  };
+
   let blocks_router = get_blocks //
     .or(get_block_go)
-    .or(get_block_hash);
+    .or(get_block_raw)
+    .or(get_block_children)
+    .or(get_block_source)
+    .or(get_block_work)
+    .or(request_block)
+    .or(set_tip)
+    .or(get_block_hash)
+    .or(get_tip)
+    .or(get_block_from_tip)
+    .or(get_mana_usage);
+  #[cfg(feature = "test-api")]
+  let blocks_router = add_block.or(blocks_router);
+
+  // == Transactions ==
+
+  let transaction_base = path!("transactions" / ..).and(hash_param());
 
-  // == Functions ==
+  let query_tx = node_query_sender.clone();
+  let get_transaction_status =
+    transaction_base.and(path!("status")).then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let req = NodeRequest::get_transaction_status(hash);
+        let status = ask(query_tx, req).await;
+        ok_json(status)
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let get_transaction =
+    transaction_base.and(path!()).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let req = NodeRequest::get_transaction(hash);
+        match ask(query_tx, req).await {
+          Some(transaction) => Ok(ok_json(hex::encode(&*transaction))),
+          None => {
+            let hash = u256_to_hex(&hash);
+            let message = format!("Transaction '{}' not found", hash);
+            Err(warp::reject::custom(NotFound::from(message)))
+          }
+        }
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let get_transaction_proof =
+    transaction_base.and(path!("proof")).and_then(move |hash: U256| {
+      let query_tx = query_tx.clone();
+      async move {
+        let req = NodeRequest::get_transaction_proof(hash);
+        match ask(query_tx, req).await {
+          Some(proof) => Ok(ok_json(proof)),
+          None => {
+            let hash = u256_to_hex(&hash);
+            let message =
+              format!("No inclusion proof for transaction '{}'", hash);
+            Err(warp::reject::custom(NotFound::from(message)))
+          }
+        }
+      }
+    });
 
   let query_tx = node_query_sender.clone();
-  let get_functions = path!("functions").then(move || {
+  let get_fee_estimate = path!("mempool" / "fees").then(move || {
     let query_tx = query_tx.clone();
     async move {
-      let functions = ask(query_tx, NodeRequest::get_functions()).await;
-      let functions: Vec<u128> =
-        functions.into_iter().map(|x| x as u128).collect();
-      let functions = u128_names_to_strings(&functions);
-      ok_json(functions)
+      let estimate = ask(query_tx, NodeRequest::get_fee_estimate()).await;
+      ok_json(estimate)
     }
   });
 
+  let transactions_router = get_transaction_status
+    .or(get_transaction_proof)
+    .or(get_transaction)
+    .or(get_fee_estimate);
+
+  // == Functions ==
+
+  #[derive(Deserialize)]
+  struct GetFunctionsQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    prefix: Option<String>,
+  }
+
+  let query_tx = node_query_sender.clone();
+  let get_functions = path!("functions")
+    .and(query::<GetFunctionsQuery>())
+    .then(move |query: GetFunctionsQuery| {
+      let query_tx = query_tx.clone();
+      async move {
+        let offset = query.offset.unwrap_or(0);
+        let req = NodeRequest::get_functions(offset, query.limit, query.prefix);
+        let functions = ask(query_tx, req).await;
+        let functions: Vec<String> =
+          functions.iter().map(|name| name.to_string()).collect();
+        ok_json(functions)
+      }
+    });
+
   let get_function_base = path!("functions" / String / ..).and_then(
     move |name_txt: String| async move {
       match Name::from_str(&name_txt) {
@@ -421,23 +681,47 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
     },
   );
 
+  #[derive(Deserialize)]
+  struct PublishQuery {
+    priority: Option<u64>,
+  }
+
   let query_tx = node_query_sender.clone();
-  let interact_publish = post().and(path!("publish")).and(json_body()).then(
-    move |code: Vec<HexStatement>| {
+  let interact_publish = post()
+    .and(path!("publish"))
+    .and(query::<PublishQuery>())
+    .and(json_body())
+    .then(move |query: PublishQuery, code: Vec<HexStatement>| {
       let query_tx = query_tx.clone();
       async move {
         let code: Vec<hvm::Statement> =
           code.into_iter().map(|x| x.into()).collect();
-        let results = ask(query_tx, NodeRequest::publish(code)).await;
-        let result: Vec<Result<(), ()>> = results.into_iter().collect();
+        let priority = query.priority.unwrap_or(0);
+        let req = NodeRequest::publish_with_priority(code, priority);
+        let results = ask(query_tx, req).await;
+        let result: Vec<Result<(), String>> = results.into_iter().collect();
         ok_json(result)
       }
-    },
-  );
+    });
+
+  let query_tx = node_query_sender.clone();
+  let interact_dry_run = post()
+    .and(path!("run" / "diff"))
+    .and(json_body())
+    .then(move |code: Vec<HexStatement>| {
+      let query_tx = query_tx.clone();
+      async move {
+        let code: Vec<hvm::Statement> =
+          code.into_iter().map(|x| x.into()).collect();
+        let info = ask(query_tx, NodeRequest::dry_run(code)).await;
+        ok_json(info)
+      }
+    });
 
   let interact_router = interact_code_run
     .or(interact_code_publish)
     .or(interact_run)
+    .or(interact_dry_run)
     .or(interact_publish);
 
   // == Reg ==
@@ -469,6 +753,44 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
 
   let reg_router = get_reg;
 
+  // == Results ==
+
+  #[derive(Deserialize)]
+  struct ResultsQuery {
+    name: Option<String>,
+  }
+
+  // Server-sent events with the results of the statements of every block
+  // computed from now on, optionally only those mentioning a name
+  let query_tx = node_query_sender.clone();
+  let stream_results = path!("results" / "stream")
+    .and(query::<ResultsQuery>())
+    .and_then(move |query: ResultsQuery| {
+      let query_tx = query_tx.clone();
+      async move {
+        let filter = match query.name {
+          None => None,
+          Some(name_txt) => match Name::from_str(&name_txt) {
+            Ok(name) => Some(name),
+            Err(err) => {
+              let msg = format!("Invalid name '{}': {}", name_txt, err);
+              return Err(reject::custom(InvalidParameter::from(msg)));
+            }
+          },
+        };
+        let request = NodeRequest::subscribe_results(filter);
+        let rx = match ask(query_tx, request).await {
+          Ok(rx) => rx,
+          Err(err) => return Err(reject::custom(InvalidParameter::from(err))),
+        };
+        let events = ReceiverStream::new(rx).map(|(bhash, result)| {
+          let event = ResultEvent { block: bhash.into(), result };
+          warp::sse::Event::default().json_data(event)
+        });
+        Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+      }
+    });
+
   // == Peers ==
 
   let get_peers_base = path!("peers" / ..);
@@ -491,16 +813,206 @@ async fn api_serve<'a, C: ProtoComm + 'static>(
     }
   });
 
-  let peers_router = get_peers.or(get_all_peers);
+  let query_tx = node_query_sender.clone();
+  let get_peer_counts = get_peers_base.and(path!("count")).then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      let counts = ask(query_tx, NodeRequest::get_peer_counts()).await;
+      ok_json(counts)
+    }
+  });
+
+  let peers_router = get_peers.or(get_all_peers).or(get_peer_counts);
+
+  // == Mining ==
+
+  let query_tx = node_query_sender.clone();
+  let set_mining = post().and(path!("mining")).and(json_body()).and_then(
+    move |enabled: bool| {
+      let query_tx = query_tx.clone();
+      async move {
+        let res = ask(query_tx, NodeRequest::set_mining(enabled)).await;
+        match res {
+          Ok(()) => Ok(ok_json(enabled)),
+          Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+        }
+      }
+    },
+  );
+
+  #[derive(Deserialize)]
+  struct ExpectedTimeQuery {
+    hash_rate: u64,
+  }
+
+  let query_tx = node_query_sender.clone();
+  let get_expected_block_time = path!("mining" / "expected-time")
+    .and(query::<ExpectedTimeQuery>())
+    .and_then(move |query: ExpectedTimeQuery| {
+      let query_tx = query_tx.clone();
+      async move {
+        if query.hash_rate == 0 {
+          let msg = "The hash rate must be positive.".to_string();
+          return Err(reject::custom(InvalidParameter::from(msg)));
+        }
+        let req = NodeRequest::get_expected_block_time(query.hash_rate);
+        let secs = ask(query_tx, req).await;
+        Ok(ok_json(secs))
+      }
+    });
+
+  #[derive(Deserialize)]
+  struct DifficultyQuery {
+    count: usize,
+  }
+
+  let query_tx = node_query_sender.clone();
+  let get_difficulty_history = path!("mining" / "difficulty")
+    .and(query::<DifficultyQuery>())
+    .then(move |query: DifficultyQuery| {
+      let query_tx = query_tx.clone();
+      async move {
+        let req = NodeRequest::get_difficulty_history(query.count);
+        let history: Vec<_> = ask(query_tx, req)
+          .await
+          .into_iter()
+          .map(|(height, difficulty)| DifficultyInfo {
+            height,
+            difficulty: difficulty.into(),
+          })
+          .collect();
+        ok_json(history)
+      }
+    });
+
+  let query_tx = node_query_sender.clone();
+  let get_target = path!("mining" / "target").then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      let target = ask(query_tx, NodeRequest::get_target()).await;
+      let difficulty = crate::node::target_to_difficulty(target).into();
+      ok_json(TargetInfo { target: target.into(), difficulty })
+    }
+  });
+
+  let query_tx = node_query_sender.clone();
+  let get_work = path!("mining" / "work").then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      let (prev, version, body, target) =
+        ask(query_tx, NodeRequest::get_work()).await;
+      let body = hex::encode(&body.data);
+      let prev = prev.into();
+      ok_json(WorkInfo { prev, version, body, target: target.into() })
+    }
+  });
+
+  // Takes a block given in the protocol format, hex-encoded
+  let query_tx = node_query_sender.clone();
+  let submit_block =
+    post().and(path!("mining" / "submit")).and(json_body()).and_then(
+      move |block_hex: String| {
+        let query_tx = query_tx.clone();
+        async move {
+          let block = hex::decode(&block_hex)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| crate::node::Block::from_bytes(&bytes));
+          let block = match block {
+            Ok(block) => block,
+            Err(err) => {
+              let msg = format!("Invalid block: {}", err);
+              return Err(reject::custom(InvalidParameter::from(msg)));
+            }
+          };
+          match ask(query_tx, NodeRequest::submit_block(block)).await {
+            Ok(()) => Ok(ok_json(())),
+            Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+          }
+        }
+      },
+    );
+
+  let mining_router = set_mining
+    .or(get_work)
+    .or(submit_block)
+    .or(get_target)
+    .or(get_expected_block_time)
+    .or(get_difficulty_history);
+
+  // == State ==
+
+  let query_tx = node_query_sender.clone();
+  let export_state =
+    post().and(path!("state" / "export")).and(json_body()).and_then(
+      move |name: String| {
+        let query_tx = query_tx.clone();
+        async move {
+          let res = ask(query_tx, NodeRequest::export_state(name)).await;
+          match res {
+            Ok(()) => Ok(ok_json(())),
+            Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+          }
+        }
+      },
+    );
+
+  let query_tx = node_query_sender.clone();
+  let get_states =
+    post().and(path!("state" / "functions")).and(json_body()).and_then(
+      move |names: Vec<Name>| {
+        let query_tx = query_tx.clone();
+        async move {
+          if names.len() > crate::node::MAX_STATES_PER_QUERY {
+            let err = format!(
+              "Too many functions; at most {} states are read at once.",
+              crate::node::MAX_STATES_PER_QUERY
+            );
+            return Err(reject::custom(InvalidParameter::from(err)));
+          }
+          let states = ask(query_tx, NodeRequest::get_states(names)).await;
+          Ok(ok_json(states))
+        }
+      },
+    );
+
+  let query_tx = node_query_sender.clone();
+  let pause = post().and(path!("pause")).and_then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      match ask(query_tx, NodeRequest::pause()).await {
+        Ok(()) => Ok(ok_json(())),
+        Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+      }
+    }
+  });
+
+  let query_tx = node_query_sender.clone();
+  let resume = post().and(path!("resume")).and_then(move || {
+    let query_tx = query_tx.clone();
+    async move {
+      match ask(query_tx, NodeRequest::resume()).await {
+        Ok(()) => Ok(ok_json(())),
+        Err(err) => Err(reject::custom(InvalidParameter::from(err))),
+      }
+    }
+  });
+
+  let state_router = export_state.or(get_states).or(pause).or(resume);
 
   // ==
 
   let app = root
     .or(get_stats)
+    .or(get_health)
+    .or(get_chain_params)
     .or(blocks_router)
+    .or(transactions_router)
     .or(functions_router)
     .or(interact_router)
+    .or(stream_results)
     .or(peers_router)
+    .or(mining_router)
+    .or(state_router)
     .or(constructor_router)
     .or(reg_router);
 
@@ -23,6 +23,16 @@ fn num_bits(n: u128) -> usize {
   (u128::BITS - n.leading_zeros()) as usize
 }
 
+// Reads the bit `offset` bits after `index`. Past the end of the input, moves
+// `index` to it, so a truncated input can be told apart from an invalid one.
+fn read_bit(bits: &BitVec, index: &mut usize, offset: usize) -> Option<bool> {
+  let bit = bits.get(*index + offset);
+  if bit.is_none() {
+    *index = bits.len();
+  }
+  bit
+}
+
 // A number with a known amount of bits
 
 pub fn serialize_fixlen(size: usize, value: u64, bits: &mut BitVec) {
@@ -44,6 +54,7 @@ pub fn deserialize_fixlen(
 ) -> Option<u64> {
   let mut result = 0;
   if *index + size > bits.len() {
+    *index = bits.len();
     return None;
   }
   for i in 0..size {
@@ -61,6 +72,7 @@ pub fn deserialize_fixlen_big(
 ) -> Option<U256> {
   let mut result = U256::from(0);
   if *index + size > bits.len() {
+    *index = bits.len();
     return None;
   }
   for i in 0..size {
@@ -86,8 +98,8 @@ pub fn serialize_varlen(value: u128, bits: &mut BitVec) {
 pub fn deserialize_varlen(bits: &BitVec, index: &mut usize) -> Option<u128> {
   let mut val: u128 = 0;
   let mut add: u128 = 1;
-  while bits.get(*index as usize)? {
-    val = val + if bits.get(*index as usize + 1)? { add } else { 0 };
+  while read_bit(bits, index, 0)? {
+    val = val + if read_bit(bits, index, 1)? { add } else { 0 };
     add = add << 1;
     *index = *index + 2;
   }
@@ -127,8 +139,8 @@ pub fn deserialize_bits(
   names: &mut Names,
 ) -> Option<BitVec> {
   let mut result = BitVec::new();
-  while bits.get(*index as usize)? {
-    result.push(bits.get(*index as usize + 1)?);
+  while read_bit(bits, index, 0)? {
+    result.push(read_bit(bits, index, 1)?);
     *index = *index + 2;
   }
   *index = *index + 1;
@@ -155,7 +167,7 @@ pub fn deserialize_list<T: ProtoSerialize>(
   names: &mut Names,
 ) -> Option<Vec<T>> {
   let mut result = Vec::new();
-  while bits.get(*index as usize)? {
+  while read_bit(bits, index, 0)? {
     *index = *index + 1;
     result.push(T::proto_deserialize(bits, index, names)?);
   }
@@ -195,7 +207,7 @@ pub fn deserialize_vector<T: ProtoSerialize>(
 // A block
 
 pub fn serialized_block_size(block: &Block) -> u128 {
-  return 32 + 16 + 16 + 2 + block.body.data.len() as u128;
+  return 1 + 32 + 16 + 16 + 2 + block.body.data.len() as u128;
 }
 
 // A hash
@@ -223,6 +235,35 @@ pub fn deserialize_bytes(
   Some(result)
 }
 
+// Errors
+
+/// Reason why a whole value could not be deserialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtoDeserializeError {
+  /// The input ended before the value was complete.
+  Truncated { len: usize },
+  /// The input has an invalid tag or field, around bit `index`.
+  Invalid { index: usize },
+  /// The value was read, but non-zero bits remain after bit `index`.
+  TrailingBits { index: usize, len: usize },
+}
+
+impl std::fmt::Display for ProtoDeserializeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ProtoDeserializeError::Truncated { len } => {
+        write!(f, "input truncated after {} bits", len)
+      }
+      ProtoDeserializeError::Invalid { index } => {
+        write!(f, "invalid data around bit {}", index)
+      }
+      ProtoDeserializeError::TrailingBits { index, len } => {
+        write!(f, "trailing garbage from bit {} to {}", index, len)
+      }
+    }
+  }
+}
+
 pub trait ProtoSerialize
 where
   Self: Sized,
@@ -239,8 +280,37 @@ where
     self.proto_serialize(&mut bits, &mut HashMap::new());
     return bits;
   }
-  fn proto_deserialized(bits: &BitVec) -> Option<Self> {
-    Self::proto_deserialize(bits, &mut 0, &mut HashMap::new())
+  /// Deserializes a value from the start of `bits`. Bits after it are
+  /// ignored, as they always were, so that this reads what older nodes read.
+  fn proto_deserialized(bits: &BitVec) -> Result<Self, ProtoDeserializeError> {
+    Self::proto_deserialized_at(bits).map(|(value, _)| value)
+  }
+  /// Like `proto_deserialized`, but the bits after the value must be zeros,
+  /// i.e. byte padding. Only for local policy, as on mempool admission:
+  /// values peers send are read with `proto_deserialized`.
+  fn proto_deserialized_exact(
+    bits: &BitVec,
+  ) -> Result<Self, ProtoDeserializeError> {
+    let (value, index) = Self::proto_deserialized_at(bits)?;
+    if bits.iter().skip(index).any(|bit| bit) {
+      let len = bits.len();
+      return Err(ProtoDeserializeError::TrailingBits { index, len });
+    }
+    Ok(value)
+  }
+  /// Deserializes a value from the start of `bits`, with the index it ends
+  /// at. The readers move the index to the end of the input when they run
+  /// out of it, which tells a truncated input apart from an invalid one.
+  fn proto_deserialized_at(
+    bits: &BitVec,
+  ) -> Result<(Self, usize), ProtoDeserializeError> {
+    let len = bits.len();
+    let mut index = 0;
+    match Self::proto_deserialize(bits, &mut index, &mut HashMap::new()) {
+      Some(value) => Ok((value, index)),
+      None if index >= len => Err(ProtoDeserializeError::Truncated { len }),
+      None => Err(ProtoDeserializeError::Invalid { index }),
+    }
   }
 }
 
@@ -269,14 +339,14 @@ impl ProtoSerialize for Name {
   ) -> Option<Self> {
     let mut nam: u128 = 0;
     let mut add: u128 = 1;
-    let compressed = bits.get(*index as usize)?;
+    let compressed = read_bit(bits, index, 0)?;
     *index += 1;
     if compressed {
       let id = deserialize_varlen(bits, index)?;
       let nm = *names.get(&id)?;
       Some(Name::from_u128_unchecked(nm))
     } else {
-      while bits.get(*index as usize)? {
+      while read_bit(bits, index, 0)? {
         *index += 1;
         let got = deserialize_fixlen(6, bits, index)?;
         nam = nam + add * got as u128;
@@ -586,13 +656,55 @@ impl ProtoSerialize for Hash {
   }
 }
 
+// Format version written before serialized blocks, in files and in messages
+// carrying blocks past version 0. Readers branch on it, so the format can
+// change while blocks in older formats still load. This is the newest version
+// they accept; the one a block is built in depends on its height, see
+// `node::block_version_at`.
+// - 0: the block hash covers the whole body.
+// - 1: the block hash covers a `BlockHeader`, committing to the body through
+//   its hash and the Merkle root of its transactions. Same layout as 0.
+pub const BLOCK_FORMAT_VERSION: u64 = 1;
+
+// Writes a block in the layout of version 0, without the version byte
+fn serialize_block_v0(block: &Block, bits: &mut BitVec) {
+  serialize_fixlen_big(256, &block.prev, bits);
+  serialize_fixlen_big(128, &U256::from(block.time), bits);
+  serialize_fixlen_big(128, &U256::from(block.meta), bits);
+  serialize_fixlen(16, block.body.data.len() as u64, bits);
+  serialize_bytes(block.body.data.len() as u128, &block.body.data, bits);
+}
+
+fn deserialize_block_v0(
+  version: u64,
+  bits: &BitVec,
+  index: &mut usize,
+) -> Option<Block> {
+  let prev = deserialize_fixlen_big(256, bits, index)?;
+  let time = deserialize_fixlen_big(128, bits, index)?.low_u128();
+  let meta = deserialize_fixlen_big(128, bits, index)?.low_u128();
+  let size = deserialize_fixlen(16, bits, index)?;
+  let data = deserialize_bytes(size, bits, index)?;
+  let body = Body { data };
+  return Some(Block { version, prev, time, meta, body });
+}
+
+/// Deserializes a block written before blocks had a format version: a
+/// version 0 block without the version byte. The whole input must be used,
+/// so versioned blocks aren't mistaken for one.
+pub fn deserialize_legacy_block(bits: &BitVec) -> Option<Block> {
+  let mut index = 0;
+  let block = deserialize_block_v0(0, bits, &mut index)?;
+  if index != bits.len() {
+    return None;
+  }
+  Some(block)
+}
+
 impl ProtoSerialize for Block {
   fn proto_serialize(&self, bits: &mut BitVec, _names: &mut Names) {
-    serialize_fixlen_big(256, &self.prev, bits);
-    serialize_fixlen_big(128, &U256::from(self.time), bits);
-    serialize_fixlen_big(128, &U256::from(self.meta), bits);
-    serialize_fixlen(16, self.body.data.len() as u64, bits);
-    serialize_bytes(self.body.data.len() as u128, &self.body.data, bits);
+    serialize_fixlen(8, self.version, bits);
+    serialize_block_v0(self, bits);
   }
 
   fn proto_deserialize(
@@ -600,13 +712,10 @@ impl ProtoSerialize for Block {
     index: &mut usize,
     _names: &mut Names,
   ) -> Option<Self> {
-    let prev = deserialize_fixlen_big(256, bits, index)?;
-    let time = deserialize_fixlen_big(128, bits, index)?.low_u128();
-    let meta = deserialize_fixlen_big(128, bits, index)?.low_u128();
-    let size = deserialize_fixlen(16, bits, index)?;
-    let data = deserialize_bytes(size, bits, index)?;
-    let body = Body { data };
-    return Some(Block::new(prev, time, meta, body));
+    match deserialize_fixlen(8, bits, index)? {
+      version @ (0 | 1) => deserialize_block_v0(version, bits, index),
+      _ => None,
+    }
   }
 }
 
@@ -629,7 +738,7 @@ impl ProtoSerialize for net::Address {
     index: &mut usize,
     _names: &mut Names,
   ) -> Option<net::Address> {
-    if bits[*index as usize] as u128 == 0 {
+    if !read_bit(bits, index, 0)? {
       *index = *index + 1;
       let val0 = deserialize_fixlen(8, bits, index)? as u8;
       let val1 = deserialize_fixlen(8, bits, index)? as u8;
@@ -666,9 +775,21 @@ impl<A: ProtoAddr> ProtoSerialize for Message<A> {
       // This is supposed to use < 1500 bytes when blocks = 1, to avoid UDP fragmentation
       Message::NoticeTheseBlocks { magic, gossip, blocks, peers } => {
         serialize_fixlen(32, *magic as u64, bits);
-        serialize_fixlen(4, 0, bits);
-        serialize_fixlen(1, *gossip as u64, bits);
-        serialize_list(&blocks, bits, names);
+        // Version 0 blocks go in the layout nodes without block versions
+        // read, so both keep talking until version 1 activates
+        if blocks.iter().all(|block| block.version == 0) {
+          serialize_fixlen(4, 0, bits);
+          serialize_fixlen(1, *gossip as u64, bits);
+          for block in blocks {
+            bits.push(true);
+            serialize_block_v0(block, bits);
+          }
+          bits.push(false);
+        } else {
+          serialize_fixlen(4, 5, bits);
+          serialize_fixlen(1, *gossip as u64, bits);
+          serialize_list(&blocks, bits, names);
+        }
         serialize_list(peers, bits, names);
       }
       Message::GiveMeThatBlock { magic, bhash } => {
@@ -687,6 +808,14 @@ impl<A: ProtoAddr> ProtoSerialize for Message<A> {
           serialize_bytes(tx_len as u128, tx, bits);
         }
       }
+      Message::Ping { magic } => {
+        serialize_fixlen(32, *magic as u64, bits);
+        serialize_fixlen(4, 3, bits);
+      }
+      Message::Pong { magic } => {
+        serialize_fixlen(32, *magic as u64, bits);
+        serialize_fixlen(4, 4, bits);
+      }
     }
   }
   fn proto_deserialize(
@@ -698,6 +827,17 @@ impl<A: ProtoAddr> ProtoSerialize for Message<A> {
     let code = deserialize_fixlen(4, bits, index)?;
     match code {
       0 => {
+        let gossip = deserialize_fixlen(1, bits, index)? != 0;
+        let mut blocks = Vec::new();
+        while read_bit(bits, index, 0)? {
+          *index = *index + 1;
+          blocks.push(deserialize_block_v0(0, bits, index)?);
+        }
+        *index = *index + 1;
+        let peers = deserialize_list(bits, index, names)?;
+        Some(Message::NoticeTheseBlocks { magic, gossip, blocks, peers })
+      }
+      5 => {
         let gossip = deserialize_fixlen(1, bits, index)? != 0;
         let blocks = deserialize_list(bits, index, names)?;
         let peers = deserialize_list(bits, index, names)?;
@@ -715,6 +855,8 @@ impl<A: ProtoAddr> ProtoSerialize for Message<A> {
           tx: Transaction::new(data),
         })
       }
+      3 => Some(Message::Ping { magic }),
+      4 => Some(Message::Pong { magic }),
       _ => None,
     }
   }
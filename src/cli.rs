@@ -12,11 +12,12 @@ use warp::Future;
 use kindelia::api::{client as api_client, Hash, HexStatement};
 use kindelia::bits::ProtoSerialize;
 use kindelia::common::Name;
-use kindelia::crypto;
+use kindelia::constants;
+use kindelia::crypto::{self, Keccakable};
 use kindelia::hvm::{self, view_statement, Statement};
 use kindelia::net;
 use kindelia::node;
-use kindelia::util::bytes_to_bitvec;
+use kindelia::util::{bytes_to_bitvec, U256};
 use kindelia::{config, events};
 
 // This client is meant to talk with a node implementing UDP protocol
@@ -208,17 +209,38 @@ pub enum CliCommand {
 pub enum NodeCommand {
   /// Clean the node's data.
   Clean,
+  /// Verify the integrity of the chain stored on disk, without starting.
+  Verify,
   /// Starts a Kindelia node.
   Start {
     /// Network id / magic number.
     #[clap(long)]
     network_id: Option<u32>,
+    /// Friendly name for this node, shown on its logs.
+    #[clap(long)]
+    name: Option<String>,
     /// Initial peer nodes.
     #[clap(long, short = 'p')]
     initial_peers: Option<Vec<String>>,
+    /// Trusted peer nodes, never dropped for inactivity.
+    #[clap(long)]
+    pinned_peers: Option<Vec<String>>,
     /// Mine blocks.
     #[clap(long, short = 'm')]
     mine: bool,
+    /// Run as a read-only follower: sync blocks, but never mine or accept
+    /// transactions.
+    #[clap(long)]
+    follower: bool,
+    /// Keep only the blocks of about the last `node.prune_depth` heights,
+    /// dropping older history. Needs `--import-state` and a trusted
+    /// checkpoint.
+    #[clap(long)]
+    pruned: bool,
+    /// Start from a runtime state exported by a trusted node, instead of
+    /// replaying the whole chain.
+    #[clap(long)]
+    import_state: Option<PathBuf>,
     /// Log events as JSON
     #[clap(long, short)]
     json: bool,
@@ -319,6 +341,8 @@ pub enum GetStatsKind {
   CtrCount,
   /// Get the number of namespaces.
   RegCount,
+  /// Get the estimated network hash rate, in hashes per second.
+  HashRate,
 }
 
 // Config Resolution
@@ -366,12 +390,33 @@ where
       return T::arg_from(env_value);
     }
     if let (Some(prop_path), Some(config_values)) = (self.prop, config_values) {
-      // If config file and argument prop path are set, read from config file
-      return Self::resolve_from_config_aux(config_values, prop_path);
+      // If config file sets the argument prop path, read from config file
+      if Self::get_prop(config_values, prop_path).is_some() {
+        return Self::resolve_from_config_aux(config_values, prop_path);
+      }
     }
     (self.default_value)()
   }
 
+  /// Like `resolve`, without a CLI argument, for settings that may be left
+  /// unset. A default value that is an error means there is no default, and
+  /// gives `None`.
+  pub fn resolve_opt(
+    self,
+    config_values: Option<&toml::Value>,
+  ) -> Result<Option<T>, String>
+  where
+    T: ArgumentFrom<String> + ArgumentFrom<toml::Value>,
+  {
+    if let Some(Ok(env_value)) = self.env.map(std::env::var) {
+      return T::arg_from(env_value).map(Some);
+    }
+    if let Some(value) = self.resolve_from_file_opt(config_values)? {
+      return Ok(Some(value));
+    }
+    Ok((self.default_value)().ok())
+  }
+
   // TODO: refactor
 
   fn resolve_from_file_only(
@@ -382,18 +427,23 @@ where
     T: ArgumentFrom<toml::Value>,
   {
     if let Some(prop_path) = self.prop {
-      if let Some(config_values) = config_values {
-        Self::resolve_from_config_aux(config_values, prop_path)
-      } else {
-        (self.default_value)()
+      match config_values {
+        Some(config_values)
+          if Self::get_prop(config_values, prop_path).is_some() =>
+        {
+          Self::resolve_from_config_aux(config_values, prop_path)
+        }
+        _ => (self.default_value)(),
       }
     } else {
       panic!("Cannot resolve from config file config without 'prop' field set")
     }
   }
 
+  /// Reads a setting from the config file only, giving `None` when it isn't
+  /// set there. The default value isn't used.
   fn resolve_from_file_opt(
-    self,
+    &self,
     config_values: Option<&toml::Value>,
   ) -> Result<Option<T>, String>
   where
@@ -457,6 +507,24 @@ macro_rules! resolve_cfg {
       .unwrap()
       .resolve($cli, $cfg)?
   };
+  (env = $env:expr, prop = $prop:expr, default = $default:expr, cfg = $cfg:expr $(,)*) => {
+    ConfigSettingsBuilder::default()
+      .env($env)
+      .prop($prop)
+      .default_value(|| Ok($default))
+      .build()
+      .unwrap()
+      .resolve_opt($cfg)?
+  };
+  (env = $env:expr, prop = $prop:expr, cfg = $cfg:expr $(,)*) => {
+    ConfigSettingsBuilder::default()
+      .env($env)
+      .prop($prop)
+      .default_value(|| Err(String::new()))
+      .build()
+      .unwrap()
+      .resolve_opt($cfg)?
+  };
   (env = $env:expr, prop = $prop:expr, no_default = $default:expr, val = $cli:expr, cfg = $cfg:expr $(,)*) => {
     ConfigSettingsBuilder::default()
       .env($env)
@@ -590,6 +658,23 @@ pub fn run_cli() -> Result<(), String> {
       .resolve(data_dir, config)?;
 
       match command {
+        NodeCommand::Verify => {
+          let genesis_stmts = hvm::parse_code(constants::GENESIS_CODE)?;
+          let genesis_block = node::build_genesis_block(&genesis_stmts);
+          let genesis_hash = U256::from(genesis_block.hashed().get_hash());
+          let blocks_dir = data_path.join("blocks");
+          let initial_difficulty = resolve_initial_difficulty(config)?;
+          let block_v1_height = resolve_block_v1_height(config)?
+            .unwrap_or(node::BLOCK_V1_HEIGHT);
+          let (height, work) = node::verify_stored_chain(
+            &blocks_dir,
+            genesis_hash,
+            initial_difficulty,
+            block_v1_height,
+          )?;
+          println!("Chain is valid: {} blocks, accumulated work {}.", height, work);
+          Ok(())
+        }
         NodeCommand::Clean => {
           // warning
           println!(
@@ -613,7 +698,17 @@ pub fn run_cli() -> Result<(), String> {
           }
           Ok(())
         }
-        NodeCommand::Start { initial_peers, network_id, mine, json } => {
+        NodeCommand::Start {
+          initial_peers,
+          pinned_peers,
+          network_id,
+          name,
+          mine,
+          follower,
+          pruned,
+          import_state,
+          json,
+        } => {
           // TODO: refactor config resolution out of command handling (how?)
 
           // Get arguments from cli, env or config
@@ -626,6 +721,16 @@ pub fn run_cli() -> Result<(), String> {
             cfg = config,
           );
 
+          let name = match name {
+            Some(name) => Some(name),
+            None => ConfigSettingsBuilder::default()
+              .prop("node.name")
+              .default_value(|| Err("No name".to_string()))
+              .build()
+              .unwrap()
+              .resolve_from_file_opt(config)?,
+          };
+
           let initial_peers = resolve_cfg!(
             env = "KINDELIA_NODE_INITIAL_PEERS",
             prop = "node.network.initial_peers",
@@ -634,6 +739,14 @@ pub fn run_cli() -> Result<(), String> {
             cfg = config,
           );
 
+          let pinned_peers = resolve_cfg!(
+            env = "KINDELIA_NODE_PINNED_PEERS",
+            prop = "node.network.pinned_peers",
+            default = vec![],
+            val = pinned_peers,
+            cfg = config,
+          );
+
           let mine = resolve_cfg!(
             env = "KINDELIA_MINE",
             prop = "node.mining.enable",
@@ -642,14 +755,177 @@ pub fn run_cli() -> Result<(), String> {
             cfg = config,
           );
 
-          let slow_mining = ConfigSettingsBuilder::default()
-            .env("KINDELIA_SLOW_MINING")
-            .prop("node.debug.slow_mining")
-            .default_value(|| Ok(0))
+          let follower = resolve_cfg!(
+            env = "KINDELIA_FOLLOWER",
+            prop = "node.follower",
+            default = false,
+            val = flag_to_option(follower),
+            cfg = config,
+          );
+          let mode = if follower {
+            config::NodeMode::Follower
+          } else {
+            config::NodeMode::Full
+          };
+
+          let pruned = resolve_cfg!(
+            env = "KINDELIA_PRUNED",
+            prop = "node.pruned",
+            default = false,
+            val = flag_to_option(pruned),
+            cfg = config,
+          );
+          let storage = if pruned {
+            config::StorageMode::Pruned
+          } else {
+            config::StorageMode::Archive
+          };
+
+          let prune_depth = resolve_cfg!(
+            env = "KINDELIA_PRUNE_DEPTH",
+            prop = "node.prune_depth",
+            cfg = config,
+          );
+
+          let slow_mining = resolve_cfg!(
+            env = "KINDELIA_SLOW_MINING",
+            prop = "node.debug.slow_mining",
+            cfg = config,
+          );
+
+          let target_body_bytes = resolve_cfg!(
+            env = "KINDELIA_TARGET_BODY_BYTES",
+            prop = "node.mining.target_body_bytes",
+            cfg = config,
+          )
+          .map(|bytes: u64| bytes as usize);
+
+          let max_txs_per_block = resolve_cfg!(
+            env = "KINDELIA_MAX_TXS_PER_BLOCK",
+            prop = "node.mining.max_txs_per_block",
+            cfg = config,
+          )
+          .map(|count: u64| count as usize);
+
+          let max_tx_bytes = resolve_cfg!(
+            env = "KINDELIA_MAX_TX_BYTES",
+            prop = "node.mining.max_tx_bytes",
+            cfg = config,
+          )
+          .map(|bytes: u64| bytes as usize);
+
+          let mine_template = resolve_cfg!(
+            env = "KINDELIA_MINE_TEMPLATE",
+            prop = "node.mining.template_file",
+            cfg = config,
+          );
+
+          let initial_difficulty = Some(resolve_initial_difficulty(config)?);
+
+          let mining_intensity = resolve_cfg!(
+            env = "KINDELIA_MINING_INTENSITY",
+            prop = "node.mining.intensity",
+            default = 100,
+            cfg = config,
+          )
+          .map(|percent: u64| percent.min(100) as u8);
+
+          let send_block_ancestors = resolve_cfg!(
+            env = "KINDELIA_SEND_BLOCK_ANCESTORS",
+            prop = "node.network.send_block_ancestors",
+            cfg = config,
+          )
+          .map(|count: u64| count as usize);
+
+          let recv_buffer_size = resolve_cfg!(
+            env = "KINDELIA_RECV_BUFFER_SIZE",
+            prop = "node.network.recv_buffer_size",
+            cfg = config,
+          )
+          .map(|size: u64| size as usize);
+
+          let peer_timeout = resolve_cfg!(
+            env = "KINDELIA_PEER_TIMEOUT",
+            prop = "node.network.peer_timeout",
+            cfg = config,
+          )
+          .map(|ms: u64| ms as u128);
+
+          let pending_block_timeout = resolve_cfg!(
+            env = "KINDELIA_PENDING_BLOCK_TIMEOUT",
+            prop = "node.network.pending_block_timeout",
+            cfg = config,
+          )
+          .map(|ms: u64| ms as u128);
+
+          let min_block_difficulty = resolve_cfg!(
+            env = "KINDELIA_MIN_BLOCK_DIFFICULTY",
+            prop = "node.network.min_block_difficulty",
+            cfg = config,
+          )
+          .map(|difficulty: u64| difficulty as u128);
+
+          let statement_mana = resolve_cfg!(
+            env = "KINDELIA_STATEMENT_MANA",
+            prop = "node.network.statement_mana",
+            cfg = config,
+          );
+
+          let max_load_blocks = resolve_cfg!(
+            env = "KINDELIA_MAX_LOAD_BLOCKS",
+            prop = "node.data.max_load_blocks",
+            cfg = config,
+          );
+
+          let seed_localhost = resolve_cfg!(
+            env = "KINDELIA_SEED_LOCALHOST",
+            prop = "node.debug.seed_localhost",
+            default = false,
+            val = None,
+            cfg = config,
+          );
+
+          let udp_batching = resolve_cfg!(
+            env = "KINDELIA_UDP_BATCHING",
+            prop = "node.network.udp_batching",
+            default = false,
+            val = None,
+            cfg = config,
+          );
+
+          let tip_tiebreak = ConfigSettingsBuilder::default()
+            .prop("node.network.tip_tiebreak")
+            .default_value(|| Ok(config::TipTiebreak::default()))
+            .build()
+            .unwrap()
+            .resolve_from_file_only(config)?;
+
+          let trusted_checkpoint = ConfigSettingsBuilder::default()
+            .prop("node.network.trusted_checkpoint")
+            .default_value(|| Err("No trusted checkpoint".to_string()))
             .build()
             .unwrap()
             .resolve_from_file_opt(config)?;
 
+          let mut network_config = config::NetworkConfig::default();
+          if let Some(count) = send_block_ancestors {
+            network_config.send_block_ancestors = count;
+          }
+          if let Some(size) = recv_buffer_size {
+            network_config.recv_buffer_size = size;
+          }
+          if let Some(timeout) = peer_timeout {
+            network_config.peer_timeout = timeout;
+          }
+          if let Some(timeout) = pending_block_timeout {
+            network_config.pending_block_timeout = timeout;
+          }
+          network_config.tip_tiebreak = tip_tiebreak;
+          network_config.statement_mana = statement_mana;
+          network_config.min_block_difficulty = min_block_difficulty;
+          network_config.block_v1_height = resolve_block_v1_height(config)?;
+          network_config.trusted_checkpoint = trusted_checkpoint;
+
           let api_config = ConfigSettingsBuilder::default()
             .prop("node.api")
             .default_value(|| Ok(config::ApiConfig::default()))
@@ -659,24 +935,62 @@ pub fn run_cli() -> Result<(), String> {
 
           // Start
           let node_comm = init_socket().expect("Could not open a UDP socket");
-          let initial_peers = initial_peers
-            .iter()
-            .map(|x| net::parse_address(x))
-            .collect::<Vec<_>>();
+          let parse_peers = |peers: Vec<String>| {
+            peers
+              .iter()
+              .map(|x| {
+                net::parse_address(x)
+                  .map_err(|e| format!("Invalid peer address '{}': {}", x, e))
+              })
+              .collect::<Result<Vec<_>, _>>()
+          };
+          let mut initial_peers = parse_peers(initial_peers)?;
+          let pinned_peers = parse_peers(pinned_peers)?;
+          // Only meant for running several nodes on one machine, for testing
+          if seed_localhost {
+            let own_addr = node_comm.local_addr().map_err(|e| e.to_string())?;
+            initial_peers.extend(localhost_peers(own_addr.port()));
+          }
 
           let node_cfg = config::NodeConfig {
             network_id,
+            name,
             data_path,
-            mining: config::MineConfig { enabled: mine, slow_mining },
+            mode,
+            import_state,
+            max_load_blocks,
+            storage,
+            prune_depth,
+            network: network_config,
+            mining: config::MineConfig {
+              enabled: mine,
+              slow_mining,
+              target_body_bytes,
+              max_txs_per_block,
+              max_tx_bytes,
+              template_file: mine_template,
+              initial_difficulty,
+              intensity: mining_intensity,
+            },
             ui: Some(config::UiConfig {
               json,
-              tags: vec![events::NodeEventDiscriminant::Heartbeat],
+              tags: vec![
+                events::NodeEventDiscriminant::Heartbeat,
+                events::NodeEventDiscriminant::Status,
+              ],
             }),
             api: Some(api_config),
             ws: None, // TODO: load from config file
           };
 
-          node::start(node_cfg, node_comm, initial_peers);
+          // Batching sends packets together, with fewer system calls
+          let started = if udp_batching {
+            let node_comm = net::BatchedUdp::new(node_comm);
+            node::start(node_cfg, node_comm, initial_peers, pinned_peers)
+          } else {
+            node::start(node_cfg, node_comm, initial_peers, pinned_peers)
+          };
+          started.map_err(|err| format!("Could not start the node: {}", err))?;
 
           Ok(())
         }
@@ -808,6 +1122,7 @@ pub async fn get_info(
             GetStatsKind::FunCount => stats.fun_count,
             GetStatsKind::CtrCount => stats.ctr_count,
             GetStatsKind::RegCount => stats.reg_count,
+            GetStatsKind::HashRate => stats.hash_rate,
           };
           println!("{}", val);
         }
@@ -874,9 +1189,7 @@ pub fn publish_code(
     print!("Transaction #{}: ", i);
     match result {
       Ok(_) => println!("PUBLISHED (tx added to mempool)"),
-      Err(_) => {
-        println!("NOT PUBLISHED (tx is probably already on mempool)")
-      }
+      Err(err) => println!("NOT PUBLISHED ({})", err),
     }
   }
   Ok(())
@@ -886,10 +1199,12 @@ pub fn test_code(code: &str, sudo: bool) {
   hvm::test_statements_from_code(code, sudo);
 }
 
+// Ports tried, in order, when binding the node's socket
+const TRY_PORTS: [u16; 4] =
+  [net::UDP_PORT, net::UDP_PORT + 1, net::UDP_PORT + 2, net::UDP_PORT + 3];
+
 fn init_socket() -> Option<UdpSocket> {
-  let try_ports =
-    [net::UDP_PORT, net::UDP_PORT + 1, net::UDP_PORT + 2, net::UDP_PORT + 3];
-  for port in try_ports {
+  for port in TRY_PORTS {
     if let Ok(socket) = UdpSocket::bind(&format!("0.0.0.0:{}", port)) {
       socket.set_nonblocking(true).ok();
       return Some(socket);
@@ -898,6 +1213,21 @@ fn init_socket() -> Option<UdpSocket> {
   None
 }
 
+/// Peers at 127.0.0.1 on the ports other local nodes may have bound.
+fn localhost_peers(own_port: u16) -> Vec<net::Address> {
+  TRY_PORTS
+    .iter()
+    .filter(|&&port| port != own_port)
+    .map(|&port| net::Address::IPv4 {
+      val0: 127,
+      val1: 0,
+      val2: 0,
+      val3: 1,
+      port,
+    })
+    .collect()
+}
+
 // Utils
 // =====
 
@@ -947,6 +1277,35 @@ fn handle_config_file(path: &Path) -> Result<toml::Value, String> {
   Ok(config)
 }
 
+/// Resolves the network's initial difficulty from the environment or the
+/// config file, defaulting to `node::INITIAL_DIFFICULTY`. It must be positive,
+/// as targets are derived by dividing by it.
+fn resolve_initial_difficulty(
+  config: Option<&toml::Value>,
+) -> Result<u128, String> {
+  let difficulty: Option<u64> = resolve_cfg!(
+    env = "KINDELIA_INITIAL_DIFFICULTY",
+    prop = "node.mining.initial_difficulty",
+    cfg = config,
+  );
+  match difficulty {
+    None => Ok(node::INITIAL_DIFFICULTY),
+    Some(0) => Err("The initial difficulty must be greater than 0.".to_string()),
+    Some(difficulty) => Ok(difficulty as u128),
+  }
+}
+
+fn resolve_block_v1_height(
+  config: Option<&toml::Value>,
+) -> Result<Option<u128>, String> {
+  let height: Option<u64> = resolve_cfg!(
+    env = "KINDELIA_BLOCK_V1_HEIGHT",
+    prop = "node.network.block_v1_height",
+    cfg = config,
+  );
+  Ok(height.map(|height| height as u128))
+}
+
 fn init_config_file(path: &Path) -> Result<(), String> {
   let dir_path = path.parent().ok_or_else(|| {
     format!("Failed to resolve parent directory for '{}'", path.display())
@@ -987,7 +1346,7 @@ fn statement_from_hex(hex: &str) -> Result<Statement, String> {
   let bytes = hex::decode(hex)
     .map_err(|err| format!("Invalid hexadecimal '{}': {}", hex, err))?;
   hvm::Statement::proto_deserialized(&bytes_to_bitvec(&bytes))
-    .ok_or(format!("Failed to deserialize '{}'", hex))
+    .map_err(|err| format!("Failed to deserialize '{}': {}", hex, err))
 }
 
 fn arg_from_file_or_stdin<T: ArgumentFrom<String>>(
@@ -1189,6 +1548,37 @@ impl ArgumentFrom<toml::Value> for bool {
   }
 }
 
+impl ArgumentFrom<toml::Value> for config::TipTiebreak {
+  fn arg_from(t: toml::Value) -> Result<Self, String> {
+    t.try_into().map_err(|_| "Invalid tip tiebreak rule".to_string())
+  }
+}
+
+impl ArgumentFrom<toml::Value> for config::TrustedCheckpoint {
+  fn arg_from(t: toml::Value) -> Result<Self, String> {
+    let field = |name: &str| {
+      t.get(name).ok_or(format!("Missing checkpoint field `{}`", name))
+    };
+    let hash = field("hash")?.as_str().ok_or("Checkpoint hash isn't a string")?;
+    let hash = Hash::try_from(hash)?;
+    let height = field("height")?
+      .as_integer()
+      .and_then(|height| u64::try_from(height).ok())
+      .ok_or("Invalid checkpoint height")?;
+    // Accumulated work can exceed TOML integers, so it's a hex string
+    let work = field("work")?.as_str().ok_or("Checkpoint work isn't a string")?;
+    let work = Hash::try_from(work)?;
+    let target = match t.get("target") {
+      Some(target) => {
+        let target = target.as_str().ok_or("Checkpoint target isn't a string")?;
+        Some(Hash::try_from(target)?)
+      }
+      None => None,
+    };
+    Ok(config::TrustedCheckpoint { hash, height, work, target })
+  }
+}
+
 impl ArgumentFrom<toml::Value> for config::ApiConfig {
   fn arg_from(t: toml::Value) -> Result<Self, String> {
     t.try_into().map_err(|_| "Could not convert value into array".to_string())
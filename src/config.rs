@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
+use crate::api::Hash;
 use crate::events::NodeEventDiscriminant;
 
 // Node config
@@ -14,6 +15,35 @@ pub struct NodeConfig {
   pub data_path: PathBuf, // TODO: abstract node file handling
   #[builder(default)]
   pub network_id: u32,
+  /// Friendly name shown on this node's logs, to tell nodes of a fleet apart.
+  /// Purely informational.
+  #[builder(default)]
+  pub name: Option<String>,
+  #[builder(default)]
+  pub mode: NodeMode,
+  /// Directory with a runtime state exported by another node, used to start
+  /// without replaying the whole chain. The state is trusted as is, and
+  /// only the block it was taken at is accepted at its height.
+  #[builder(default)]
+  pub import_state: Option<PathBuf>,
+  /// Loads only the block files of about the last this many heights,
+  /// starting the chain on the oldest of them, for quick starts. The node
+  /// then lacks the history before it: older blocks can't be queried nor
+  /// replayed. Needs `import_state`, as only blocks past the imported state
+  /// can be skipped, and a `TrustedCheckpoint` with its target, as targets
+  /// can't be recomputed without the blocks before them; the chain then
+  /// starts on the checkpoint's retarget period. See `node::suffix_start`.
+  #[builder(default)]
+  pub max_load_blocks: Option<u64>,
+  /// Whether old blocks are kept; see `StorageMode`.
+  #[builder(default)]
+  pub storage: StorageMode,
+  /// How many blocks behind the tip a pruned node keeps. Defaults to
+  /// `node::PRUNE_DEPTH`. Ignored by archive nodes.
+  #[builder(default)]
+  pub prune_depth: Option<u64>,
+  #[builder(default)]
+  pub network: NetworkConfig,
   #[builder(default)]
   pub mining: MineConfig,
   #[builder(default)]
@@ -24,6 +54,146 @@ pub struct NodeConfig {
   pub ws: Option<WsConfig>,
 }
 
+/// The role a node plays on the network.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeMode {
+  /// Syncs, mines (if enabled) and accepts transactions.
+  #[default]
+  Full,
+  /// Syncs and relays blocks, serving read-only queries. Never mines, never
+  /// accepts transactions, and refuses requests that change its state, like
+  /// setting the tip or pausing.
+  Follower,
+}
+
+/// How much of the chain's history a node keeps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageMode {
+  /// Keeps every block, with its results, and every block file.
+  #[default]
+  Archive,
+  /// Keeps only the blocks of about the last `prune_depth` heights, so
+  /// memory use stops growing with the chain. Older blocks, and their
+  /// results, can't be queried nor replayed, and their files are removed;
+  /// see `Node::prune`. Needs a `TrustedCheckpoint` and the state imported
+  /// at it, as the runtime can't be rebuilt without the dropped blocks.
+  Pruned,
+}
+
+// Network config
+// ==============
+
+#[derive(Debug, Clone, Builder, Serialize, Deserialize)]
+#[builder(setter(strip_option))]
+pub struct NetworkConfig {
+  /// How many blocks are usually sent back when a peer asks for a block.
+  /// Peers far behind get more, and fewer are sent while the socket is
+  /// congested; see `Node::send_block_count`.
+  pub send_block_ancestors: usize,
+  /// How many peers are kept at most. The stalest are forgotten first.
+  pub max_peers: usize,
+  /// How many of the kept peers may be ones that contacted us first.
+  pub max_inbound_peers: usize,
+  /// How many milliseconds without notice until a peer is forgotten. Peers
+  /// quiet for half of it are pinged.
+  pub peer_timeout: u128,
+  /// How to choose between two tips with the same accumulated work.
+  pub tip_tiebreak: TipTiebreak,
+  /// Most mana a single statement may spend while computing blocks. Changes
+  /// statement results, so the whole network should use the same value; the
+  /// public network uses none, and nodes refuse to join it otherwise. Must
+  /// be positive.
+  pub statement_mana: Option<u64>,
+  /// Seeds the choice of gossip targets, so that it is reproducible. Meant
+  /// for tests; when unset, targets are picked with the thread RNG.
+  pub peer_seed: Option<u64>,
+  /// Size of the buffer incoming packets are read into, in bytes. Packets
+  /// larger than it are dropped. Never lower than `node::MAX_UDP_SIZE_SLOW`.
+  pub recv_buffer_size: usize,
+  /// How many block requests from a single peer are answered per second.
+  pub block_serve_peer_rate: u32,
+  /// How many bytes of blocks are sent per second, at most, answering block
+  /// requests from all peers.
+  pub block_serve_bytes_rate: usize,
+  /// Block trusted to be on the main chain. See `TrustedCheckpoint`.
+  pub trusted_checkpoint: Option<TrustedCheckpoint>,
+  /// How many milliseconds a downloaded block may wait for its missing
+  /// ancestors before it is dropped.
+  pub pending_block_timeout: u128,
+  /// Lowest difficulty a received block's hash must meet. Blocks below it
+  /// are dropped before any other check, as no chain of this network could
+  /// contain them. Unset, nothing is filtered.
+  pub min_block_difficulty: Option<u128>,
+  /// Height from which blocks must be in format version 1, overriding
+  /// `node::BLOCK_V1_HEIGHT`. Changes which blocks are valid, so the whole
+  /// network should use the same value; it can't be changed on the public
+  /// network.
+  pub block_v1_height: Option<u128>,
+}
+
+/// A block trusted to be on the main chain. Blocks up to its height are
+/// accepted without computing their statements, and any block at its height
+/// other than it is refused.
+///
+/// The node needs the state taken at the checkpoint, imported with
+/// `NodeConfig::import_state`, as it doesn't compute the statements before
+/// it; it refuses to start otherwise.
+///
+/// This weakens the security model: the node no longer checks that the
+/// history before the checkpoint is valid. A wrong checkpoint leaves the
+/// node on a chain of its own. Only use checkpoints from a source trusted as
+/// much as the node itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedCheckpoint {
+  pub hash: Hash,
+  pub height: u64,
+  /// Expected accumulated work of the chain up to the checkpoint, as a
+  /// 0x-prefixed, 64 digit hex string.
+  pub work: Hash,
+  /// Target the checkpoint's children must hit, in the same format. Lets
+  /// the node start its chain on the checkpoint's retarget period, without
+  /// loading older blocks; see `NodeConfig::max_load_blocks`.
+  pub target: Option<Hash>,
+}
+
+/// Rule that picks the tip when two chains have exactly the same work.
+///
+/// With `FirstSeen`, each node keeps the tip it received first, so nodes that
+/// saw competing blocks in different orders stay split until one of the
+/// chains grows. With `LowestHash`, every node picks the same tip regardless
+/// of arrival order, converging sooner. Since nodes with different rules can
+/// disagree on equal-work tips, the whole network should use the same one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TipTiebreak {
+  /// Keeps the current tip.
+  #[default]
+  FirstSeen,
+  /// Picks the tip with the lowest block hash.
+  LowestHash,
+}
+
+impl Default for NetworkConfig {
+  fn default() -> Self {
+    NetworkConfig {
+      send_block_ancestors: 64,
+      max_peers: crate::node::MAX_PEERS,
+      max_inbound_peers: crate::node::MAX_INBOUND_PEERS,
+      peer_timeout: crate::node::PEER_TIMEOUT,
+      tip_tiebreak: TipTiebreak::default(),
+      statement_mana: None,
+      peer_seed: None,
+      recv_buffer_size: crate::net::RECV_BUFFER_SIZE,
+      block_serve_peer_rate: crate::node::BLOCK_SERVE_PEER_RATE,
+      block_serve_bytes_rate: crate::node::BLOCK_SERVE_BYTES_RATE,
+      trusted_checkpoint: None,
+      pending_block_timeout: crate::node::PENDING_BLOCK_TIMEOUT,
+      min_block_difficulty: None,
+      block_v1_height: None,
+    }
+  }
+}
+
 // Mineration config
 // =================
 
@@ -32,6 +202,34 @@ pub struct NodeConfig {
 pub struct MineConfig {
   pub enabled: bool,
   pub slow_mining: Option<u64>,
+  /// Soft cap on the size of mined block bodies, in bytes. Can't exceed
+  /// `MAX_BODY_SIZE`. Leaving headroom yields faster propagating blocks.
+  #[builder(default)]
+  pub target_body_bytes: Option<usize>,
+  /// Cap on the number of transactions in mined blocks, for faster
+  /// validation. Can't exceed `MAX_BODY_TXS`.
+  #[builder(default)]
+  pub max_txs_per_block: Option<usize>,
+  /// Largest transaction, in bytes, admitted to the mempool. Defaults to the
+  /// protocol limit, `MAX_BODY_SIZE`. Doesn't affect blocks from peers.
+  #[builder(default)]
+  pub max_tx_bytes: Option<usize>,
+  /// File with Kindelia statements to include on every mined block, ahead of
+  /// the mempool transactions.
+  #[builder(default)]
+  pub template_file: Option<PathBuf>,
+  /// Difficulty of the first blocks, in expected hashes per block. Defaults
+  /// to `node::INITIAL_DIFFICULTY`; lower it for testnets on slow machines.
+  /// It is a consensus rule, so nodes with different values form different
+  /// networks and ignore each other's messages. Can't be changed on the
+  /// public network.
+  #[builder(default)]
+  pub initial_difficulty: Option<u128>,
+  /// Share of the time the miner thread spends hashing, in percent. The
+  /// miner sleeps between attempt batches to keep to it, so it doesn't peg a
+  /// core. Defaults to full speed.
+  #[builder(default)]
+  pub intensity: Option<u8>,
 }
 
 // User Interface config
@@ -51,11 +249,60 @@ pub struct UiConfig {
 #[builder(setter(strip_option))]
 pub struct ApiConfig {
   pub port: u16,
+  /// How many API requests may wait for the node at once. When the queue is
+  /// full, API handlers block until the node drains it, so a burst of
+  /// requests is served at the node's pace instead of piling up.
+  #[builder(default = "crate::node::REQUEST_QUEUE_SIZE")]
+  #[serde(default = "default_request_queue_size")]
+  pub request_queue_size: usize,
+  /// How many queued API requests the node handles on each run of its
+  /// request task, bounding the time spent on them per tick.
+  #[builder(default = "crate::node::REQUESTS_PER_RUN")]
+  #[serde(default = "default_requests_per_run")]
+  pub requests_per_run: usize,
+  /// Largest term, in nodes, read back for a response (e.g. a function's
+  /// state). Bigger terms are reported as too big instead of being built.
+  #[builder(default = "crate::node::MAX_TERM_SIZE")]
+  #[serde(default = "default_max_term_size")]
+  pub max_term_size: usize,
+  /// How many of the latest blocks are kept ready for "recent blocks"
+  /// queries, which are then answered without walking the chain.
+  #[builder(default = "crate::node::RECENT_BLOCKS")]
+  #[serde(default = "default_recent_blocks")]
+  pub recent_blocks: usize,
+  /// Whether the tip can be forced to any known block through the API.
+  /// Meant for recovering a node stuck on a bad fork; dangerous otherwise.
+  #[builder(default = "false")]
+  #[serde(default)]
+  pub allow_set_tip: bool,
+}
+
+fn default_request_queue_size() -> usize {
+  crate::node::REQUEST_QUEUE_SIZE
+}
+
+fn default_requests_per_run() -> usize {
+  crate::node::REQUESTS_PER_RUN
+}
+
+fn default_max_term_size() -> usize {
+  crate::node::MAX_TERM_SIZE
+}
+
+fn default_recent_blocks() -> usize {
+  crate::node::RECENT_BLOCKS
 }
 
 impl Default for ApiConfig {
   fn default() -> Self {
-    ApiConfig { port: 8000 }
+    ApiConfig {
+      port: 8000,
+      request_queue_size: crate::node::REQUEST_QUEUE_SIZE,
+      requests_per_run: crate::node::REQUESTS_PER_RUN,
+      max_term_size: crate::node::MAX_TERM_SIZE,
+      recent_blocks: crate::node::RECENT_BLOCKS,
+      allow_set_tip: false,
+    }
   }
 }
 
@@ -9,7 +9,7 @@ use tokio::{self, sync::broadcast};
 use warp::ws::{Message, WebSocket};
 use warp::{Filter, Rejection, Reply};
 
-use crate::api::Hash;
+use crate::api::{Hash, MessageCounts};
 use crate::config::{UiConfig, WsConfig};
 use crate::net::ProtoAddr;
 use crate::node::{HashedBlock, Peer};
@@ -44,11 +44,17 @@ pub enum NodeEventType {
   HandleMessage {
     event: HandleMessageEvent,
   },
+  Status {
+    event: StatusEvent,
+  },
   Heartbeat {
+    /// The node's configured name, if any.
+    name: Option<String>,
     peers: HeartbeatPeers,
     tip: HeartbeatTip,
     blocks: HeartbeatBlocks,
     runtime: HeartbeatRuntime,
+    messages: HeartbeatMessages,
     tip_blocks: Vec<Hash>,
   },
 }
@@ -140,6 +146,23 @@ pub enum HandleMessageEvent {
   },
 }
 
+// Changes in the node's condition an operator should know about. Most come in
+// pairs, marking when a condition starts and when it ends.
+#[derive(Debug, Clone, serde::Serialize)]
+pub enum StatusEvent {
+  StaleTip { seconds: u128 },
+  TipAdvanced,
+  Congested,
+  Uncongested,
+  ChainSplit { heights: Vec<u128> },
+  ChainSplitOver,
+  PendingEvicted { count: usize },
+  SaveFailed { path: String, error: String },
+  SaveRecovered,
+  PauseExpired { seconds: u128 },
+  TipForced { block: Hash, height: u64 },
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct HeartbeatPeers {
   pub num: usize,
@@ -164,6 +187,14 @@ pub struct HeartbeatRuntime {
   pub size: HeartbeatStatInfo,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HeartbeatMessages {
+  /// Messages handled since the node started, by type.
+  pub counts: MessageCounts,
+  /// Messages handled per second since the previous heartbeat.
+  pub per_second: f64,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct HeartbeatStatInfo {
   pub current: i64,
@@ -182,6 +213,7 @@ pub enum NodeEventDiscriminant {
   Heartbeat,
   Mining,
   Peers,
+  Status,
 }
 
 impl std::str::FromStr for NodeEventDiscriminant {
@@ -193,6 +225,7 @@ impl std::str::FromStr for NodeEventDiscriminant {
       "peers" => Ok(NodeEventDiscriminant::Peers),
       "handle_message" => Ok(NodeEventDiscriminant::HandleMessage),
       "heartbeat" => Ok(NodeEventDiscriminant::Heartbeat),
+      "status" => Ok(NodeEventDiscriminant::Status),
       _ => Err(format!(
         "Was not possible to convert from {} to an event discriminant",
         s
@@ -210,6 +243,7 @@ impl From<&NodeEventType> for NodeEventDiscriminant {
       NodeEventType::HandleMessage { .. } => {
         NodeEventDiscriminant::HandleMessage
       }
+      NodeEventType::Status { .. } => NodeEventDiscriminant::Status,
       NodeEventType::Heartbeat { .. } => NodeEventDiscriminant::Heartbeat,
     }
   }
@@ -253,6 +287,16 @@ impl std::fmt::Display for HeartbeatRuntime {
   }
 }
 
+impl std::fmt::Display for HeartbeatMessages {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_fmt(format_args!(
+      "messages: {{ total: {} | per second: {:.1} }}",
+      self.counts.total(),
+      self.per_second
+    ))
+  }
+}
+
 impl std::fmt::Display for HeartbeatStatInfo {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_fmt(format_args!(
@@ -314,6 +358,64 @@ impl std::fmt::Display for PeersEvent {
   }
 }
 
+impl std::fmt::Display for StatusEvent {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let formatted = match self {
+      StatusEvent::StaleTip { seconds } => {
+        format!(
+          "[stale_tip] no new tip for {} seconds; the network may have \
+          stalled or this node is partitioned",
+          seconds
+        )
+      }
+      StatusEvent::TipAdvanced => {
+        "[tip_advanced] the tip advanced again".to_string()
+      }
+      StatusEvent::Congested => {
+        "[congested] socket is congested; pausing gossip".to_string()
+      }
+      StatusEvent::Uncongested => {
+        "[uncongested] socket is no longer congested; resuming gossip"
+          .to_string()
+      }
+      StatusEvent::ChainSplit { heights } => {
+        format!(
+          "[chain_split] peers keep reporting different tips at heights {:?}",
+          heights
+        )
+      }
+      StatusEvent::ChainSplitOver => {
+        "[chain_split_over] peers agree on the tip again".to_string()
+      }
+      StatusEvent::PendingEvicted { count } => {
+        format!("[pending_evicted] evicted {} stale pending blocks", count)
+      }
+      StatusEvent::SaveFailed { path, error } => {
+        format!(
+          "[save_failed] couldn't save block to '{}': {}; mining is paused \
+          until blocks can be saved again",
+          path, error
+        )
+      }
+      StatusEvent::SaveRecovered => {
+        "[save_recovered] unsaved blocks were written; resuming mining"
+          .to_string()
+      }
+      StatusEvent::PauseExpired { seconds } => {
+        format!(
+          "[pause_expired] block processing was paused for over {} seconds; \
+          resuming",
+          seconds
+        )
+      }
+      StatusEvent::TipForced { block, height } => {
+        format!("[tip_forced] tip forced to {} (height {})", block, height)
+      }
+    };
+    f.write_fmt(format_args!("{}", formatted))
+  }
+}
+
 impl std::fmt::Display for RollbackInfo {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_fmt(format_args!(
@@ -401,8 +503,18 @@ impl std::fmt::Display for NodeEventType {
       NodeEventType::HandleMessage { event } => {
         format!("[handle_message] {}", event)
       }
-      NodeEventType::Heartbeat { peers, tip, blocks, runtime, .. } => {
-        format!("[heartbeat] {} {} {} {}", peers, tip, blocks, runtime)
+      NodeEventType::Status { event } => {
+        format!("[status] {}", event)
+      }
+      NodeEventType::Heartbeat {
+        name, peers, tip, blocks, runtime, messages, ..
+      } => {
+        let name =
+          name.as_ref().map(|n| format!("[{}] ", n)).unwrap_or_default();
+        format!(
+          "[heartbeat] {}{} {} {} {} {}",
+          name, peers, tip, blocks, runtime, messages
+        )
       }
     };
 
@@ -633,11 +745,52 @@ impl NodeEventType {
     };
     NodeEventType::HandleMessage { event }
   }
+
+  // STATUS
+  pub fn stale_tip(seconds: u128) -> Self {
+    NodeEventType::Status { event: StatusEvent::StaleTip { seconds } }
+  }
+  pub fn tip_advanced() -> Self {
+    NodeEventType::Status { event: StatusEvent::TipAdvanced }
+  }
+  pub fn congested() -> Self {
+    NodeEventType::Status { event: StatusEvent::Congested }
+  }
+  pub fn uncongested() -> Self {
+    NodeEventType::Status { event: StatusEvent::Uncongested }
+  }
+  pub fn chain_split(heights: Vec<u128>) -> Self {
+    NodeEventType::Status { event: StatusEvent::ChainSplit { heights } }
+  }
+  pub fn chain_split_over() -> Self {
+    NodeEventType::Status { event: StatusEvent::ChainSplitOver }
+  }
+  pub fn pending_evicted(count: usize) -> Self {
+    NodeEventType::Status { event: StatusEvent::PendingEvicted { count } }
+  }
+  pub fn save_failed(path: &std::path::Path, error: &std::io::Error) -> Self {
+    let event = StatusEvent::SaveFailed {
+      path: path.display().to_string(),
+      error: error.to_string(),
+    };
+    NodeEventType::Status { event }
+  }
+  pub fn save_recovered() -> Self {
+    NodeEventType::Status { event: StatusEvent::SaveRecovered }
+  }
+  pub fn pause_expired(seconds: u128) -> Self {
+    NodeEventType::Status { event: StatusEvent::PauseExpired { seconds } }
+  }
+  pub fn tip_forced(block: U256, height: u64) -> Self {
+    let event = StatusEvent::TipForced { block: block.into(), height };
+    NodeEventType::Status { event }
+  }
 }
 
 #[macro_export]
 macro_rules! heartbeat {
   (
+    name: $name:expr,
     peers: { num: $peers_num:expr },
     tip: {
       height: $tip_height:expr,
@@ -660,9 +813,14 @@ macro_rules! heartbeat {
         available: $size_avail:expr,
       }
     },
+    messages: {
+      counts: $message_counts:expr,
+      per_second: $message_rate:expr,
+    },
     tip_blocks: $tip_blocks:expr
   ) => {
     NodeEventType::Heartbeat {
+      name: $name,
       peers: $crate::events::HeartbeatPeers { num: $peers_num },
       tip: $crate::events::HeartbeatTip {
         height: $tip_height,
@@ -685,6 +843,10 @@ macro_rules! heartbeat {
           available: $size_avail,
         },
       },
+      messages: $crate::events::HeartbeatMessages {
+        counts: $message_counts,
+        per_second: $message_rate,
+      },
       tip_blocks: $tip_blocks.iter().map(|x: &U256| (*x).into()).collect(),
     }
   };
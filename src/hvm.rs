@@ -244,6 +244,7 @@
 use std::collections::{hash_map, HashMap, HashSet};
 use std::fmt::{self, Write};
 use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
 use std::fs::File;
 use std::sync::Arc;
@@ -531,6 +532,76 @@ pub enum Rollback {
   Nil,
 }
 
+// A copy of every heap and of the Rollback list. Unlike `export_state`, it includes the current
+// heap, so restoring it brings the runtime back to the exact tick it was taken at.
+#[derive(Clone)]
+pub struct RuntimeImage {
+  heap: Vec<Heap>,
+  draw: u64,
+  curr: u64,
+  nuls: Vec<u64>,
+  back: Arc<Rollback>,
+}
+
+impl RuntimeImage {
+  // Writes the image to a new directory: every heap, plus a `_runtime_` file listing the heap
+  // uuids, the heap indices and the Rollback list, head first.
+  pub fn save(&self, path: &PathBuf) -> std::io::Result<()> {
+    std::fs::create_dir(path)?;
+    let mut meta : Vec<u128> = vec![];
+    meta.push(self.heap.len() as u128);
+    meta.extend(self.heap.iter().map(|heap| heap.uuid));
+    meta.push(self.draw as u128);
+    meta.push(self.curr as u128);
+    meta.push(self.nuls.len() as u128);
+    meta.extend(self.nuls.iter().map(|nul| *nul as u128));
+    let mut back = self.back.clone();
+    while let Rollback::Cons { keep, life, head, tail } = &*back {
+      meta.extend([*keep as u128, *life as u128, *head as u128]);
+      back = tail.clone();
+    }
+    for heap in &self.heap {
+      heap.serialize(path, false)?;
+    }
+    std::fs::write(path.join("_runtime_"), &util::u128s_to_u8s(&meta))
+  }
+
+  // Reads an image written by `save`.
+  pub fn load(path: &PathBuf) -> std::io::Result<RuntimeImage> {
+    fn next(meta: &mut std::vec::IntoIter<u128>) -> std::io::Result<u128> {
+      meta.next().ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+    }
+    let mut meta = util::u8s_to_u128s(&std::fs::read(path.join("_runtime_"))?).into_iter();
+    let heap_count = next(&mut meta)?;
+    if heap_count != MAX_HEAPS as u128 {
+      return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+    }
+    let mut heap = Vec::new();
+    for _ in 0 .. heap_count {
+      heap.push(Heap::deserialize(next(&mut meta)?, path)?);
+    }
+    let draw = next(&mut meta)? as u64;
+    let curr = next(&mut meta)? as u64;
+    let nuls_count = next(&mut meta)?;
+    let mut nuls = Vec::new();
+    for _ in 0 .. nuls_count {
+      nuls.push(next(&mut meta)? as u64);
+    }
+    let mut rollbacks = vec![];
+    while let Some(keep) = meta.next() {
+      let life = next(&mut meta)?;
+      let head = next(&mut meta)?;
+      rollbacks.push((keep as u64, life as u64, head as u64));
+    }
+    // Rebuilds the Rollback list from its tail
+    let mut back = Arc::new(Rollback::Nil);
+    for (keep, life, head) in rollbacks.into_iter().rev() {
+      back = Arc::new(Rollback::Cons { keep, life, head, tail: back });
+    }
+    Ok(RuntimeImage { heap, draw, curr, nuls, back })
+  }
+}
+
 // The current and past states
 pub struct Runtime {
   heap: Vec<Heap>,      // heap objects
@@ -539,6 +610,7 @@ pub struct Runtime {
   nuls: Vec<u64>,       // reuse heap indices
   back: Arc<Rollback>,  // past states
   path: PathBuf,        // where to save runtime state
+  stmt_mana: Option<u64>, // mana a single statement may spend, if capped
 }
 
 #[derive(Debug, Clone)]
@@ -610,7 +682,12 @@ pub type StatementResult = Result<StatementInfo, StatementErr>;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum StatementInfo {
   Ctr { name: Name, args: Vec<Name> },
-  Fun { name: Name, args: Vec<Name> },
+  Fun {
+    name: Name,
+    args: Vec<Name>,
+    #[serde_as(as = "DisplayFromStr")]
+    used_mana: u64,
+  },
   Run {
     done_term: Term,
     #[serde_as(as = "DisplayFromStr")]
@@ -628,6 +705,15 @@ pub struct StatementErr {
   pub err: String,
 }
 
+/// A function state changed by dry-running statements. `None` stands for a
+/// missing state, or one too big to be read back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDiff {
+  pub name: Name,
+  pub old: Option<Term>,
+  pub new: Option<Term>,
+}
+
 pub type ParseResult<'a, A> = Result<(&'a str, A), ParseErr>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1076,7 +1162,7 @@ impl fmt::Display for StatementInfo {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
       StatementInfo::Ctr { name, args } => write!(f, "[ctr] {}", name),
-      StatementInfo::Fun { name, args } => write!(f, "[fun] {}", name),
+      StatementInfo::Fun { name, .. } => write!(f, "[fun] {}", name),
       StatementInfo::Reg { name, .. } => write!(f, "[reg] {}", name),
       StatementInfo::Run { done_term, used_mana, size_diff, .. } =>
         write!(f, "[run] {} \x1b[2m[{} mana | {} size]\x1b[0m", view_term(&done_term), used_mana, size_diff)
@@ -1504,9 +1590,13 @@ impl Hashs {
 }
 
 
-pub fn init_runtime(heaps_path: PathBuf, init_stmts: &[Statement]) -> Runtime {
+/// Creates a runtime persisted at `heaps_path` and runs `init_stmts` on it.
+/// Fails if the heaps directory can't be created.
+pub fn init_runtime(heaps_path: PathBuf, init_stmts: &[Statement]) -> Result<Runtime, String> {
   // Default runtime store path
-  std::fs::create_dir_all(&heaps_path).unwrap(); // TODO: handle unwrap
+  std::fs::create_dir_all(&heaps_path).map_err(|err| {
+    format!("Could not create the heaps directory '{}': {}.", heaps_path.display(), err)
+  })?;
   let mut heap = Vec::new();
   for i in 0 .. MAX_HEAPS {
     heap.push(init_heap());
@@ -1518,12 +1608,13 @@ pub fn init_runtime(heaps_path: PathBuf, init_stmts: &[Statement]) -> Runtime {
     nuls: (2 .. MAX_HEAPS).collect(),
     back: Arc::new(Rollback::Nil),
     path: heaps_path,
+    stmt_mana: None,
   };
 
   rt.run_statements(init_stmts, true, false);
   rt.commit();
 
-  rt
+  Ok(rt)
 }
 
 impl Runtime {
@@ -1592,7 +1683,7 @@ impl Runtime {
   pub fn run_statements(&mut self, statements: &[Statement], silent: bool, debug: bool) -> Vec<StatementResult> {
     statements.iter().enumerate().map(
       |(i, s)| {
-        let res = self.run_statement(s, silent, debug, Some(i));
+        let res = self.run_statement_catching(s, silent, debug, Some(i));
         if let Ok(..) = res {
           self.draw();
         }
@@ -1614,7 +1705,7 @@ impl Runtime {
   pub fn test_statements(&mut self, statements: &[Statement]) -> Vec<StatementResult> {
     let mut results = vec![];
     for (idx, statement) in statements.iter().enumerate() {
-      let res = self.run_statement(statement, true, false, Some(idx));
+      let res = self.run_statement_catching(statement, true, false, Some(idx));
       match res {
         Ok(..) => {
           results.push(res);
@@ -1639,6 +1730,45 @@ impl Runtime {
     }
   }
 
+  // Like `test_statements`, but also returns the function states they changed,
+  // sorted by name. At most `max_diffs` changes are returned, and terms bigger
+  // than `term_limit` nodes are not read back. Nothing is committed.
+  pub fn diff_statements(&mut self, statements: &[Statement], max_diffs: usize, term_limit: usize) -> (Vec<StatementResult>, Vec<StateDiff>) {
+    let mut results = vec![];
+    for (idx, statement) in statements.iter().enumerate() {
+      let res = self.run_statement_catching(statement, true, false, Some(idx));
+      let failed = res.is_err();
+      results.push(res);
+      if failed {
+        break;
+      }
+    }
+    // Until undone, every state written by the statements is on the draw heap
+    let mut names: Vec<U120> = self.get_heap(self.draw).disk.links.keys().copied().collect();
+    names.sort_by_key(|name| **name);
+    let news: Vec<Option<Term>> = names.iter().map(|name| self.read_state(*name, term_limit)).collect();
+    self.undo();
+    let mut diffs = vec![];
+    for (name, new) in names.into_iter().zip(news) {
+      if diffs.len() >= max_diffs {
+        break;
+      }
+      let old = self.read_state(name, term_limit);
+      if old != new {
+        diffs.push(StateDiff { name: Name::from(name), old, new });
+      }
+    }
+    (results, diffs)
+  }
+
+  // Reads a state back, treating a taken (and never saved) one as missing
+  fn read_state(&mut self, name: U120, term_limit: usize) -> Option<Term> {
+    match self.read_disk(name) {
+      Some(RawCell(U128_NONE)) | None => None,
+      Some(..) => self.read_disk_as_term(name, Some(term_limit)),
+    }
+  }
+
   pub fn compute_at(&mut self, loc: Loc, mana: u64) -> Result<RawCell, RuntimeError> {
     compute_at(self, loc, mana)
   }
@@ -1944,6 +2074,29 @@ impl Runtime {
     }
   }
 
+  /// Like `run_statement`, but a panic while running the statement is turned
+  /// into a failed result, with its partial changes undone, instead of taking
+  /// the whole node down along with it.
+  pub fn run_statement_catching(&mut self, statement: &Statement, silent: bool, sudo: bool, stmt_index: Option<usize>) -> StatementResult {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| self.run_statement(statement, silent, sudo, stmt_index)));
+    match res {
+      Ok(res) => res,
+      Err(payload) => {
+        self.undo();
+        let reason = match payload.downcast_ref::<&str>() {
+          Some(reason) => reason.to_string(),
+          None => match payload.downcast_ref::<String>() {
+            Some(reason) => reason.clone(),
+            None => "unknown cause".to_string(),
+          },
+        };
+        let err = format!("Statement panicked: {}", reason);
+        println!("{:02$} ERROR: {}", self.get_tick(), err, 10);
+        Err(StatementErr { err })
+      }
+    }
+  }
+
   /// Run statement in the `draw` heap.
   ///
   /// It doesn't alter `curr` heap.
@@ -1978,15 +2131,18 @@ impl Runtime {
         let name = *name;
         self.set_arity(name, args.len() as u64);
         self.define_function(name, func, stmt_index, hash);
+        let mana_ini = self.get_mana();
         if let Some(state) = init {
           let state = self.create_term(state, Loc(0), &mut init_name_map());
           let state = handle_runtime_err(self, "fun", state)?;
-          let state = self.compute(state, self.get_mana_limit());
-          let state = handle_runtime_err(self, "fun", state)?;
+          let mana_lim = self.get_statement_mana_limit(self.get_mana());
+          let state = self.compute(state, mana_lim);
+          let state = self.statement_mana_err(state, "fun")?;
           self.write_disk(U120::from(name), state);
         }
         let args = args.iter().map(|x| *x).collect::<Vec<_>>();
-        StatementInfo::Fun { name, args }
+        let used_mana = self.get_mana() - mana_ini;
+        StatementInfo::Fun { name, args, used_mana }
       }
       Statement::Ctr { name, args, sign } => {
         if self.exists(name) {
@@ -2006,7 +2162,7 @@ impl Runtime {
       }
       Statement::Run { expr, sign } => {
         let mana_ini = self.get_mana();
-        let mana_lim = if !sudo { self.get_mana_limit() } else { u64::MAX }; // ugly
+        let mana_lim = if !sudo { self.get_statement_mana_limit(mana_ini) } else { u64::MAX }; // ugly
         let size_ini = self.get_size();
         let size_lim = self.get_size_limit();
         handle_runtime_err(self, "run", check_term(&expr))?; 
@@ -2014,15 +2170,9 @@ impl Runtime {
         let host = self.alloc_term(expr);
         let host = handle_runtime_err(self, "run", host)?;
         let done = self.run_io(subj, U120::from_u128_unchecked(0), host, mana_lim);
-        if let Err(err) = done {
-          return error(self, "run", show_runtime_error(err));
-        }
-        let done = done.unwrap();
+        let done = self.statement_mana_err(done, "run")?;
         let done = self.compute(done, mana_lim);
-        if let Err(err) = done {
-          return error(self, "run", show_runtime_error(err));
-        }
-        let done = done.unwrap();
+        let done = self.statement_mana_err(done, "run")?;
         // TODO:
         // The term return by Done is only read and stored in debug mode for
         // testing purpouses. In the future, the Done return value will be
@@ -2077,6 +2227,38 @@ impl Runtime {
     (self.get_tick() + 1) * BLOCK_MANA_LIMIT
   }
 
+  /// Caps the mana a single statement may spend, on top of the block limit.
+  /// Statements going over it fail instead of running unbounded. Since that
+  /// changes their results, every node of a network must use the same cap.
+  pub fn set_statement_mana(&mut self, cap: Option<u64>) {
+    self.stmt_mana = cap;
+  }
+
+  // Maximum mana counter for a statement starting at `mana_ini`
+  pub fn get_statement_mana_limit(&self, mana_ini: u64) -> u64 {
+    let limit = self.get_mana_limit();
+    match self.stmt_mana {
+      Some(cap) => std::cmp::min(limit, mana_ini.saturating_add(cap)),
+      None => limit,
+    }
+  }
+
+  // Like `handle_runtime_err`, telling apart running out of the statement's
+  // own mana cap from running out of the block's mana
+  fn statement_mana_err<T>(&mut self, val: Result<T, RuntimeError>, tag: &str) -> Result<T, StatementErr> {
+    val.map_err(|err| {
+      let err = match (err, self.stmt_mana) {
+        (RuntimeError::NotEnoughMana, Some(cap)) => {
+          format!("Not enough mana (statements may spend at most {}).", cap)
+        }
+        (err, _) => show_runtime_error(err),
+      };
+      self.undo(); // TODO: don't undo inside here. too much coupling
+      println!("{:03$} [{}] ERROR: {}", self.get_tick(), tag, err, 10);
+      StatementErr { err }
+    })
+  }
+
   // Maximum size = 2048 * block_number
   pub fn get_size_limit(&self) -> u64 {
     (self.get_tick() as u64 + 1) * (BLOCK_BITS_LIMIT / 128)
@@ -2090,6 +2272,37 @@ impl Runtime {
     return self.back.clone();
   }
 
+  /// Tick of the oldest snapshot kept for rollbacks, if any. Rolling back
+  /// before it leaves no snapshot to restore, so the runtime starts over.
+  pub fn get_oldest_snapshot_tick(&self) -> Option<u64> {
+    let mut oldest = None;
+    let mut back = self.back.clone();
+    while let Rollback::Cons { head, tail, .. } = &*back.clone() {
+      // The genesis state is committed without a tick
+      let tick = self.get_heap(*head).tick;
+      oldest = Some(if tick == U64_NONE { 0 } else { tick });
+      back = tail.clone();
+    }
+    oldest
+  }
+
+  /// Tick `rollback(tick)` lands on: that of the newest snapshot at or
+  /// before `tick`, or 0 when there is none and the runtime starts over.
+  pub fn get_rollback_tick(&self, tick: u64) -> u64 {
+    let mut back = self.back.clone();
+    while let Rollback::Cons { head, tail, .. } = &*back.clone() {
+      let snapshot = self.get_heap(*head).tick;
+      if snapshot == U64_NONE {
+        return 0;
+      }
+      if snapshot <= tick {
+        return snapshot;
+      }
+      back = tail.clone();
+    }
+    0
+  }
+
   /// Advances the heap time counter.
   pub fn open(&mut self) {
     self.set_tick(self.get_tick() + 1);
@@ -2167,6 +2380,11 @@ impl Runtime {
   // included on the Rollback list. In other words, it forgets up to ~16 recent blocks. This
   // function is used to avoid re-processing the entire block history on node startup.
   pub fn save_state_metadata(&self) -> std::io::Result<()> {
+    self.write_state_metadata(&self.path)
+  }
+
+  // Writes the Rollback list metadata (keeps, lifes and heap uuids) to the given directory.
+  fn write_state_metadata(&self, path: &PathBuf) -> std::io::Result<()> {
     fn build_persistence_buffers(rt: &Runtime, rollback: &Rollback, keeps: &mut Vec<u128>, lifes: &mut Vec<u128>, uuids: &mut Vec<u128>) {
       match rollback {
         Rollback::Cons { keep, life, head, tail } => {
@@ -2182,12 +2400,36 @@ impl Runtime {
     let mut lifes : Vec<u128> = vec![];
     let mut uuids : Vec<u128> = vec![];
     build_persistence_buffers(self, &self.back,  &mut keeps, &mut lifes, &mut uuids);
-    std::fs::write(self.path.join("_keeps_"), &util::u128s_to_u8s(&keeps))?;
-    std::fs::write(self.path.join("_lifes_"), &util::u128s_to_u8s(&lifes))?;
-    std::fs::write(self.path.join("_uuids_"), &util::u128s_to_u8s(&uuids))?;
+    std::fs::write(path.join("_keeps_"), &util::u128s_to_u8s(&keeps))?;
+    std::fs::write(path.join("_lifes_"), &util::u128s_to_u8s(&lifes))?;
+    std::fs::write(path.join("_uuids_"), &util::u128s_to_u8s(&uuids))?;
     return Ok(());
   }
 
+  // Exports a self-contained copy of the state to a new directory: the heaps on the Rollback
+  // list and their metadata. Like `save_state_metadata`, this doesn't include the current heap,
+  // so the exported state is up to ~16 blocks older than the current tick.
+  pub fn export_state(&self, path: &PathBuf) -> std::io::Result<()> {
+    std::fs::create_dir(path)?;
+    let mut back = self.back.clone();
+    while let Rollback::Cons { head, tail, .. } = &*back {
+      self.heap[*head as usize].serialize(path, false)?;
+      back = tail.clone();
+    }
+    self.write_state_metadata(path)
+  }
+
+  // Replaces the current state by one exported with `export_state`, setting the runtime to the
+  // tick it was exported at. The imported state is trusted as is: nothing checks that it is the
+  // result of running the chain up to that tick, so it must come from a trusted source.
+  pub fn import_state(&mut self, path: &PathBuf) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(path)? {
+      let entry = entry?;
+      std::fs::copy(entry.path(), self.path.join(entry.file_name()))?;
+    }
+    self.restore_state()
+  }
+
   // Restores the saved state. This loads the persisted Rollback list and its heaps.
   pub fn restore_state(&mut self) -> std::io::Result<()> {
     for i in 0 .. MAX_HEAPS {
@@ -2234,6 +2476,27 @@ impl Runtime {
     return Ok(());
   }
 
+  // Copies the whole state, see `RuntimeImage`.
+  pub fn image(&self) -> RuntimeImage {
+    RuntimeImage {
+      heap: self.heap.clone(),
+      draw: self.draw,
+      curr: self.curr,
+      nuls: self.nuls.clone(),
+      back: self.back.clone(),
+    }
+  }
+
+  // Replaces the whole state by `image`. The persistence path and the statement mana cap are
+  // settings of this runtime, so they are kept.
+  pub fn restore_image(&mut self, image: RuntimeImage) {
+    self.heap = image.heap;
+    self.draw = image.draw;
+    self.curr = image.curr;
+    self.nuls = image.nuls;
+    self.back = image.back;
+  }
+
   // Reverts until the last 
   pub fn clear_current_heap(&mut self) {
     self.heap[self.curr as usize].clear();
@@ -4117,9 +4380,17 @@ fn show_runtime_error(err: RuntimeError) -> String {
 }
 
 pub fn readback_term(rt: &Runtime, term: RawCell, limit:Option<usize>) -> Option<Term> {
-  fn find_names(rt: &Runtime, term: RawCell, names: &mut LocMap<String>) {
+  // Gives up, returning `None`, after visiting `limit` nodes
+  fn find_names(rt: &Runtime, term: RawCell, names: &mut LocMap<String>, limit: Option<usize>) -> Option<()> {
     let mut stack = vec![term];
+    let mut count = 0;
     while !stack.is_empty() {
+      if let Some(limit) = limit {
+        if count == limit {
+          return None;
+        }
+        count += 1;
+      }
       let term = stack.pop().unwrap();
       match get_tag(term) {
         LAM => {
@@ -4167,6 +4438,7 @@ pub fn readback_term(rt: &Runtime, term: RawCell, limit:Option<usize>) -> Option
         _ => {}
       }
     }
+    Some(())
  }
 
   struct DupStore {
@@ -4383,7 +4655,7 @@ pub fn readback_term(rt: &Runtime, term: RawCell, limit:Option<usize>) -> Option
   let mut names: LocMap<String> = init_loc_map();
   let mut seen: HashSet<RawCell> = HashSet::new();
   let mut dup_store = DupStore::new();
-  find_names(rt, term, &mut names);
+  find_names(rt, term, &mut names, limit)?;
   readback(rt, term, &mut names, &mut seen, &mut dup_store, limit)
 }
 
@@ -5113,7 +5385,7 @@ pub fn test_statements(statements: &Vec<Statement>, debug: bool) {
   // TODO: code below does not need heaps_path at all. extract heap persistence out of Runtime.
   let heaps_path = dirs::home_dir().unwrap().join(".kindelia").join("state").join("heaps");
   let genesis_smts = parse_code(constants::GENESIS_CODE).expect("Genesis code parses");
-  let mut rt = init_runtime(heaps_path, &genesis_smts);
+  let mut rt = init_runtime(heaps_path, &genesis_smts).expect("Runtime initializes");
   let init = Instant::now();
   rt.run_statements(&statements, false, debug);
   println!();
@@ -31,6 +31,11 @@ where
 
 /// Defines how the messages will be sent and received
 /// by the chain nodes.
+///
+/// This is the node's transport: `Node` is generic over it, so it never
+/// touches sockets directly. `UdpSocket` implements it for production, and
+/// tests plug in-memory implementations. Other backends (e.g. TCP or QUIC)
+/// only need to implement this trait.
 pub trait ProtoComm
 where
   Self: Sized + Send,
@@ -40,9 +45,32 @@ where
     &mut self,
     addresses: Vec<Self::Address>,
     message: &Message<Self::Address>,
+  ) -> SendStatus;
+  /// Appends the pending messages to `messages`. `buffer` is scratch space
+  /// for reading them; larger packets may be dropped. Both are reused by the
+  /// caller between calls, to save allocations.
+  fn proto_recv(
+    &mut self,
+    buffer: &mut [u8],
+    messages: &mut Vec<(Self::Address, Message<Self::Address>)>,
   );
-  fn proto_recv(&mut self) -> Vec<(Self::Address, Message<Self::Address>)>;
   fn get_addr(&self) -> Self::Address;
+  /// Sends the messages queued by `proto_send`, on transports that batch
+  /// them. The node calls it once per tick. `None` if nothing was queued.
+  fn flush(&mut self) -> Option<SendStatus> {
+    None
+  }
+}
+
+/// Whether a `proto_send` got its messages out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+  Sent,
+  /// The transport couldn't take some of the messages right now (e.g. the
+  /// socket buffer is full), so they were dropped.
+  Congested,
+  /// The messages were queued, and go out on the next `flush`.
+  Queued,
 }
 
 // UDP Implementation
@@ -51,6 +79,12 @@ where
 /// Default UDP port to listen to.
 pub const UDP_PORT: u16 = 42000;
 
+/// Default size of the buffer packets are received on; fits any datagram.
+pub const RECV_BUFFER_SIZE: usize = 65536;
+
+/// Most packets a `BatchedUdp` hands to the OS in a single call.
+pub const SEND_BATCH_SIZE: usize = 512;
+
 /// An UDP address representation.
 #[derive(
   Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
@@ -62,6 +96,14 @@ pub enum Address {
 
 impl ProtoAddr for Address {}
 
+impl Address {
+  pub fn port(&self) -> u16 {
+    match self {
+      Address::IPv4 { port, .. } => *port,
+    }
+  }
+}
+
 impl std::fmt::Display for Address {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
@@ -72,19 +114,69 @@ impl std::fmt::Display for Address {
   }
 }
 
-/// Converts a string to an UDP Address.
-/// TODO: UNSAFE.
-pub fn parse_address(code: &str) -> Address {
-  let strs = code.split(':').collect::<Vec<&str>>();
-  let vals =
-    strs[0].split('.').map(|o| o.parse::<u8>().unwrap()).collect::<Vec<u8>>();
-  let port = strs.get(1).map(|s| s.parse::<u16>().unwrap()).unwrap_or(UDP_PORT);
-  Address::IPv4 {
-    val0: vals[0],
-    val1: vals[1],
-    val2: vals[2],
-    val3: vals[3],
-    port,
+/// Reason why a string is not a valid UDP address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrParseError {
+  /// The `host:port` separator is followed by nothing.
+  MissingPort,
+  /// The host doesn't have exactly 4 octets.
+  WrongOctetCount { count: usize },
+  /// An octet is not a number from 0 to 255.
+  InvalidOctet { octet: String },
+  /// The port is not a number from 0 to 65535.
+  InvalidPort { port: String },
+}
+
+impl std::fmt::Display for AddrParseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      AddrParseError::MissingPort => write!(f, "missing port after ':'"),
+      AddrParseError::WrongOctetCount { count } => {
+        write!(f, "expected 4 octets, found {}", count)
+      }
+      AddrParseError::InvalidOctet { octet } => {
+        write!(f, "invalid octet '{}'", octet)
+      }
+      AddrParseError::InvalidPort { port } => {
+        write!(f, "invalid port '{}'", port)
+      }
+    }
+  }
+}
+
+/// Converts a string like `"1.2.3.4:5678"` to an UDP Address. The port is
+/// optional and defaults to `UDP_PORT`.
+pub fn parse_address(code: &str) -> Result<Address, AddrParseError> {
+  let (host, port) = match code.split_once(':') {
+    Some((_, "")) => return Err(AddrParseError::MissingPort),
+    Some((host, port)) => {
+      let port = port
+        .parse::<u16>()
+        .map_err(|_| AddrParseError::InvalidPort { port: port.to_string() })?;
+      (host, port)
+    }
+    None => (code, UDP_PORT),
+  };
+  let vals = host
+    .split('.')
+    .map(|o| {
+      o.parse::<u8>()
+        .map_err(|_| AddrParseError::InvalidOctet { octet: o.to_string() })
+    })
+    .collect::<Result<Vec<u8>, _>>()?;
+  match vals[..] {
+    [val0, val1, val2, val3] => {
+      Ok(Address::IPv4 { val0, val1, val2, val3, port })
+    }
+    _ => Err(AddrParseError::WrongOctetCount { count: vals.len() }),
+  }
+}
+
+fn to_socket_addr(address: Address) -> SocketAddrV4 {
+  match address {
+    Address::IPv4 { val0, val1, val2, val3, port } => {
+      SocketAddrV4::new(Ipv4Addr::new(val0, val1, val2, val3), port)
+    }
   }
 }
 
@@ -95,37 +187,46 @@ impl ProtoComm for UdpSocket {
     &mut self,
     addresses: Vec<Self::Address>,
     message: &Message<Self::Address>,
-  ) {
+  ) -> SendStatus {
     let bytes = bitvec_to_bytes(&message.proto_serialized());
+    let mut status = SendStatus::Sent;
     for address in addresses {
-      match address {
-        Address::IPv4 { val0, val1, val2, val3, port } => {
-          let addr =
-            SocketAddrV4::new(Ipv4Addr::new(val0, val1, val2, val3), port);
-          self.send_to(bytes.as_slice(), addr).ok();
+      // The socket is non-blocking, so a full buffer fails right away;
+      // other errors concern a single destination and are ignored
+      if let Err(err) = self.send_to(bytes.as_slice(), to_socket_addr(address))
+      {
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+          status = SendStatus::Congested;
         }
       }
     }
+    status
   }
-  fn proto_recv(&mut self) -> Vec<(Self::Address, Message<Self::Address>)> {
-    let mut buffer = [0; 65536];
-    let mut messages = Vec::new();
-    while let Ok((msg_len, sender_addr)) = self.recv_from(&mut buffer) {
+  fn proto_recv(
+    &mut self,
+    buffer: &mut [u8],
+    messages: &mut Vec<(Self::Address, Message<Self::Address>)>,
+  ) {
+    while let Ok((msg_len, sender_addr)) = self.recv_from(buffer) {
       let bits = BitVec::from_bytes(&buffer[0..msg_len]);
-      if let Some(msge) = Message::proto_deserialized(&bits) {
-        let addr = match sender_addr.ip() {
-          std::net::IpAddr::V4(v4addr) => {
-            let [val0, val1, val2, val3] = v4addr.octets();
-            Address::IPv4 { val0, val1, val2, val3, port: sender_addr.port() }
-          }
-          _ => {
-            panic!("TODO: IPv6")
-          }
-        };
-        messages.push((addr, msge));
+      match Message::proto_deserialized(&bits) {
+        Ok(msge) => {
+          let addr = match sender_addr.ip() {
+            std::net::IpAddr::V4(v4addr) => {
+              let [val0, val1, val2, val3] = v4addr.octets();
+              Address::IPv4 { val0, val1, val2, val3, port: sender_addr.port() }
+            }
+            _ => {
+              panic!("TODO: IPv6")
+            }
+          };
+          messages.push((addr, msge));
+        }
+        Err(err) => {
+          crate::dbg_println!("Dropped packet from {}: {}", sender_addr, err);
+        }
       }
     }
-    messages
   }
   fn get_addr(&self) -> Self::Address {
     // TODO: remove unwrap and panic
@@ -138,3 +239,155 @@ impl ProtoComm for UdpSocket {
     }
   }
 }
+
+/// An UDP transport that queues outgoing packets and sends them together when
+/// flushed, once per node tick. On Linux, a flush takes one `sendmmsg` call
+/// per `SEND_BATCH_SIZE` packets, instead of one `send_to` per packet, which
+/// adds up when gossiping to many peers. Elsewhere, packets are still sent
+/// one by one. A plain `UdpSocket` sends every message right away.
+pub struct BatchedUdp {
+  socket: UdpSocket,
+  // Serialized messages queued since the last flush
+  messages: Vec<Vec<u8>>,
+  // Queued packets: destination, and index of their message
+  packets: Vec<(SocketAddrV4, usize)>,
+  /// How many packets were sent so far.
+  pub sent_packets: u64,
+  /// How many system calls sending them took.
+  pub send_calls: u64,
+}
+
+impl BatchedUdp {
+  pub fn new(socket: UdpSocket) -> Self {
+    BatchedUdp {
+      socket,
+      messages: vec![],
+      packets: vec![],
+      sent_packets: 0,
+      send_calls: 0,
+    }
+  }
+
+  pub fn socket(&self) -> &UdpSocket {
+    &self.socket
+  }
+
+  // Sends the packets one by one
+  #[cfg(not(target_os = "linux"))]
+  fn send_packets(&mut self) -> SendStatus {
+    let mut status = SendStatus::Sent;
+    for (addr, index) in &self.packets {
+      self.send_calls += 1;
+      match self.socket.send_to(&self.messages[*index], addr) {
+        Ok(_) => self.sent_packets += 1,
+        Err(err) => {
+          if err.kind() == std::io::ErrorKind::WouldBlock {
+            status = SendStatus::Congested;
+          }
+        }
+      }
+    }
+    status
+  }
+
+  // Hands the packets to the OS in batches of `SEND_BATCH_SIZE`
+  #[cfg(target_os = "linux")]
+  fn send_packets(&mut self) -> SendStatus {
+    use std::os::unix::io::AsRawFd;
+    let fd = self.socket.as_raw_fd();
+    let mut names: Vec<libc::sockaddr_in> = self
+      .packets
+      .iter()
+      .map(|(addr, _)| libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: addr.port().to_be(),
+        sin_addr: libc::in_addr {
+          s_addr: u32::from_ne_bytes(addr.ip().octets()),
+        },
+        sin_zero: [0; 8],
+      })
+      .collect();
+    let mut iovecs: Vec<libc::iovec> = self
+      .packets
+      .iter()
+      .map(|(_, index)| libc::iovec {
+        iov_base: self.messages[*index].as_ptr() as *mut libc::c_void,
+        iov_len: self.messages[*index].len(),
+      })
+      .collect();
+    let mut headers: Vec<libc::mmsghdr> = names
+      .iter_mut()
+      .zip(iovecs.iter_mut())
+      .map(|(name, iovec)| {
+        // SAFETY: both structs are plain C data, valid when zeroed
+        let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+        header.msg_hdr.msg_name = name as *mut _ as *mut libc::c_void;
+        header.msg_hdr.msg_namelen =
+          std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        header.msg_hdr.msg_iov = iovec;
+        header.msg_hdr.msg_iovlen = 1;
+        header
+      })
+      .collect();
+    let mut start = 0;
+    while start < headers.len() {
+      let count = std::cmp::min(headers.len() - start, SEND_BATCH_SIZE);
+      self.send_calls += 1;
+      // SAFETY: the headers point to `names`, `iovecs` and `self.messages`,
+      // which outlive the call and aren't touched meanwhile
+      let sent = unsafe {
+        libc::sendmmsg(fd, headers[start..].as_mut_ptr(), count as _, 0)
+      };
+      if sent < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+          return SendStatus::Congested;
+        }
+        // The first packet's destination was refused; skips it
+        start += 1;
+      } else {
+        self.sent_packets += sent as u64;
+        start += sent as usize;
+      }
+    }
+    SendStatus::Sent
+  }
+}
+
+impl ProtoComm for BatchedUdp {
+  type Address = Address;
+  fn proto_send(
+    &mut self,
+    addresses: Vec<Self::Address>,
+    message: &Message<Self::Address>,
+  ) -> SendStatus {
+    if addresses.is_empty() {
+      return SendStatus::Queued;
+    }
+    let index = self.messages.len();
+    self.messages.push(bitvec_to_bytes(&message.proto_serialized()));
+    for address in addresses {
+      self.packets.push((to_socket_addr(address), index));
+    }
+    SendStatus::Queued
+  }
+  fn proto_recv(
+    &mut self,
+    buffer: &mut [u8],
+    messages: &mut Vec<(Self::Address, Message<Self::Address>)>,
+  ) {
+    self.socket.proto_recv(buffer, messages)
+  }
+  fn get_addr(&self) -> Self::Address {
+    self.socket.get_addr()
+  }
+  fn flush(&mut self) -> Option<SendStatus> {
+    if self.packets.is_empty() {
+      return None;
+    }
+    let status = self.send_packets();
+    self.packets.clear();
+    self.messages.clear();
+    Some(status)
+  }
+}
@@ -1,26 +1,33 @@
 #![allow(clippy::style)]
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread::JoinHandle;
 
 use bit_vec::BitVec;
 use primitive_types::U256;
 use priority_queue::PriorityQueue;
+use rand::rngs::StdRng;
 use rand::seq::IteratorRandom;
+use rand::SeedableRng;
 use sha3::Digest;
 
 use crate::api::{self, CtrInfo, RegInfo};
-use crate::api::{BlockInfo, FuncInfo, NodeRequest};
-use crate::bits::{serialized_block_size, ProtoSerialize};
+use crate::api::{BlockInfo, DryRunInfo, FuncInfo, NodeRequest};
+use crate::bits::{
+  deserialize_legacy_block, serialized_block_size, ProtoSerialize,
+};
 use crate::common::Name;
-use crate::config::{MineConfig, NodeConfig};
+use crate::config::{
+  ApiConfig, MineConfig, NetworkConfig, NodeConfig, NodeMode, StorageMode,
+  TipTiebreak,
+};
 use crate::constants;
 use crate::crypto::{self, Hashed, Keccakable};
 use crate::hvm::{self, *};
-use crate::net::{ProtoAddr, ProtoComm};
+use crate::net::{ProtoAddr, ProtoComm, SendStatus};
 use crate::util::*;
 
 use crate::events::{self, NodeEventEmittedInfo, NodeEventType};
@@ -89,7 +96,41 @@ impl Transaction {
   }
 
   pub fn to_statement(&self) -> Option<Statement> {
-    hvm::Statement::proto_deserialized(&BitVec::from_bytes(&self.data))
+    hvm::Statement::proto_deserialized(&BitVec::from_bytes(&self.data)).ok()
+  }
+
+  /// Like `to_statement`, but refuses non-zero bits after the statement.
+  /// This is only local policy, for what the node admits and mines: blocks
+  /// run whatever `to_statement` reads, as on older nodes.
+  pub fn to_exact_statement(&self) -> Option<Statement> {
+    let bits = BitVec::from_bytes(&self.data);
+    hvm::Statement::proto_deserialized_exact(&bits).ok()
+  }
+
+  /// Builds a transaction from a statement, serializing it in the protocol
+  /// format. This is the inverse of `to_statement()`.
+  pub fn from_statement(stmt: &Statement) -> Transaction {
+    Transaction::new(bitvec_to_bytes(&stmt.proto_serialized()))
+  }
+
+  /// Identifies the logical action of this transaction: deploying a given
+  /// function, constructor or name, by a given signer. Transactions sharing
+  /// a key replace each other on the mempool. Only signed deploys have a
+  /// key, as only their signer can produce another transaction for it;
+  /// unsigned ones could be evicted by anyone. `run` statements have none.
+  pub fn replacement_key(&self) -> Option<U256> {
+    let stmt = self.to_exact_statement()?;
+    let (tag, name, sign) = match &stmt {
+      Statement::Fun { name, sign, .. } => (0u8, name, sign),
+      Statement::Ctr { name, sign, .. } => (1, name, sign),
+      Statement::Reg { name, sign, .. } => (2, name, sign),
+      Statement::Run { .. } => return None,
+    };
+    let signer = sign.as_ref()?.signer_name(&hvm::hash_statement(&stmt))?;
+    let mut bytes = vec![tag];
+    bytes.extend_from_slice(&(**name).to_le_bytes());
+    bytes.extend_from_slice(&(*signer).to_le_bytes());
+    Some(hash_bytes(&bytes))
   }
 }
 
@@ -102,7 +143,7 @@ impl Deref for Transaction {
 
 impl From<&Statement> for Transaction {
   fn from(stmt: &Statement) -> Self {
-    Transaction::new(stmt.proto_serialized().to_bytes())
+    Transaction::from_statement(stmt)
   }
 }
 
@@ -133,6 +174,32 @@ impl Body {
     I: IntoIterator<Item = T>,
     T: Into<Transaction>,
   {
+    Body::fill_up_to(transactions, MAX_BODY_SIZE)
+  }
+
+  /// Fills block body with first transactions from iterator that fit in
+  /// `max_size` bytes. The size is capped to `MAX_BODY_SIZE`.
+  pub fn fill_up_to<I, T>(transactions: I, max_size: usize) -> Body
+  where
+    I: IntoIterator<Item = T>,
+    T: Into<Transaction>,
+  {
+    Body::fill_with_limits(transactions, max_size, MAX_BODY_TXS)
+  }
+
+  /// Like `fill_up_to`, but also stops after `max_txs` transactions. The
+  /// count is capped to `MAX_BODY_TXS`.
+  pub fn fill_with_limits<I, T>(
+    transactions: I,
+    max_size: usize,
+    max_txs: usize,
+  ) -> Body
+  where
+    I: IntoIterator<Item = T>,
+    T: Into<Transaction>,
+  {
+    let max_size = std::cmp::min(max_size, MAX_BODY_SIZE);
+    let max_txs = std::cmp::min(max_txs, MAX_BODY_TXS);
     let mut body_vec = vec![0];
     let mut tx_count = 0;
     for transaction in transactions.into_iter() {
@@ -141,7 +208,10 @@ impl Body {
       if tx_len == 0 {
         continue;
       }
-      if tx_count + 1 > 255 {
+      if tx_count + 1 > max_txs {
+        break;
+      }
+      if body_vec.len() + 2 + tx_len > max_size {
         break;
       }
       if add_transaction_to_body_vec(&mut body_vec, &transaction).is_err() {
@@ -175,7 +245,7 @@ impl Body {
         return Err(());
       }
       // Fails if tx count overflows 255, as we store it in a single byte.
-      if tx_count + 1 > 255 {
+      if tx_count + 1 > MAX_BODY_TXS {
         return Err(());
       }
       tx_count += 1;
@@ -198,6 +268,9 @@ pub type HashedBlock = Hashed<Block>;
 
 #[derive(Debug, Clone)]
 pub struct Block {
+  /// Format version, which decides how the block is hashed. See
+  /// `bits::BLOCK_FORMAT_VERSION`.
+  pub version: u64,
   /// 32 bytes hash of previous block.
   pub prev: U256,
   /// Block timestamp.
@@ -209,22 +282,209 @@ pub struct Block {
 }
 
 impl Block {
-  pub fn new(prev: U256, time: u128, meta: u128, body: Body) -> Block {
-    Block { prev, time, meta, body }
+  /// Builds a block in the format `version`. Which one is due depends on the
+  /// block's height; see `block_version_at`.
+  pub fn new(
+    version: u64,
+    prev: U256,
+    time: u128,
+    meta: u128,
+    body: Body,
+  ) -> Block {
+    Block { version, prev, time, meta, body }
+  }
+
+  /// Fields a version 1 block's hash commits to, in place of the body.
+  pub fn header(&self) -> BlockHeader {
+    BlockHeader {
+      version: self.version,
+      prev: self.prev,
+      time: self.time,
+      meta: self.meta,
+      transactions_root: transactions_root(&self.body),
+      body_hash: hash_bytes(&self.body.data),
+    }
+  }
+
+  /// Serializes the block in the protocol format, as raw bytes.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    bitvec_to_bytes(&self.proto_serialized())
+  }
+
+  /// Deserializes a block from its protocol format raw bytes.
+  pub fn from_bytes(bytes: &[u8]) -> Result<Block, String> {
+    Block::proto_deserialized(&bytes_to_bitvec(bytes))
+      .map_err(|err| format!("Invalid block serialization: {}", err))
+  }
+
+  /// Deserializes a block file. Files written before blocks had a format
+  /// version lack the version byte; those are read too, and reported as
+  /// legacy so they can be rewritten.
+  pub fn from_file_bytes(bytes: &[u8]) -> Result<(Block, bool), String> {
+    let bits = bytes_to_bitvec(bytes);
+    let mut index = 0;
+    let block = Block::proto_deserialize(&bits, &mut index, &mut HashMap::new());
+    match block {
+      Some(block) if index == bits.len() => Ok((block, false)),
+      _ => match deserialize_legacy_block(&bits) {
+        Some(block) => Ok((block, true)),
+        None => Block::from_bytes(bytes).map(|block| (block, false)),
+      },
+    }
+  }
+
+  /// Serializes the block as a hexadecimal string.
+  pub fn to_hex(&self) -> String {
+    hex::encode(self.to_bytes())
+  }
+
+  /// Deserializes a block from a hexadecimal string.
+  pub fn from_hex(txt: &str) -> Result<Block, String> {
+    let bytes = hex::decode(txt.trim())
+      .map_err(|err| format!("Invalid hexadecimal string: {}", err))?;
+    Block::from_bytes(&bytes)
+  }
+
+  /// Serializes the block as a base64 string.
+  pub fn to_base64(&self) -> String {
+    base64::encode(self.to_bytes())
+  }
+
+  /// Deserializes a block from a base64 string.
+  pub fn from_base64(txt: &str) -> Result<Block, String> {
+    let bytes = base64::decode(txt.trim())
+      .map_err(|err| format!("Invalid base64 string: {}", err))?;
+    Block::from_bytes(&bytes)
   }
 }
 
 impl crypto::Keccakable for Block {
+  fn keccak256(&self) -> crypto::Hash {
+    // Version 0 blocks hash their whole body
+    if self.version == 0 {
+      let mut bytes: Vec<u8> = Vec::new();
+      bytes.extend_from_slice(&u256_to_bytes(self.prev));
+      bytes.extend_from_slice(&u128_to_bytes(self.time));
+      bytes.extend_from_slice(&u128_to_bytes(self.meta));
+      bytes.extend_from_slice(&self.body.data);
+      return crypto::Hash::keccak256_from_bytes(&bytes);
+    }
+    self.header().keccak256()
+  }
+}
+
+/// What the hash of a version 1 block commits to. The body is replaced by
+/// its hash and by the Merkle root of its transactions, so light clients can
+/// check a block's proof of work, and that a transaction is included in it,
+/// without downloading the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+  pub version: u64,
+  pub prev: U256,
+  pub time: u128,
+  pub meta: u128,
+  pub transactions_root: U256,
+  pub body_hash: U256,
+}
+
+impl crypto::Keccakable for BlockHeader {
   fn keccak256(&self) -> crypto::Hash {
     let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(&self.version.to_le_bytes());
     bytes.extend_from_slice(&u256_to_bytes(self.prev));
     bytes.extend_from_slice(&u128_to_bytes(self.time));
     bytes.extend_from_slice(&u128_to_bytes(self.meta));
-    bytes.extend_from_slice(&self.body.data);
+    bytes.extend_from_slice(&u256_to_bytes(self.transactions_root));
+    bytes.extend_from_slice(&u256_to_bytes(self.body_hash));
     crypto::Hash::keccak256_from_bytes(&bytes)
   }
 }
 
+// Transactions root
+// -----------------
+
+// The transactions of a body are committed to by a Merkle tree over their
+// hashes. Leaves and inner nodes are hashed with distinct prefixes, so one
+// can't be passed off as the other. A node without a sibling moves up a level
+// unchanged. An empty body has a zero root.
+
+fn merkle_leaf(tx_hash: U256) -> U256 {
+  let mut bytes = vec![0];
+  bytes.extend_from_slice(&u256_to_bytes(tx_hash));
+  hash_bytes(&bytes)
+}
+
+fn merkle_node(left: U256, right: U256) -> U256 {
+  let mut bytes = vec![1];
+  bytes.extend_from_slice(&u256_to_bytes(left));
+  bytes.extend_from_slice(&u256_to_bytes(right));
+  hash_bytes(&bytes)
+}
+
+fn merkle_leaves(body: &Body) -> Vec<U256> {
+  let transactions = extract_transactions(body);
+  transactions.iter().map(|tx| merkle_leaf(tx.hash)).collect()
+}
+
+fn merkle_parents(level: &[U256]) -> Vec<U256> {
+  let pair_up = |pair: &[U256]| match pair {
+    [left, right] => merkle_node(*left, *right),
+    _ => pair[0],
+  };
+  level.chunks(2).map(pair_up).collect()
+}
+
+/// Merkle root of the transactions of a body.
+pub fn transactions_root(body: &Body) -> U256 {
+  let mut level = merkle_leaves(body);
+  if level.is_empty() {
+    return zero_hash();
+  }
+  while level.len() > 1 {
+    level = merkle_parents(&level);
+  }
+  level[0]
+}
+
+/// Sibling hashes met going from the `index`-th transaction of a body up to
+/// the transactions root, each flagged when it is the left one. `None` if the
+/// body doesn't have that many transactions.
+pub fn transaction_path(
+  body: &Body,
+  index: usize,
+) -> Option<Vec<(U256, bool)>> {
+  let mut level = merkle_leaves(body);
+  if index >= level.len() {
+    return None;
+  }
+  let mut index = index;
+  let mut path = vec![];
+  while level.len() > 1 {
+    let sibling = index ^ 1;
+    if sibling < level.len() {
+      path.push((level[sibling], sibling < index));
+    }
+    level = merkle_parents(&level);
+    index /= 2;
+  }
+  Some(path)
+}
+
+/// Root reached from a transaction hash by following a `transaction_path`.
+pub fn transactions_root_from_path(
+  tx_hash: U256,
+  path: &[(U256, bool)],
+) -> U256 {
+  let mut hash = merkle_leaf(tx_hash);
+  for (sibling, is_left) in path {
+    hash = match is_left {
+      true => merkle_node(*sibling, hash),
+      false => merkle_node(hash, *sibling),
+    };
+  }
+  hash
+}
+
 // Node
 // ====
 
@@ -248,17 +508,26 @@ pub enum InclusionState {
 #[rustfmt::skip]
 pub struct Node<C: ProtoComm> {
   pub data_path    : PathBuf,                           // path where files are saved
-  pub network_id   : u32,                               // Network ID / magic number
+  pub network_id   : u32,                               // Network ID
+  pub magic        : u32,                               // magic number of our messages; see `network_magic`
+  pub name         : Option<String>,                    // friendly name for logs; informational only
   pub comm         : C,                                 // UDP socket
   pub addr         : C::Address,                        // UDP port
   pub runtime      : Runtime,                           // Kindelia's runtime
+  pub state_floor  : u64,                               // tick of the imported state, if any; the runtime can't roll back before it
+  pub state_block  : Option<U256>,                      // hash of the block the imported state was taken at, if any
   pub query_recv   : mpsc::Receiver<NodeRequest<C>>,    // Receives an API request
-  pub pool         : PriorityQueue<Transaction, u64>,   // transactions to be mined
+  pub pool         : Mempool,                           // transactions to be mined
   pub peers        : PeersStore<C::Address>,            // peers store and state control
   pub genesis_hash : U256,
+  pub base       : U256,                           // oldest block of the chain held; the genesis block, unless started on a truncated chain
   pub tip        : U256,                           // current tip
+  pub tip_time   : u128,                           // wall-clock time of the last tip update
+  pub started_at : u128,                           // wall-clock time the node was created
+  pub heard_tip  : bool,                           // whether any peer gossiped its tip yet
   pub block      : U256Map<HashedBlock>,           // block hash -> block
   pub pending    : U256Map<HashedBlock>,           // block hash -> downloaded block, waiting for ancestors
+  pub pending_at : U256Map<u128>,                  // block hash -> when it was put on .pending
   pub ancestor   : U256Map<U256>,                  // block hash -> hash of its most recent missing ancestor (shortcut jump table)
   pub wait_list  : U256Map<Vec<U256>>,             // block hash -> hashes of blocks that are waiting for this one
   pub children   : U256Map<Vec<U256>>,             // block hash -> hashes of this block's children
@@ -266,10 +535,378 @@ pub struct Node<C: ProtoComm> {
   pub target     : U256Map<U256>,                  // block hash -> this block's target
   pub height     : U256Map<u128>,                  // block hash -> cached height
   pub results    : U256Map<Vec<StatementResult>>,  // block hash -> results of the statements in this block
+  pub ticks      : U256Map<u64>,                   // block hash -> runtime tick after computing this block
+  pub lost_ticks : U256Map<u64>,                   // block hash -> computed blocks up to this one that didn't advance the tick
+  pub tx_index   : U256Map<U256>,                  // tx hash -> hash of the main chain block that included it (~64 bytes per tx)
 
   #[cfg(feature = "events")]
   pub event_emitter : mpsc::Sender<NodeEventEmittedInfo>,
   pub miner_comm    : Option<MinerCommunication>,
+  pub net_config    : NetworkConfig,                    // network settings
+  pub mine_config   : MineConfig,                       // mining settings
+  pub api_config    : ApiConfig,                        // API request handling settings
+  pub mining_enabled: bool,                            // whether the node asks the miner for new blocks
+  pub mine_template : Vec<Transaction>,                 // transactions included on every mined block
+  pub mode          : NodeMode,                         // full node or read-only follower
+  pub gossiped_tip  : U256,                             // tip sent on the last gossip rounds
+  pub tip_sent_at   : HashMap<C::Address, u128>,        // peer -> when `gossiped_tip` was sent to it
+  pub block_requests: U256Map<BlockRequest<C::Address>>, // block hash -> outstanding request for it
+  pub congested_until: u128,                           // gossip is paused until this time, after failed sends
+  pub congestion_backoff: u128,                        // current gossip pause, in ms; 0 when sends go out
+  pub message_counts: api::MessageCounts,              // messages handled so far, by type
+  pub message_rate  : f64,                              // messages handled per second, as of the last sample
+  pub message_sample: (u128, u64),                      // time and message total of the last rate sample
+  pub recent_blocks : VecDeque<(U256, BlockInfo)>,      // last blocks of the main chain, oldest first
+  pub functions     : Option<Vec<(String, Name)>>,      // deployed function names, sorted; rebuilt when none
+  pub peer_tips     : HashMap<C::Address, (U256, u128)>, // peer -> last tip it gossiped, and when
+  pub split_since   : Option<u128>,                     // since when peers report competing tips, if they do
+  pub split_warned  : bool,                             // whether the current chain split was logged
+  pub block_serving : BlockServing<C::Address>,         // budget for answering block requests
+  pub recv_buffer   : Vec<u8>,                          // scratch space for incoming packets, allocated once
+  pub recv_messages : Vec<(C::Address, Message<C::Address>)>, // incoming messages; kept to reuse its allocation
+  pub block_timings : VecDeque<api::BlockTiming>,       // computation times of the last computed blocks, oldest first
+  pub unsaved_blocks: BTreeMap<u128, U256>,             // height -> main chain block whose file couldn't be written
+  pub stale_warned  : bool,                             // whether the current stall of the tip was logged
+  pub max_load_blocks: Option<u64>,                     // how many block files are loaded at most; see `NodeConfig::max_load_blocks`
+  pub block_v1_height: u128,                            // height from which blocks must be version 1; see `BLOCK_V1_HEIGHT`
+  pub storage       : StorageMode,                      // whether blocks deeper than `prune_depth` are dropped
+  pub prune_depth   : u64,                              // how many blocks behind the tip a pruned node keeps
+  pub paused_since  : Option<u128>,                     // since when block processing is paused for a backup, if it is
+  pub block_sources : U256Map<C::Address>,              // block hash -> peer that first sent it, for the last received blocks
+  pub block_source_order: VecDeque<U256>,              // hashes on `block_sources`, oldest first
+  pub results_subs  : Vec<(Option<Name>, api::ResultsSend)>, // streams of computed statement results, with their name filters
+}
+
+// Node snapshots
+// --------------
+
+// Version of the `NodeSnapshot` format. Snapshots are only restored by builds
+// reading the same version, so it must be bumped whenever a field is added,
+// removed or changes meaning, or the way blocks or heaps are encoded changes.
+pub const NODE_SNAPSHOT_VERSION: u64 = 3;
+
+/// Complete copy of a node's chain state: every known block, the indices
+/// built over them, and the runtime. Unlike the block files, restoring it
+/// doesn't recompute any block. See `Node::snapshot` and `Node::restore`.
+#[rustfmt::skip]
+#[derive(Clone)]
+pub struct NodeSnapshot {
+  pub version      : u64,
+  pub genesis_hash : U256,
+  pub base         : U256,
+  pub tip          : U256,
+  pub state_floor  : u64,
+  pub block        : U256Map<HashedBlock>,
+  pub pending      : U256Map<HashedBlock>,
+  pub pending_at   : U256Map<u128>,
+  pub ancestor     : U256Map<U256>,
+  pub wait_list    : U256Map<Vec<U256>>,
+  pub children     : U256Map<Vec<U256>>,
+  pub work         : U256Map<U256>,
+  pub target       : U256Map<U256>,
+  pub height       : U256Map<u128>,
+  pub results      : U256Map<Vec<StatementResult>>,
+  pub ticks        : U256Map<u64>,
+  pub lost_ticks   : U256Map<u64>,
+  pub tx_index     : U256Map<U256>,
+  pub runtime      : RuntimeImage,
+}
+
+/// How `NodeSnapshot::save` writes everything but the runtime. Blocks are in
+/// their protocol format, as hexadecimal strings.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ChainSnapshotFile {
+  version: u64,
+  genesis_hash: api::Hash,
+  base: api::Hash,
+  tip: api::Hash,
+  state_floor: u64,
+  block: Vec<String>,
+  pending: Vec<String>,
+  pending_at: Vec<(api::Hash, u128)>,
+  ancestor: Vec<(api::Hash, api::Hash)>,
+  wait_list: Vec<(api::Hash, Vec<api::Hash>)>,
+  children: Vec<(api::Hash, Vec<api::Hash>)>,
+  work: Vec<(api::Hash, api::Hash)>,
+  target: Vec<(api::Hash, api::Hash)>,
+  height: Vec<(api::Hash, u128)>,
+  results: Vec<(api::Hash, Vec<StatementResult>)>,
+  ticks: Vec<(api::Hash, u64)>,
+  lost_ticks: Vec<(api::Hash, u64)>,
+  tx_index: Vec<(api::Hash, api::Hash)>,
+}
+
+fn to_hash_pairs<T, R>(
+  map: &U256Map<T>,
+  f: impl Fn(&T) -> R,
+) -> Vec<(api::Hash, R)> {
+  map.iter().map(|(k, v)| (api::Hash::from(*k), f(v))).collect()
+}
+
+fn from_hash_pairs<T, R>(
+  pairs: Vec<(api::Hash, R)>,
+  f: impl Fn(R) -> T,
+) -> U256Map<T> {
+  let mut map = u256map_new();
+  for (k, v) in pairs {
+    map.insert(U256::from(k), f(v));
+  }
+  map
+}
+
+fn to_hashes(hashes: &[U256]) -> Vec<api::Hash> {
+  hashes.iter().map(|h| api::Hash::from(*h)).collect()
+}
+
+fn from_hashes(hashes: Vec<api::Hash>) -> Vec<U256> {
+  hashes.into_iter().map(U256::from).collect()
+}
+
+fn to_block_hexes(blocks: &U256Map<HashedBlock>) -> Vec<String> {
+  blocks.values().map(|block| block.to_hex()).collect()
+}
+
+fn from_block_hexes(hexes: &[String]) -> Result<U256Map<HashedBlock>, String> {
+  let mut blocks = u256map_new();
+  for hex in hexes {
+    let block = Block::from_hex(hex)?.hashed();
+    blocks.insert(U256::from(block.get_hash()), block);
+  }
+  Ok(blocks)
+}
+
+impl NodeSnapshot {
+  /// Writes the snapshot to a new directory, as a `chain.json` file with the
+  /// blocks and indices, and a `heaps` directory with the runtime.
+  pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+    std::fs::create_dir(path).map_err(|err| {
+      format!("Could not create '{}': {}", path.display(), err)
+    })?;
+    let file = ChainSnapshotFile {
+      version: self.version,
+      genesis_hash: self.genesis_hash.into(),
+      base: self.base.into(),
+      tip: self.tip.into(),
+      state_floor: self.state_floor,
+      block: to_block_hexes(&self.block),
+      pending: to_block_hexes(&self.pending),
+      pending_at: to_hash_pairs(&self.pending_at, |at| *at),
+      ancestor: to_hash_pairs(&self.ancestor, |h| api::Hash::from(*h)),
+      wait_list: to_hash_pairs(&self.wait_list, |hs| to_hashes(hs)),
+      children: to_hash_pairs(&self.children, |hs| to_hashes(hs)),
+      work: to_hash_pairs(&self.work, |w| api::Hash::from(*w)),
+      target: to_hash_pairs(&self.target, |t| api::Hash::from(*t)),
+      height: to_hash_pairs(&self.height, |h| *h),
+      results: to_hash_pairs(&self.results, |r| r.clone()),
+      ticks: to_hash_pairs(&self.ticks, |t| *t),
+      lost_ticks: to_hash_pairs(&self.lost_ticks, |l| *l),
+      tx_index: to_hash_pairs(&self.tx_index, |h| api::Hash::from(*h)),
+    };
+    let json = serde_json::to_string(&file).map_err(|err| err.to_string())?;
+    let chain_path = path.join("chain.json");
+    std::fs::write(&chain_path, json).map_err(|err| {
+      format!("Could not write '{}': {}", chain_path.display(), err)
+    })?;
+    let heaps_path = path.join("heaps");
+    self.runtime.save(&heaps_path).map_err(|err| {
+      format!("Could not write '{}': {}", heaps_path.display(), err)
+    })
+  }
+
+  /// Reads a snapshot written by `save`. Fails on snapshots written in
+  /// another format version.
+  pub fn load(path: &PathBuf) -> Result<NodeSnapshot, String> {
+    let chain_path = path.join("chain.json");
+    let json = std::fs::read_to_string(&chain_path).map_err(|err| {
+      format!("Could not read '{}': {}", chain_path.display(), err)
+    })?;
+    let file: ChainSnapshotFile = serde_json::from_str(&json)
+      .map_err(|err| format!("Invalid node snapshot: {}", err))?;
+    check_snapshot_version(file.version)?;
+    let heaps_path = path.join("heaps");
+    let runtime = RuntimeImage::load(&heaps_path).map_err(|err| {
+      format!("Could not read '{}': {}", heaps_path.display(), err)
+    })?;
+    Ok(NodeSnapshot {
+      version: file.version,
+      genesis_hash: file.genesis_hash.into(),
+      base: file.base.into(),
+      tip: file.tip.into(),
+      state_floor: file.state_floor,
+      block: from_block_hexes(&file.block)?,
+      pending: from_block_hexes(&file.pending)?,
+      pending_at: from_hash_pairs(file.pending_at, |at| at),
+      ancestor: from_hash_pairs(file.ancestor, U256::from),
+      wait_list: from_hash_pairs(file.wait_list, from_hashes),
+      children: from_hash_pairs(file.children, from_hashes),
+      work: from_hash_pairs(file.work, U256::from),
+      target: from_hash_pairs(file.target, U256::from),
+      height: from_hash_pairs(file.height, |h| h),
+      results: from_hash_pairs(file.results, |r| r),
+      ticks: from_hash_pairs(file.ticks, |t| t),
+      lost_ticks: from_hash_pairs(file.lost_ticks, |l| l),
+      tx_index: from_hash_pairs(file.tx_index, U256::from),
+      runtime,
+    })
+  }
+}
+
+fn check_snapshot_version(version: u64) -> Result<(), String> {
+  if version != NODE_SNAPSHOT_VERSION {
+    return Err(format!(
+      "Node snapshot has format version {}, but this build reads version {}.",
+      version, NODE_SNAPSHOT_VERSION
+    ));
+  }
+  Ok(())
+}
+
+// Block requests
+// --------------
+
+/// A `GiveMeThatBlock` request waiting for its answer.
+#[derive(Debug, Clone)]
+pub struct BlockRequest<A: ProtoAddr> {
+  pub peer: A,
+  pub sent_at: u128,
+  pub attempts: u32,
+}
+
+// Block serving
+// -------------
+
+/// Limits how much the node spends answering `GiveMeThatBlock` requests,
+/// which walk the chain and serialize many blocks each. Counts are kept over
+/// one second windows.
+#[derive(Debug, Clone)]
+pub struct BlockServing<A: ProtoAddr> {
+  window_start: u128,
+  served_bytes: usize,
+  requests: HashMap<A, u32>, // peer -> requests answered on this window
+}
+
+impl<A: ProtoAddr> Default for BlockServing<A> {
+  fn default() -> Self {
+    BlockServing { window_start: 0, served_bytes: 0, requests: HashMap::new() }
+  }
+}
+
+impl<A: ProtoAddr> BlockServing<A> {
+  /// Whether a request from `addr` at time `now` may be answered, given at
+  /// most `per_peer` answers per peer and `max_bytes` bytes per second. If
+  /// so, counts it.
+  pub fn allow(
+    &mut self,
+    addr: A,
+    now: u128,
+    per_peer: u32,
+    max_bytes: usize,
+  ) -> bool {
+    if now.saturating_sub(self.window_start) >= 1000 {
+      self.window_start = now;
+      self.served_bytes = 0;
+      self.requests.clear();
+    }
+    if self.served_bytes >= max_bytes {
+      return false;
+    }
+    let count = self.requests.entry(addr).or_insert(0);
+    if *count >= per_peer {
+      return false;
+    }
+    *count += 1;
+    true
+  }
+
+  /// Accounts for `bytes` served on the current window.
+  pub fn record(&mut self, bytes: usize) {
+    self.served_bytes = self.served_bytes.saturating_add(bytes);
+  }
+}
+
+// Mempool
+// -------
+
+/// Transactions waiting to be mined, ordered by priority. Transactions
+/// performing the same logical action (see `Transaction::replacement_key`)
+/// replace each other; only the one with the highest priority is kept.
+#[derive(Default)]
+pub struct Mempool {
+  queue: PriorityQueue<Transaction, u64>,
+  by_key: U256Map<Transaction>, // replacement key -> transaction holding it
+  keys: U256Map<U256>, // transaction hash -> its replacement key, found on admission
+}
+
+impl Mempool {
+  pub fn new() -> Self {
+    Mempool::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.queue.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.queue.is_empty()
+  }
+
+  pub fn contains(&self, tx: &Transaction) -> bool {
+    self.queue.get(tx).is_some()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&Transaction, &u64)> {
+    self.queue.iter()
+  }
+
+  /// Transactions from highest to lowest priority. Ties are broken by hash,
+  /// so the order only depends on which transactions are on the mempool, not
+  /// on when they arrived.
+  pub fn sorted(&self) -> Vec<&Transaction> {
+    let mut txs: Vec<_> = self.queue.iter().collect();
+    txs.sort_by(|(a, a_prio), (b, b_prio)| {
+      b_prio.cmp(a_prio).then_with(|| a.hash.cmp(&b.hash))
+    });
+    txs.into_iter().map(|(tx, _)| tx).collect()
+  }
+
+  /// Adds a transaction with the given `priority`, evicting the one it
+  /// replaces, if any. A replacement must have a strictly higher priority
+  /// than the transaction it evicts.
+  pub fn insert(
+    &mut self,
+    tx: Transaction,
+    priority: u64,
+  ) -> Result<(), String> {
+    if self.contains(&tx) {
+      return Err("Transaction is already on the mempool.".to_string());
+    }
+    if let Some(key) = tx.replacement_key() {
+      if let Some(old) = self.by_key.get(&key) {
+        if self.queue.get_priority(old) >= Some(&priority) {
+          let msg = "A transaction for the same action, with higher priority, \
+                     is already on the mempool.";
+          return Err(msg.to_string());
+        }
+        let old = old.clone();
+        self.keys.remove(&old.hash);
+        self.queue.remove(&old);
+      }
+      self.by_key.insert(key, tx.clone());
+      self.keys.insert(tx.hash, key);
+    }
+    self.queue.push(tx, priority);
+    Ok(())
+  }
+
+  pub fn remove(&mut self, tx: &Transaction) {
+    if self.queue.remove(tx).is_some() {
+      if let Some(key) = self.keys.remove(&tx.hash) {
+        if self.by_key.get(&key) == Some(tx) {
+          self.by_key.remove(&key);
+        }
+      }
+    }
+  }
 }
 
 // Peers
@@ -284,25 +921,115 @@ pub struct Peer<A: ProtoAddr> {
 pub struct PeersStore<A: ProtoAddr> {
   seen: HashMap<A, Peer<A>>,
   active: HashMap<A, Peer<A>>,
+  inbound: HashSet<A>, // peers that contacted us first
+  outbound: HashSet<A>, // peers we contacted first
+  pinned: HashSet<A>, // trusted peers, never evicted nor timed out
+  max_peers: usize,
+  max_inbound: usize,
+  timeout: u128, // how many milliseconds without notice until we forget a peer
+  rng: Option<StdRng>, // seeded RNG for reproducible picks; thread RNG if none
 }
 
 impl<A: ProtoAddr> PeersStore<A> {
   pub fn new() -> PeersStore<A> {
-    PeersStore { seen: HashMap::new(), active: HashMap::new() }
+    PeersStore::with_limits(MAX_PEERS, MAX_INBOUND_PEERS)
+  }
+
+  /// Creates a store that keeps at most `max_peers` peers, of which at most
+  /// `max_inbound` may be peers that contacted us first.
+  pub fn with_limits(max_peers: usize, max_inbound: usize) -> PeersStore<A> {
+    PeersStore {
+      seen: HashMap::new(),
+      active: HashMap::new(),
+      inbound: HashSet::new(),
+      outbound: HashSet::new(),
+      pinned: HashSet::new(),
+      max_peers,
+      max_inbound: std::cmp::min(max_inbound, max_peers),
+      timeout: PEER_TIMEOUT,
+      rng: None,
+    }
+  }
+
+  /// Makes random peer picks reproducible, deriving them from `seed`.
+  pub fn set_seed(&mut self, seed: u64) {
+    self.rng = Some(StdRng::seed_from_u64(seed));
+  }
+
+  pub fn set_timeout(&mut self, timeout: u128) {
+    self.timeout = timeout;
+  }
+
+  pub fn get_timeout(&self) -> u128 {
+    self.timeout
+  }
+
+  /// Returns the active peers we haven't heard from for `quiet_for` ms.
+  pub fn get_quiet(&self, quiet_for: u128) -> Vec<Peer<A>> {
+    let limit = get_time().saturating_sub(quiet_for);
+    self.active.values().filter(|p| p.seen_at < limit).cloned().collect()
+  }
+
+  /// Pins a peer: it is kept on the store and active no matter how long we
+  /// go without hearing from it.
+  pub fn pin(&mut self, addr: A) {
+    self.pinned.insert(addr);
+  }
+
+  pub fn is_pinned(&self, addr: &A) -> bool {
+    self.pinned.contains(addr)
+  }
+
+  /// Whether `peer` was last seen more than `timeout` ms before `now`.
+  /// Written as an addition so that a clock smaller than the timeout (e.g.
+  /// right after startup on a test clock) can't underflow.
+  fn is_timed_out(&self, peer: &Peer<A>, now: u128) -> bool {
+    !self.is_pinned(&peer.address)
+      && peer.seen_at.saturating_add(self.timeout) < now
   }
 
   /// This function checks and puts a peer as active on `PeerStore`.
   pub fn activate(&mut self, addr: &A, peer: Peer<A>) {
-    let now = get_time();
-    // Only activate if its `seen_at` is newer than `now - TIMEOUT`
-    if peer.seen_at >= now - PEER_TIMEOUT {
+    // Only activate if it hasn't timed out yet
+    if !self.is_timed_out(&peer, get_time()) {
       self.active.insert(*addr, peer);
     }
   }
 
+  /// Forgets the unpinned peer with the oldest `seen_at`, only considering
+  /// inbound peers if `inbound_only` is set.
+  fn evict_stalest(&mut self, inbound_only: bool) {
+    let stalest = self
+      .seen
+      .values()
+      .filter(|peer| !self.pinned.contains(&peer.address))
+      .filter(|peer| !inbound_only || self.inbound.contains(&peer.address))
+      .min_by_key(|peer| peer.seen_at)
+      .map(|peer| peer.address);
+    if let Some(addr) = stalest {
+      self.seen.remove(&addr);
+      self.active.remove(&addr);
+      self.inbound.remove(&addr);
+      self.outbound.remove(&addr);
+    }
+  }
+
+  /// Notes that we sent a message to `addrs`. Known peers that didn't
+  /// contact us first count as outbound from then on.
+  pub fn mark_contacted(&mut self, addrs: &[A]) {
+    for addr in addrs {
+      if self.seen.contains_key(addr) && !self.inbound.contains(addr) {
+        self.outbound.insert(*addr);
+      }
+    }
+  }
+
+  /// Registers a peer. `inbound` tells if the peer contacted us, rather than
+  /// being an initial peer or shared with us by someone else.
   pub fn see_peer(
     &mut self,
     peer: Peer<A>,
+    inbound: bool,
     #[cfg(feature = "events")] event_emitter: mpsc::Sender<
       NodeEventEmittedInfo,
     >,
@@ -311,7 +1038,22 @@ impl<A: ProtoAddr> PeersStore<A> {
     match self.seen.get(&addr) {
       // New peer, not seen before
       None => {
+        // Makes room for the new peer, evicting the stalest ones
+        if inbound && self.inbound.len() >= self.max_inbound {
+          self.evict_stalest(true);
+        }
+        if self.seen.len() >= self.max_peers {
+          self.evict_stalest(false);
+        }
+        // Pinned peers are always added, even past the limits
+        let no_room = self.max_peers == 0 || (inbound && self.max_inbound == 0);
+        if no_room && !self.is_pinned(&addr) {
+          return;
+        }
         self.seen.insert(addr, peer);
+        if inbound {
+          self.inbound.insert(addr);
+        }
         emit_event!(
           event_emitter,
           NodeEventType::see_peer_not_seen(&peer),
@@ -355,10 +1097,25 @@ impl<A: ProtoAddr> PeersStore<A> {
     #[cfg(feature = "events")] event_emitter: mpsc::Sender<
       NodeEventEmittedInfo,
     >,
+  ) {
+    self.timeout_at(
+      get_time(),
+      #[cfg(feature = "events")]
+      event_emitter,
+    );
+  }
+
+  /// Inactivates the peers timed out at time `now`.
+  pub fn timeout_at(
+    &mut self,
+    now: u128,
+    #[cfg(feature = "events")] event_emitter: mpsc::Sender<
+      NodeEventEmittedInfo,
+    >,
   ) {
     let mut forget = Vec::new();
     for (_, peer) in &self.active {
-      if peer.seen_at < get_time() - PEER_TIMEOUT {
+      if self.is_timed_out(peer, now) {
         emit_event!(
           event_emitter,
           NodeEventType::timeout(&peer),
@@ -385,11 +1142,32 @@ impl<A: ProtoAddr> PeersStore<A> {
     self.seen.values().cloned().collect()
   }
 
-  pub fn get_random_active(&self, amount: u128) -> Vec<Peer<A>> {
+  /// Counts the peers on this store. Addresses only shared with us by other
+  /// peers are neither inbound nor outbound until we hear from or contact
+  /// them.
+  pub fn get_counts(&self) -> api::PeerCounts {
+    api::PeerCounts {
+      total: self.seen.len(),
+      active: self.active.len(),
+      inbound: self.inbound.len(),
+      outbound: self.outbound.len(),
+    }
+  }
+
+  pub fn get_random_active(&mut self, amount: u128) -> Vec<Peer<A>> {
     let amount = amount as usize;
-    let mut rng = rand::thread_rng();
-    let peers = self.active.values().cloned().choose_multiple(&mut rng, amount);
-    peers
+    match &mut self.rng {
+      Some(rng) => {
+        // Map iteration order varies between runs, so it is fixed first
+        let mut peers: Vec<_> = self.active.values().cloned().collect();
+        peers.sort_by_cached_key(|peer| peer.address.proto_serialized());
+        peers.into_iter().choose_multiple(rng, amount)
+      }
+      None => {
+        let mut rng = rand::thread_rng();
+        self.active.values().cloned().choose_multiple(&mut rng, amount)
+      }
+    }
   }
 }
 
@@ -398,7 +1176,7 @@ impl<A: ProtoAddr> PeersStore<A> {
 
 #[derive(Debug, Clone)]
 pub enum MinerMessage {
-  Request { prev: U256, body: Body, targ: U256 },
+  Request { version: u64, prev: U256, body: Body, targ: U256 },
   Answer { block: HashedBlock },
   Stop,
 }
@@ -428,6 +1206,12 @@ pub enum Message<A: ProtoAddr> {
     magic: u32,
     tx: Transaction,
   },
+  Ping {
+    magic: u32,
+  },
+  Pong {
+    magic: u32,
+  },
 }
 
 // Constants
@@ -439,6 +1223,12 @@ pub const _HASH_SIZE: usize = 32;
 // Size of a block's body, in bytes
 pub const MAX_BODY_SIZE: usize = 1280;
 
+// Maximum number of transactions in a block's body; the count takes one byte
+pub const MAX_BODY_TXS: usize = 255;
+
+// How many blocks behind the tip the parent of a submitted block may be
+pub const MAX_WORK_AGE: u128 = 3;
+
 // Max size of a big UDP packet, in bytes
 pub const MAX_UDP_SIZE_SLOW: usize = 8000;
 
@@ -480,9 +1270,109 @@ pub const TIME_PER_PERIOD: u128 = TIME_PER_BLOCK * BLOCKS_PER_PERIOD;
 // Initial difficulty, in expected hashes per block
 pub const INITIAL_DIFFICULTY: u128 = 256;
 
+// ID of the public network
+pub const PUBLIC_NETWORK_ID: u32 = 0xCAFE0004;
+
+// Height from which blocks must be in format version 1, unless the network
+// sets its own with `NetworkConfig::block_v1_height`. No network schedules it
+// yet: the public one takes a release fixing the height once enough nodes
+// read version 1 blocks, and others may hold chains from earlier builds
+pub const BLOCK_V1_HEIGHT: u128 = u128::MAX;
+
+// After how many expected block intervals without a new tip do we warn?
+pub const STALE_TIP_FACTOR: u128 = 10;
+
+// How many blocks are averaged when estimating the network hash rate?
+pub const HASH_RATE_WINDOW: usize = 60;
+
 // How many milliseconds without notice until we forget a peer?
 pub const PEER_TIMEOUT: u128 = 10 * 1000;
 
+// Delay between checks for quiet peers to ping, in ms
+pub const KEEPALIVE_DELAY: u128 = 1000;
+
+// How many milliseconds to wait for a requested block before asking again
+pub const BLOCK_REQUEST_TIMEOUT: u128 = 1000;
+
+// How long a block waits for its missing ancestors before it's dropped, in ms
+pub const PENDING_BLOCK_TIMEOUT: u128 = 10 * 60 * 1000;
+
+// How many times a block is requested from the same peer before trying another
+pub const BLOCK_REQUEST_RETRIES: u32 = 3;
+
+// How many times, in total, a block is requested before giving up on it
+pub const BLOCK_REQUEST_MAX_ATTEMPTS: u32 = 9;
+
+// Bounds of the pause on gossip after the socket reports congestion, in ms.
+// It doubles while the congestion lasts.
+pub const CONGESTION_BACKOFF_MIN: u128 = 100;
+pub const CONGESTION_BACKOFF_MAX: u128 = 5000;
+
+// How long a node that heard no blocks from peers waits before mining on its
+// own chain, in ms
+pub const SYNC_GRACE_PERIOD: u128 = 30 * 1000;
+
+// How long a node waits, at most, for the missing ancestors of the blocks it
+// heard of before mining again, in ms. Bounds syncs that never finish, like
+// peers that keep sending orphans
+pub const SYNC_TIMEOUT: u128 = 5 * 60 * 1000;
+
+// How many blocks a peer's tip can be above ours while the node counts as
+// synced, so that tips racing each other don't stop mining
+pub const SYNC_HEIGHT_MARGIN: u128 = 2;
+
+// How long a tip gossiped by a peer counts towards chain split detection and
+// syncing, in ms
+pub const PEER_TIP_WINDOW: u128 = 60 * 1000;
+
+// How long peers must keep reporting competing tips at the same height until
+// a chain split is reported, in ms
+pub const CHAIN_SPLIT_DELAY: u128 = 30 * 1000;
+
+// File of an exported state holding the hash of the block it was taken at
+pub const STATE_BLOCK_FILE: &str = "_block_";
+
+// How many blocks are loaded from disk between progress reports
+pub const LOAD_BLOCKS_LOG_INTERVAL: usize = 1000;
+
+// How many blocks behind the tip pruned nodes keep: about two days of blocks,
+// twice the age of the runtime's oldest snapshot. The base never passes that
+// snapshot, and forks below it are refused, so that rolling the runtime back
+// never needs a pruned block
+pub const PRUNE_DEPTH: u64 = 2 * 24 * 60 * 60 * 1000 / TIME_PER_BLOCK as u64;
+
+// How many milliseconds until an unchanged tip is gossiped again to the same
+// peer, in case the previous packet was lost
+pub const TIP_RESEND_INTERVAL: u128 = 5 * 1000;
+
+// Longest interval between gossips of a stable tip to the same peer, in ms.
+// Kept below `PEER_TIP_WINDOW`, so that peers don't forget our tip
+pub const TIP_KEEPALIVE_INTERVAL: u128 = 30 * 1000;
+
+// How long block processing can stay paused for a backup, in ms. Blocks
+// arriving meanwhile are dropped and must be fetched again, so the node falls
+// behind the network; past this, it resumes on its own
+pub const MAX_PAUSE_TIME: u128 = 10 * 60 * 1000;
+
+// How many block requests from a single peer are answered per second
+pub const BLOCK_SERVE_PEER_RATE: u32 = 10;
+
+// How many bytes of blocks are sent per second, at most, answering requests
+pub const BLOCK_SERVE_BYTES_RATE: usize = 1 << 20;
+
+// Minimum amount of blocks sent when a peer asks for one, so sync progresses
+pub const SEND_BLOCK_ANCESTORS_MIN: usize = 4;
+
+// How many times the configured amount of blocks is sent, at most, to peers
+// that are far behind
+pub const SEND_BLOCK_ANCESTORS_BOOST: usize = 4;
+
+// How many peers we keep at most?
+pub const MAX_PEERS: usize = 1024;
+
+// How many of the kept peers may be ones that contacted us first?
+pub const MAX_INBOUND_PEERS: usize = 512;
+
 // How many peers we need to keep minimum?
 pub const _PEER_COUNT_MINIMUM: u128 = 256;
 
@@ -498,6 +1388,51 @@ pub const HANDLE_MESSAGE_DELAY: u128 = 20;
 // Delay between handling of API requests, in ms
 pub const HANDLE_REQUEST_DELAY: u128 = 20;
 
+// Default capacity of the API request queue
+pub const REQUEST_QUEUE_SIZE: usize = 64;
+
+// Default maximum size, in nodes, of terms read for API responses
+pub const MAX_TERM_SIZE: usize = 1 << 16;
+
+// Default number of recent blocks cached for API queries
+pub const RECENT_BLOCKS: usize = 64;
+
+// How many of the last received blocks remember the peer that sent them
+pub const BLOCK_SOURCES_KEPT: usize = 4096;
+
+// How many of the last computed blocks are kept, to report the slowest ones
+pub const BLOCK_TIMINGS_KEPT: usize = 256;
+
+// How many of the slowest recent blocks are reported on the stats
+pub const SLOWEST_BLOCKS_REPORTED: usize = 5;
+
+// Blocks taking longer than this to compute are logged, in ms
+pub const SLOW_BLOCK_TIME: u128 = 1000;
+
+// Maximum number of state changes reported by a dry run
+pub const MAX_STATE_DIFFS: usize = 256;
+
+// Maximum number of function states read by a single API query
+pub const MAX_STATES_PER_QUERY: usize = 64;
+
+// How many computed results a subscriber may leave unread before it's dropped
+pub const RESULTS_STREAM_BUFFER: usize = 4096;
+
+// Maximum number of subscribers to computed results at once
+pub const MAX_RESULTS_SUBSCRIBERS: usize = 64;
+
+// Maximum number of retarget periods listed by a difficulty history query
+pub const MAX_DIFFICULTY_HISTORY: usize = 1024;
+
+// Default maximum of API requests handled per run of the request task
+pub const REQUESTS_PER_RUN: usize = 32;
+
+// Duration of one iteration of the main loop, in ms
+pub const TICK_DURATION: u64 = 1;
+
+// Ticks taking longer than this, in ms, are reported as the node falling behind
+pub const TICK_OVERRUN_WARNING: u128 = 100;
+
 // This limits how many messages we accept at once
 pub const _HANDLE_MESSAGE_LIMIT: u128 = 5;
 
@@ -528,12 +1463,29 @@ pub fn target_to_difficulty(target: U256) -> U256 {
   let p256 =
     "0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF";
   let p256 = U256::from(p256);
-  return p256 / (p256 - target);
+  // The highest possible target has no room left; saturates its difficulty
+  return p256.checked_div(p256 - target).unwrap_or(p256);
 }
 
-/// Converts a difficulty to a target.
-pub fn difficulty_to_target(difficulty: U256) -> U256 {
-  let p256 =
+/// Expected seconds until a miner doing `hashes_per_second` finds a block
+/// hitting `target`. Infinite without any hash rate.
+pub fn expected_block_time(target: U256, hashes_per_second: u64) -> f64 {
+  if hashes_per_second == 0 {
+    return f64::INFINITY;
+  }
+  // Difficulties too large for an u128 are way beyond any real miner
+  let difficulty = target_to_difficulty(target);
+  let difficulty = if difficulty > u256(u128::MAX) {
+    u128::MAX
+  } else {
+    difficulty.low_u128()
+  };
+  difficulty as f64 / hashes_per_second as f64
+}
+
+/// Converts a difficulty to a target.
+pub fn difficulty_to_target(difficulty: U256) -> U256 {
+  let p256 =
     "0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF";
   let p256 = U256::from(p256);
   return p256 - p256 / difficulty;
@@ -547,10 +1499,23 @@ pub fn difficulty_to_target(difficulty: U256) -> U256 {
 pub fn compute_next_target(last_target: U256, scale: U256) -> U256 {
   let p32 = U256::from("0x100000000");
   let last_difficulty = target_to_difficulty(last_target);
-  let next_difficulty = u256(1) + (last_difficulty * scale - u256(1)) / p32;
+  let scaled = last_difficulty.saturating_mul(scale).saturating_sub(u256(1));
+  let next_difficulty = u256(1).saturating_add(scaled / p32);
   return difficulty_to_target(next_difficulty);
 }
 
+/// Checks if the block at `height` starts a new difficulty period.
+pub fn starts_new_period(height: u128) -> bool {
+  height > BLOCKS_PER_PERIOD && height % BLOCKS_PER_PERIOD == 1
+}
+
+/// Computes the target of a block starting a new period, given its parent's
+/// target and how long the last period took, in milliseconds.
+pub fn next_period_target(last_target: U256, period_time: u128) -> U256 {
+  let next_scaler = 2u128.pow(32) * TIME_PER_PERIOD / period_time.max(1);
+  compute_next_target(last_target, u256(next_scaler))
+}
+
 // Estimates how many hashes were necessary to get this one.
 pub fn get_hash_work(hash: U256) -> U256 {
   if hash == u256(0) {
@@ -593,9 +1558,14 @@ pub fn add_transaction_to_body_vec(
 pub fn extract_transactions(body: &Body) -> Vec<Transaction> {
   let mut transactions = Vec::new();
   let mut index = 1;
-  let tx_count = body.data[0].reverse_bits();
+  // Blocks are hashed from their transactions, so malformed bodies received
+  // from peers must not panic here
+  let tx_count = match body.data.first() {
+    Some(byte) => byte.reverse_bits(),
+    None => return transactions,
+  };
   for _ in 0..tx_count {
-    if index >= body.data.len() {
+    if index + 2 > body.data.len() {
       break;
     }
     let tx_len =
@@ -611,9 +1581,62 @@ pub fn extract_transactions(body: &Body) -> Vec<Transaction> {
   transactions
 }
 
+/// Converts a block body to a vector of transactions, like
+/// `extract_transactions`, but fails on anything a well-formed body can't
+/// have: truncated or trailing bytes and transactions that aren't statements.
+pub fn validate_body(body: &Body) -> Result<Vec<Transaction>, String> {
+  if body.data.len() > MAX_BODY_SIZE {
+    return Err(format!("Body is larger than {} bytes.", MAX_BODY_SIZE));
+  }
+  let tx_count = match body.data.first() {
+    Some(byte) => byte.reverse_bits() as usize,
+    None => return Err("Body is empty.".to_string()),
+  };
+  let mut transactions = Vec::with_capacity(tx_count);
+  let mut index = 1;
+  for i in 0..tx_count {
+    if index + 2 > body.data.len() {
+      return Err(format!("Body ends before transaction {}.", i));
+    }
+    let tx_len =
+      Transaction::decode_length((body.data[index], body.data[index + 1]));
+    index += 2;
+    if index + tx_len > body.data.len() {
+      return Err(format!("Transaction {} is truncated.", i));
+    }
+    let transaction_body = body.data[index..index + tx_len].to_vec();
+    let transaction = Transaction::new(transaction_body);
+    if transaction.to_exact_statement().is_none() {
+      return Err(format!("Transaction {} is not a valid statement.", i));
+    }
+    transactions.push(transaction);
+    index += tx_len;
+  }
+  if index != body.data.len() {
+    return Err("Body has trailing bytes.".to_string());
+  }
+  Ok(transactions)
+}
+
 /// Initial target of 256 hashes per block.
 pub fn initial_target() -> U256 {
-  difficulty_to_target(u256(INITIAL_DIFFICULTY))
+  initial_target_for(INITIAL_DIFFICULTY)
+}
+
+/// Initial target of `difficulty` hashes per block.
+pub fn initial_target_for(difficulty: u128) -> U256 {
+  difficulty_to_target(u256(difficulty))
+}
+
+/// Magic number tagging the messages of a network. A non-default initial
+/// difficulty makes a different chain, so it is mixed into the network id,
+/// and nodes that disagree on it drop each other's messages.
+pub fn network_magic(network_id: u32, initial_difficulty: u128) -> u32 {
+  if initial_difficulty == INITIAL_DIFFICULTY {
+    return network_id;
+  }
+  let hash = hash_bytes(&initial_difficulty.to_be_bytes());
+  network_id ^ hash.low_u32()
 }
 
 /// The hash of the genesis block's parent.
@@ -621,18 +1644,299 @@ pub fn zero_hash() -> U256 {
   u256(0)
 }
 
-/// Builds the Genesis Block.
+/// Builds the Genesis Block. It stays in format version 0, so its hash is
+/// the same on every build.
 pub fn build_genesis_block(stmts: &[Statement]) -> Block {
   let body = Body::from_transactions_iter(stmts)
     .expect("Genesis statements should fit in a block body");
-  Block::new(zero_hash(), 0, 0, body)
+  Block::new(0, zero_hash(), 0, 0, body)
+}
+
+/// Format version a block at `height` must be in, given the height version 1
+/// activates at.
+pub fn block_version_at(height: u128, v1_height: u128) -> u64 {
+  if height >= v1_height {
+    1
+  } else {
+    0
+  }
+}
+
+/// Runs the statements of a block on `runtime`, as the next tick.
+pub fn run_block(
+  runtime: &mut Runtime,
+  block: &HashedBlock,
+) -> Vec<StatementResult> {
+  let transactions = extract_transactions(&block.body);
+  let mut statements = Vec::new();
+  for transaction in transactions {
+    if let Some(statement) = transaction.to_statement() {
+      statements.push(statement);
+    }
+  }
+  let bhash = U256::from(block.get_hash());
+  runtime.set_time(block.time >> 8);
+  runtime.set_meta(block.meta >> 8);
+  runtime.set_hax0((bhash >> 000).low_u128() >> 8);
+  runtime.set_hax1((bhash >> 120).low_u128() >> 8);
+  runtime.open();
+  let result = runtime.run_statements(&statements, false, false);
+  runtime.commit();
+  result
+}
+
+/// Whether a statement concerns `name`: it declares it, or its term refers to
+/// it, as a function, a constructor or a name literal (e.g. the function an
+/// `IO` action calls).
+pub fn statement_mentions(statement: &Statement, name: Name) -> bool {
+  match statement {
+    Statement::Fun { name: declared, .. }
+    | Statement::Ctr { name: declared, .. }
+    | Statement::Reg { name: declared, .. } => *declared == name,
+    Statement::Run { expr, .. } => term_mentions(expr, name),
+  }
+}
+
+fn term_mentions(term: &Term, name: Name) -> bool {
+  match term {
+    Term::Var { .. } => false,
+    Term::Dup { expr, body, .. } => {
+      term_mentions(expr, name) || term_mentions(body, name)
+    }
+    Term::Lam { body, .. } => term_mentions(body, name),
+    Term::App { func, argm } => {
+      term_mentions(func, name) || term_mentions(argm, name)
+    }
+    Term::Ctr { name: called, args } | Term::Fun { name: called, args } => {
+      *called == name || args.iter().any(|arg| term_mentions(arg, name))
+    }
+    Term::Num { numb } => **numb == *name,
+    Term::Op2 { val0, val1, .. } => {
+      term_mentions(val0, name) || term_mentions(val1, name)
+    }
+  }
+}
+
+/// Mana spent by the statements with `results`: running `run` statements and
+/// computing the initial state of `fun` ones. Constructors and registrations
+/// spend none.
+pub fn results_mana(results: &[StatementResult]) -> u64 {
+  let mut total: u64 = 0;
+  for result in results {
+    match result {
+      Ok(StatementInfo::Run { used_mana, .. })
+      | Ok(StatementInfo::Fun { used_mana, .. }) => {
+        total = total.saturating_add(*used_mana);
+      }
+      _ => {}
+    }
+  }
+  total
+}
+
+/// Reads the statements of a mining template file, as transactions.
+pub fn load_mine_template(
+  path: &std::path::Path,
+) -> Result<Vec<Transaction>, String> {
+  let code = std::fs::read_to_string(path)
+    .map_err(|err| format!("Could not read '{}': {}", path.display(), err))?;
+  let stmts = hvm::parse_code(&code)
+    .map_err(|err| format!("Could not parse '{}': {}", path.display(), err))?;
+  Ok(stmts.iter().map(Transaction::from_statement).collect())
+}
+
+// Stored chain
+// ------------
+
+/// Reads the hash of the block a state exported with `Node::export_state`
+/// was taken at, so that it is only imported on that block's chain.
+pub fn read_state_block(path: &Path) -> std::io::Result<U256> {
+  let bytes = std::fs::read(path.join(STATE_BLOCK_FILE))?;
+  if bytes.len() != 32 {
+    return Err(std::io::Error::new(
+      std::io::ErrorKind::InvalidData,
+      "the block hash must have 32 bytes",
+    ));
+  }
+  Ok(U256::from_big_endian(&bytes))
+}
+
+/// Lists the block files on `blocks_dir`, sorted by the height in their names.
+/// Entries that can't be read, or aren't named after a height, are skipped
+/// with a warning.
+pub fn list_block_files(
+  blocks_dir: &std::path::Path,
+) -> Result<Vec<(u64, PathBuf)>, String> {
+  let entries = std::fs::read_dir(blocks_dir).map_err(|err| {
+    format!("Could not read '{}': {}", blocks_dir.display(), err)
+  })?;
+  let mut file_paths: Vec<(u64, PathBuf)> = vec![];
+  for entry in entries {
+    let path = match entry {
+      Ok(entry) => entry.path(),
+      Err(err) => {
+        eprintln!(
+          "WARN: Could not read an entry of '{}': {}",
+          blocks_dir.display(),
+          err
+        );
+        continue;
+      }
+    };
+    // Extract block height from block file path for fast sort
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let bnum = name.split('.').nth(0).unwrap_or("");
+    match u64::from_str_radix(bnum, 16) {
+      Ok(bnum) => file_paths.push((bnum, path)),
+      Err(_) => eprintln!(
+        "WARN: Skipping '{}', which isn't named after a block height.",
+        path.display()
+      ),
+    }
+  }
+  file_paths.sort_unstable();
+  Ok(file_paths)
+}
+
+/// Rewrites a block file read in the legacy, unversioned encoding in the
+/// current one. Returns whether it was rewritten; on failure the file is left
+/// as is, and still loads.
+fn migrate_block_file(file_path: &std::path::Path, block: &Block) -> bool {
+  let bytes = bitvec_to_bytes(&block.proto_serialized());
+  if let Err(err) = std::fs::write(file_path, bytes) {
+    eprintln!(
+      "WARN: could not migrate '{}' to the versioned block encoding: {}",
+      file_path.display(),
+      err
+    );
+    return false;
+  }
+  true
+}
+
+/// How many milliseconds until a tip that hasn't changed for `stable_for` ms is
+/// gossiped again to the same peer. Starts at `TIP_RESEND_INTERVAL` and backs
+/// off as the tip stays put, up to `TIP_KEEPALIVE_INTERVAL`.
+pub fn tip_resend_interval(stable_for: u128) -> u128 {
+  (stable_for / 4).clamp(TIP_RESEND_INTERVAL, TIP_KEEPALIVE_INTERVAL)
+}
+
+/// Height a node loading at most `max` of the block files for heights `1` to
+/// `last` starts its chain on. It is rounded down to the start of a retarget
+/// period, so later targets can be recomputed, and never above `floor`, the
+/// tick the runtime may have to be computed from, e.g. that of the imported
+/// state, as the blocks after it must be computed.
+/// `None` when every block must be loaded.
+pub fn suffix_start(last: u64, max: u64, floor: u64) -> Option<u64> {
+  let wanted = last.saturating_sub(max).saturating_add(1);
+  let start = std::cmp::min(wanted, floor);
+  let period = BLOCKS_PER_PERIOD as u64;
+  let start = start.checked_sub(1)? / period * period + 1;
+  if start > 1 {
+    Some(start)
+  } else {
+    None
+  }
+}
+
+/// Height of the first block of the retarget period `height` is in. Blocks
+/// from it on, up to the next period, have the same target.
+pub fn period_start(height: u64) -> u64 {
+  let period = BLOCKS_PER_PERIOD as u64;
+  height.saturating_sub(1) / period * period + 1
+}
+
+/// Checks that a sorted list of block file heights is contiguous, i.e., that
+/// it is `1, 2, 3, ...`. Returns the missing heights, as inclusive ranges, and
+/// the repeated ones.
+pub fn check_block_heights(heights: &[u64]) -> (Vec<(u64, u64)>, Vec<u64>) {
+  let mut missing = vec![];
+  let mut repeated = vec![];
+  let mut expected = 1;
+  for &height in heights {
+    if height < expected {
+      if repeated.last() != Some(&height) {
+        repeated.push(height);
+      }
+    } else {
+      if height > expected {
+        missing.push((expected, height - 1));
+      }
+      expected = height + 1;
+    }
+  }
+  (missing, repeated)
+}
+
+/// Verifies the integrity of the chain stored on `blocks_dir`, without running
+/// it. Checks heights, parent links, timestamps and PoW, recomputing work and
+/// targets, and stops on the first bad block. Returns the tip height and work.
+pub fn verify_stored_chain(
+  blocks_dir: &std::path::Path,
+  genesis_hash: U256,
+  initial_difficulty: u128,
+  block_v1_height: u128,
+) -> Result<(u128, U256), String> {
+  let mut prev_hash = genesis_hash;
+  let mut height: u128 = 0;
+  let mut work = u256(0);
+  let mut target = initial_target_for(initial_difficulty);
+  // Timestamps of the verified blocks, indexed by height (genesis is 0)
+  let mut times: Vec<u128> = vec![0];
+  for (bnum, file_path) in list_block_files(blocks_dir)? {
+    let bad = |msg: String| {
+      format!("Bad block #{} at '{}': {}", bnum, file_path.display(), msg)
+    };
+    if bnum as u128 != height + 1 {
+      return Err(bad(format!("expected height {}", height + 1)));
+    }
+    let buffer = std::fs::read(&file_path).map_err(|e| bad(e.to_string()))?;
+    let (block, _) = Block::from_file_bytes(&buffer).map_err(bad)?;
+    let block = block.hashed();
+    let bhash = U256::from(block.get_hash());
+    if block.prev != prev_hash {
+      return Err(bad(format!("parent is not {:#x}", prev_hash)));
+    }
+    let version = block_version_at(height + 1, block_v1_height);
+    if block.version != version {
+      return Err(bad(format!("expected format version {}", version)));
+    }
+    if block.time <= times[height as usize] {
+      return Err(bad("timestamp does not advance".to_string()));
+    }
+    if bhash < target {
+      return Err(bad("not enough work".to_string()));
+    }
+    height += 1;
+    work = work + get_hash_work(bhash);
+    times.push(block.time);
+    // Computes this block's target, which its child must hit
+    if starts_new_period(height) {
+      let checkpoint_time = times[(height - BLOCKS_PER_PERIOD) as usize];
+      target = next_period_target(target, block.time - checkpoint_time);
+    }
+    prev_hash = bhash;
+  }
+  Ok((height, work))
 }
 
 // Mining
 // ------
 
+/// Whether a block hash meets `target`, i.e. proves enough work.
+pub fn hash_hits_target(hash: U256, target: U256) -> bool {
+  hash >= target
+}
+
+/// Whether `block` carries enough proof of work for `target`. Needs no node,
+/// so light clients and tools can check blocks by themselves.
+pub fn block_hits_target(block: &Block, target: U256) -> bool {
+  hash_hits_target(U256::from(&block.keccak256()), target)
+}
+
 // Given a target, attempts to mine a block by changing its nonce up to `max_attempts` times
 pub fn try_mine(
+  version: u64,
   prev: U256,
   body: Body,
   targ: U256,
@@ -640,12 +1944,12 @@ pub fn try_mine(
 ) -> Option<HashedBlock> {
   let rand = rand::random::<u128>();
   let time = get_time();
-  let mut block = Block::new(prev, time, rand, body);
+  let mut block = Block::new(version, prev, time, rand, body);
   for _i in 0..max_attempts {
     block = {
       let hashed = block.hashed();
       let hash_n = U256::from(hashed.get_hash());
-      if hash_n >= targ {
+      if hash_hits_target(hash_n, targ) {
         return Some(hashed);
       }
       let mut block = hashed.take();
@@ -674,21 +1978,41 @@ impl MinerCommunication {
 }
 
 // Main miner loop: if asked, attempts to mine a block
+/// How long the miner sleeps after hashing for `elapsed`, so that it hashes
+/// `intensity` percent of the time. Intensities of 0 and from 100 up mean
+/// full speed.
+pub fn mining_pause(
+  elapsed: std::time::Duration,
+  intensity: u8,
+) -> std::time::Duration {
+  if intensity == 0 || intensity >= 100 {
+    return std::time::Duration::ZERO;
+  }
+  let intensity = intensity as u32;
+  elapsed * (100 - intensity) / intensity
+}
+
 pub fn miner_loop(
   mut miner_comm: MinerCommunication,
   slow_mining: Option<u64>,
+  intensity: Option<u8>,
   #[cfg(feature = "events")] event_emitter: mpsc::Sender<NodeEventEmittedInfo>,
 ) {
   loop {
-    if let MinerMessage::Request { prev, body, targ } = miner_comm.read() {
+    if let MinerMessage::Request { version, prev, body, targ } = miner_comm.read()
+    {
       let before = std::time::Instant::now();
-      let mined = try_mine(prev, body, targ, MINE_ATTEMPTS);
+      let mined = try_mine(version, prev, body, targ, MINE_ATTEMPTS);
+      let elapsed = before.elapsed();
       // Slow down mining, for debugging pourposes, if enabled
       if let Some(slow_ratio) = slow_mining {
-        let elapsed = before.elapsed();
         let sleep_time = elapsed.saturating_mul(slow_ratio as u32);
         std::thread::sleep(sleep_time);
       }
+      // Keep to the configured share of CPU time
+      if let Some(intensity) = intensity {
+        std::thread::sleep(mining_pause(elapsed, intensity));
+      }
       if let Some(block) = mined {
         emit_event!(
           event_emitter,
@@ -713,17 +2037,58 @@ pub fn miner_loop(
 // ----
 
 impl<C: ProtoComm> Node<C> {
+  /// Builds the node from the options set on `builder`. See
+  /// `NodeBuilder::build`.
   pub fn new(
-    data_path: PathBuf,
-    network_id: u32,
-    initial_peers: Vec<C::Address>,
-    comm: C,
-    miner_comm: Option<MinerCommunication>,
-    #[cfg(feature = "events")] event_emitter: mpsc::Sender<
-      NodeEventEmittedInfo,
-    >,
-  ) -> (mpsc::SyncSender<NodeRequest<C>>, Self) {
-    let (query_sender, query_receiver) = mpsc::sync_channel(1);
+    builder: NodeBuilder<C>,
+  ) -> Result<(mpsc::SyncSender<NodeRequest<C>>, Self), String> {
+    let NodeBuilder {
+      data_path,
+      comm,
+      network_id,
+      name,
+      initial_peers,
+      pinned_peers,
+      mode,
+      state_snapshot,
+      api_config,
+      net_config,
+      mine_config,
+      miner_comm,
+      max_load_blocks,
+      storage,
+      prune_depth,
+      #[cfg(feature = "events")]
+      event_emitter,
+    } = builder;
+    let initial_difficulty =
+      mine_config.initial_difficulty.unwrap_or(INITIAL_DIFFICULTY);
+    if initial_difficulty == 0 {
+      return Err("The initial difficulty must be greater than 0.".to_string());
+    }
+    if net_config.statement_mana == Some(0) {
+      return Err(
+        "The statement mana must be greater than 0, or no statement would \
+        succeed."
+          .to_string(),
+      );
+    }
+    // These change which chains are valid, so the public network's nodes
+    // must agree on them
+    let default_consensus = initial_difficulty == INITIAL_DIFFICULTY
+      && net_config.statement_mana.is_none()
+      && net_config.block_v1_height.is_none();
+    if network_id == PUBLIC_NETWORK_ID && !default_consensus {
+      return Err(
+        "The initial difficulty, the statement mana and the block format \
+        version 1 height can't be changed on the public network. Use another \
+        network id."
+          .to_string(),
+      );
+    }
+
+    let (query_sender, query_receiver) =
+      mpsc::sync_channel(api_config.request_queue_size);
 
     let genesis_stmts =
       hvm::parse_code(constants::GENESIS_CODE).expect("Genesis code parses");
@@ -731,68 +2096,215 @@ impl<C: ProtoComm> Node<C> {
     let genesis_block = genesis_block.hashed();
     let genesis_hash = genesis_block.get_hash().into();
 
-    let runtime = init_runtime(data_path.join("heaps"), &genesis_stmts);
+    let mut runtime = init_runtime(data_path.join("heaps"), &genesis_stmts)
+      .map_err(|err| {
+        format!(
+          "Could not initialize the runtime. {} The runtime needs a writable \
+          data directory, and enough memory and disk space for its heaps.",
+          err
+        )
+      })?;
+    // Blocks up to the checkpoint aren't computed, so without their state
+    // the node would serve one missing their statements
+    if net_config.trusted_checkpoint.is_some() && state_snapshot.is_none() {
+      return Err(
+        "A trusted checkpoint needs the state taken at it. Import it with \
+        `import_state`."
+          .to_string(),
+      );
+    }
+    // Without them, the runtime is rebuilt from genesis on every start, so
+    // no block could be dropped
+    if storage == StorageMode::Pruned
+      && net_config.trusted_checkpoint.is_none()
+    {
+      return Err(
+        "Pruned storage needs a trusted checkpoint and the state taken at it. \
+        Import it with `import_state`."
+          .to_string(),
+      );
+    }
+    let mut state_floor = 0;
+    let mut state_block = None;
+    if let Some(path) = &state_snapshot {
+      let bhash = read_state_block(path).map_err(|err| {
+        format!(
+          "Could not read the block of the state at '{}': {}. Export it \
+          again.",
+          path.display(),
+          err
+        )
+      })?;
+      runtime.import_state(path).map_err(|err| {
+        format!("Could not import state from '{}': {}", path.display(), err)
+      })?;
+      state_floor = runtime.get_tick();
+      state_block = Some(bhash);
+      eprintln!("Imported state at tick {}, block {:#x}.", state_floor, bhash);
+      if let Some(checkpoint) = &net_config.trusted_checkpoint {
+        if state_floor != checkpoint.height
+          || bhash != U256::from(checkpoint.hash)
+        {
+          return Err(format!(
+            "The imported state is at block {:#x}, height {}, but the \
+            trusted checkpoint is block {:#x}, height {}. Import the state \
+            taken at the checkpoint.",
+            bhash,
+            state_floor,
+            U256::from(checkpoint.hash),
+            checkpoint.height
+          ));
+        }
+      }
+    }
+    runtime.set_statement_mana(net_config.statement_mana);
+
+    let mine_template = match &mine_config.template_file {
+      Some(path) => load_mine_template(path).unwrap_or_else(|err| {
+        eprintln!("WARN: ignoring the mining template. {}", err);
+        vec![]
+      }),
+      None => vec![],
+    };
+
+    let recv_buffer_size =
+      std::cmp::max(net_config.recv_buffer_size, MAX_UDP_SIZE_SLOW);
 
     #[rustfmt::skip]
     let mut node = Node {
       data_path,
       network_id,
+      magic: network_magic(network_id, initial_difficulty),
+      block_v1_height: net_config.block_v1_height.unwrap_or(BLOCK_V1_HEIGHT),
+      name,
       addr: comm.get_addr(),
       comm,
       runtime,
-      pool     : PriorityQueue:: new(),
-      peers    : PeersStore:: new(),
+      state_floor,
+      state_block,
+      pool     : Mempool::new(),
+      peers    : PeersStore:: with_limits(net_config.max_peers, net_config.max_inbound_peers),
 
       genesis_hash,
+      base     : genesis_hash,
       tip      : genesis_hash,
+      tip_time : get_time(),
+      started_at: get_time(),
+      heard_tip: false,
       block    : u256map_from([(genesis_hash, genesis_block)]),
       pending  : u256map_new(),
+      pending_at: u256map_new(),
       ancestor : u256map_new(),
       wait_list: u256map_new(),
       children : u256map_from([(genesis_hash, vec![]          )]),
       work     : u256map_from([(genesis_hash, u256(0)         )]),
       height   : u256map_from([(genesis_hash, 0               )]),
-      target   : u256map_from([(genesis_hash, initial_target())]),
+      target   : u256map_from([(genesis_hash, initial_target_for(initial_difficulty))]),
       results  : u256map_from([(genesis_hash, vec![]          )]),
+      ticks    : u256map_from([(genesis_hash, 0               )]),
+      lost_ticks: u256map_from([(genesis_hash, 0)]),
+      tx_index : u256map_new(),
 
       #[cfg(feature = "events")]
       event_emitter: event_emitter.clone(),
       query_recv : query_receiver,
+      mining_enabled: miner_comm.is_some(),
+      mine_template,
       miner_comm,
+      net_config,
+      mine_config,
+      api_config,
+      mode,
+      gossiped_tip: genesis_hash,
+      tip_sent_at: HashMap::new(),
+      block_requests: u256map_new(),
+      congested_until: 0,
+      congestion_backoff: 0,
+      message_counts: api::MessageCounts::default(),
+      message_rate: 0.0,
+      message_sample: (get_time(), 0),
+      recent_blocks: VecDeque::new(),
+      functions: None,
+      peer_tips: HashMap::new(),
+      split_since: None,
+      split_warned: false,
+      block_serving: BlockServing::default(),
+      recv_buffer: vec![0; recv_buffer_size],
+      recv_messages: Vec::new(),
+      block_timings: VecDeque::new(),
+      unsaved_blocks: BTreeMap::new(),
+      paused_since: None,
+      block_sources: u256map_new(),
+      block_source_order: VecDeque::new(),
+      results_subs: vec![],
+      max_load_blocks,
+      storage,
+      prune_depth: prune_depth.unwrap_or(PRUNE_DEPTH),
+      stale_warned: false,
     };
 
+    node.peers.set_timeout(node.net_config.peer_timeout);
+    if let Some(seed) = node.net_config.peer_seed {
+      node.peers.set_seed(seed);
+    }
+
     let now = get_time();
 
-    initial_peers.iter().for_each(|address| {
+    for address in &pinned_peers {
+      node.peers.pin(*address);
+    }
+
+    initial_peers.iter().chain(pinned_peers.iter()).for_each(|address| {
       return node.peers.see_peer(
         Peer { address: *address, seen_at: now },
+        false,
         #[cfg(feature = "events")] // TODO: remove (implement on Node)
         event_emitter.clone(),
       );
     });
 
-    // TODO: For testing purposes. Remove later.
-    // for &peer_port in try_ports.iter() {
-    //   if peer_port != port {
-    //     let address = Address::IPv4 { val0: 127, val1: 0, val2: 0, val3: 1, port: peer_port };
-    //     node.peers.see_peer(Peer { address: address, seen_at: now })
-    //   }
-    // }
+    Ok((query_sender, node))
+  }
 
-    (query_sender, node)
+  /// The address this node is bound to. With UDP, its port tells which one
+  /// was picked, when the default one was taken.
+  pub fn get_addr(&self) -> C::Address {
+    self.addr
   }
 
+  /// Admits a transaction to the mempool. Transactions that don't decode to
+  /// a statement can't be mined, so they are rejected. So are transactions
+  /// larger than the configured `max_tx_bytes`; this is a local policy, so
+  /// blocks received from peers are not affected by it.
   pub fn add_transaction(
     &mut self,
     transaction: Transaction,
-  ) -> Result<(), ()> {
-    let t_score = transaction.hash.low_u64();
-    if self.pool.get(&transaction).is_none() {
-      self.pool.push(transaction, t_score);
-      Ok(())
-    } else {
-      Err(())
+  ) -> Result<(), String> {
+    self.add_transaction_with_priority(transaction, 0)
+  }
+
+  /// Like `add_transaction`, with an explicit mempool `priority`. Signed
+  /// deploys replace a pending one by the same signer for the same name if
+  /// their priority is higher; see `Transaction::replacement_key`. The
+  /// priority is local to this node: peers admit gossiped transactions with
+  /// the default one.
+  pub fn add_transaction_with_priority(
+    &mut self,
+    transaction: Transaction,
+    priority: u64,
+  ) -> Result<(), String> {
+    if transaction.to_exact_statement().is_none() {
+      return Err("Transaction is not a valid statement.".to_string());
     }
+    let max_tx_bytes = self.mine_config.max_tx_bytes.unwrap_or(MAX_BODY_SIZE);
+    if transaction.data.len() > max_tx_bytes {
+      return Err(format!(
+        "Transaction has {} bytes, above the limit of {} bytes.",
+        transaction.data.len(),
+        max_tx_bytes
+      ));
+    }
+    self.pool.insert(transaction, priority)
   }
 
   // Registers a block on the node's database. This performs several actions:
@@ -806,17 +2318,22 @@ impl<C: ProtoComm> Node<C> {
   //     - In case of a reorg, rollback to the block before it
   //     - Run that block's code, updating the HVM state
   //     - Updates the longest chain saved on disk
-  pub fn add_block(&mut self, block: &HashedBlock) {
+  // Returns whether the given block itself was accepted into the chain; it
+  // isn't if it was refused, already known, too far into the future or is
+  // still waiting for its parent.
+  pub fn add_block(&mut self, block: &HashedBlock) -> bool {
     // Adding a block might trigger the addition of other blocks
     // that were waiting for it. Because of that, we loop here.
 
+    let added_hash: U256 = block.get_hash().into();
+    let mut accepted = false;
     // Blocks to be added
     let mut must_include = vec![block.clone()];
     // While there is a block to add...
     while let Some(block) = must_include.pop() {
       let btime = block.time;
       // If block is too far into the future, ignore it
-      if btime >= get_time() + DELAY_TOLERANCE {
+      if btime >= get_time().saturating_add(DELAY_TOLERANCE) {
         emit_event!(
           self.event_emitter,
           NodeEventType::too_late(&block),
@@ -826,6 +2343,8 @@ impl<C: ProtoComm> Node<C> {
         continue;
       }
       let bhash = block.get_hash().into();
+      // The block arrived, so it no longer needs to be requested
+      self.block_requests.remove(&bhash);
       // If we already registered this block, ignore it
       if let Some(block) = self.block.get(&bhash) {
         let height = self.height[&bhash];
@@ -849,138 +2368,55 @@ impl<C: ProtoComm> Node<C> {
         self.ancestor.remove(&bhash); // remove it from the ancestor jump table
 
         // Checks if this block PoW hits the target
-        let has_enough_work = bhash >= self.target[&phash];
+        let has_enough_work = hash_hits_target(bhash, self.target[&phash]);
         // Checks if this block's timestamp is larger than its parent's timestamp
         // Note: Bitcoin checks if it is larger than the median of the last 11 blocks; should we?
         let advances_time = btime > self.block[&phash].time;
+        // Checks if this block is in the format version due at its height
+        let right_version = block.version == self.block_version_after(phash);
+        // Checks if this block agrees with the trusted checkpoint, if any
+        let fits_checkpoint = self.fits_checkpoint(bhash, phash, work);
+        // Checks if this block agrees with the imported state, if any
+        let fits_state = self.fits_imported_state(bhash, phash);
+        // Checks if this block descends from the base. On a truncated chain,
+        // work is only counted from it, so forks from older blocks can't be
+        // weighed against it
+        let above_base = phash == self.base
+          || self.height[&phash] > self.height[&self.base];
         // If the PoW hits the target and the block's timestamp is valid...
-        if has_enough_work && advances_time {
-          self.work.insert(bhash, self.work[&phash] + work); // sets this block accumulated work
-          self.height.insert(bhash, self.height[&phash] + 1); // sets this block accumulated height
+        if has_enough_work
+          && advances_time
+          && right_version
+          && fits_checkpoint
+          && fits_state
+          && above_base
+        {
+          self.work.insert(bhash, self.work[&phash].saturating_add(work)); // sets this block accumulated work
+          self.height.insert(bhash, self.height[&phash].saturating_add(1)); // sets this block accumulated height
+          debug_assert!(
+            self.height[&bhash] > self.height[&phash],
+            "height of block {:#x} doesn't grow from its parent's",
+            bhash
+          );
 
           // If this block starts a new period, computes the new target
-          if self.height[&bhash] > 0
-            && self.height[&bhash] > BLOCKS_PER_PERIOD
-            && self.height[&bhash] % BLOCKS_PER_PERIOD == 1
-          {
-            // Finds the checkpoint hash (hash of the first block of the last period)
-            let mut checkpoint_hash = phash;
-            for _ in 0..BLOCKS_PER_PERIOD - 1 {
-              checkpoint_hash = self.block[&checkpoint_hash].prev;
-            }
+          if starts_new_period(self.height[&bhash]) {
+            let checkpoint_hash = self.period_checkpoint(phash);
             // Computes how much time the last period took to complete
-            let period_time = btime - self.block[&checkpoint_hash].time;
+            let period_time =
+              btime.saturating_sub(self.block[&checkpoint_hash].time);
             // Computes the target of this period
             let last_target = self.target[&phash];
-            let next_scaler = 2u128.pow(32) * TIME_PER_PERIOD / period_time;
-            let next_target =
-              compute_next_target(last_target, u256(next_scaler));
+            let next_target = next_period_target(last_target, period_time);
             // Sets the new target
             self.target.insert(bhash, next_target);
           // Otherwise, keep the old target
           } else {
             self.target.insert(bhash, self.target[&phash]);
           }
-          // Updates the tip work and block hash
-          let cur_tip = self.tip;
-          let new_tip = bhash;
-          if self.work[&new_tip] > self.work[&cur_tip] {
-            // When the tip updates, stop mining the last built block, which is
-            // based on the outdated tip
-            self.send_to_miner(MinerMessage::Stop);
-            emit_event!(
-              self.event_emitter,
-              NodeEventType::stop_mining(),
-              tags = mining,
-              stopped
-            );
-            // Removes this block's transactions from mempool
-            for tx in extract_transactions(&block.body) {
-              self.pool.remove(&tx);
-            }
-            self.tip = bhash;
-            if true {
-              // Block reorganization (* marks blocks for which we have runtime snapshots):
-              // tick: |  0 | *1 |  2 |  3 |  4 | *5 |  6 | *7 | *8 |
-              // hash: |  A |  B |  C |  D |  E |  F |  G |  H |    |  <- old timeline
-              // hash: |  A |  B |  C |  D |  P |  Q |  R |  S |  T |  <- new timeline
-              //               |         '-> highest common block shared by both timelines
-              //               '-----> highest runtime snapshot before block D
-              let mut must_compute = Vec::new();
-              let mut old_bhash = cur_tip;
-              let mut new_bhash = new_tip;
-              // 1. Finds the highest block with same height on both timelines
-              //    On the example above, we'd have `H, S`
-              while self.height[&new_bhash] > self.height[&old_bhash] {
-                must_compute.push(new_bhash);
-                new_bhash = self.block[&new_bhash].prev;
-              }
-              while self.height[&old_bhash] > self.height[&new_bhash] {
-                old_bhash = self.block[&old_bhash].prev;
-              }
-              // 2. Finds highest block with same value on both timelines
-              //    On the example above, we'd have `D`
-              while old_bhash != new_bhash {
-                must_compute.push(new_bhash);
-                old_bhash = self.block[&old_bhash].prev;
-                new_bhash = self.block[&new_bhash].prev;
-              }
-              // 3. Saves overwritten blocks to disk
-              // TODO: on separate thread
-              for bhash_comp in must_compute.iter().rev() {
-                let file_path = self.get_blocks_path().join(format!(
-                  "{:0>16x}.kindelia_block.bin",
-                  self.height[bhash_comp]
-                ));
-                let file_buff =
-                  bitvec_to_bytes(&self.block[bhash_comp].proto_serialized());
-                std::fs::write(file_path, file_buff)
-                  .expect("Couldn't save block to disk.");
-              }
-              // 4. Reverts the runtime to a state older than that block
-              //    On the example above, we'd find `runtime.tick = 1`
-              let mut tick = self.height[&old_bhash];
-
-              let runtime_old_tick = self.runtime.get_tick();
-              self.runtime.rollback(tick as u64);
-
-              let old = (&self.block[&cur_tip], self.height[&cur_tip]);
-              let new = (&self.block[&new_tip], self.height[&new_tip]);
-              let common = (&self.block[&old_bhash], self.height[&old_bhash]); // common ancestor
-              let ticks = (runtime_old_tick as u128, self.runtime.get_tick() as u128);
-              emit_event!(
-                self.event_emitter,
-                NodeEventType::reorg(old, new, common, ticks, work),
-                tags = add_block,
-                reorg
-              );
-
-              // 5. Finds the last block included on the reverted runtime state
-              //    On the example above, we'd find `new_bhash = B`
-              while tick as u64 > self.runtime.get_tick() {
-                must_compute.push(new_bhash);
-                new_bhash = self.block[&new_bhash].prev;
-                tick -= 1;
-              }
-              emit_event!(
-                self.event_emitter,
-                NodeEventType::computed(
-                  &block,
-                  self.height[&self.tip],
-                  &must_compute
-                ),
-                tags = add_block,
-                computed
-              ); // emitting computed blocks for measurement
-
-              // 6. Computes every block after that on the new timeline
-              //    On the example above, we'd compute `C, D, P, Q, R, S, T`
-              for bhash_comp in must_compute.iter().rev() {
-                let block_comp = &self.block[bhash_comp];
-                self.compute_block(&block_comp.clone()); // TODO: avoid clone
-              }
-            }
-          }
+          accepted |= bhash == added_hash;
+          // Moves the tip to this block, if it's a better one
+          self.maybe_reorg(bhash);
         } else {
           emit_event!(
             self.event_emitter,
@@ -1009,13 +2445,21 @@ impl<C: ProtoComm> Node<C> {
           for waiting_for_me in wait_list {
             must_include
               .push(self.pending.remove(waiting_for_me).expect("block"));
+            self.pending_at.remove(waiting_for_me);
           }
           self.wait_list.remove(&bhash);
         }
       // Otherwise, if the previous block isn't available,
-      // include this block on .pending, and on its parent's wait_list
-      } else if self.pending.get(&bhash).is_none() {
+      // include this block on .pending, and on its parent's wait_list.
+      // A block no newer than the base can't descend from it: its parent is
+      // below the horizon, so it is dropped instead of waiting for ancestors
+      // that can't be weighed
+      } else if self.pending.get(&bhash).is_none()
+        && (self.base == self.genesis_hash
+          || btime > self.block[&self.base].time)
+      {
         self.pending.insert(bhash, block.clone());
+        self.pending_at.insert(bhash, get_time());
         self.wait_list.entry(phash).or_insert_with(|| Vec::new()).push(bhash);
         emit_event!(
           self.event_emitter,
@@ -1025,25 +2469,227 @@ impl<C: ProtoComm> Node<C> {
         );
       }
     }
+    accepted
   }
 
-  pub fn compute_block(&mut self, block: &HashedBlock) {
-    let transactions = extract_transactions(&block.body);
-    let mut statements = Vec::new();
-    for transaction in transactions {
-      if let Some(statement) = transaction.to_statement() {
-        statements.push(statement);
+  /// Whether a block, child of `phash`, agrees with the imported state: at
+  /// the state's height, only the block it was taken at is accepted, as the
+  /// blocks after it are computed on top of it.
+  fn fits_imported_state(&self, bhash: U256, phash: U256) -> bool {
+    match self.state_block {
+      Some(state_block) => {
+        let height = self.height[&phash].saturating_add(1);
+        height as u64 != self.state_floor || bhash == state_block
       }
+      None => true,
     }
-    let bhash = U256::from(block.get_hash());
-    self.runtime.set_time(block.time >> 8);
-    self.runtime.set_meta(block.meta >> 8);
-    self.runtime.set_hax0((bhash >> 000).low_u128() >> 8);
-    self.runtime.set_hax1((bhash >> 120).low_u128() >> 8);
-    self.runtime.open();
-    let result = self.runtime.run_statements(&statements, false, false);
+  }
+
+  pub fn compute_block(&mut self, block: &HashedBlock) {
+    let bhash: U256 = block.get_hash().into();
+    let trusted = match &self.net_config.trusted_checkpoint {
+      Some(checkpoint) => self.height[&bhash] as u64 <= checkpoint.height,
+      None => false,
+    };
+    let started = std::time::Instant::now();
+    let result = if trusted {
+      // Blocks up to the checkpoint are trusted, so they only advance the tick
+      self.runtime.open();
+      self.runtime.commit();
+      vec![]
+    } else {
+      run_block(&mut self.runtime, block)
+    };
+    self.record_block_timing(bhash, started.elapsed().as_millis(), &result);
+    // Deployments change the function directory
+    let is_deploy =
+      |r: &StatementResult| matches!(r, Ok(StatementInfo::Fun { .. }));
+    if result.iter().any(is_deploy) {
+      self.functions = None;
+    }
+    self.publish_results(block, &result);
+    // A failing statement undoes the whole draw heap, so when a block's first
+    // statement fails, the tick its block opened is undone with it
+    let lost = self.lost_ticks.get(&block.prev).copied().unwrap_or(0);
+    let lost = lost + matches!(result.first(), Some(Err(_))) as u64;
+    self.lost_ticks.insert(bhash, lost);
     self.results.insert(bhash, result);
-    self.runtime.commit();
+    self.ticks.insert(bhash, self.runtime.get_tick());
+  }
+
+  /// Subscribes to the results of the statements of every block computed
+  /// from now on, or only of those that mention `filter`.
+  pub fn subscribe_results(
+    &mut self,
+    filter: Option<Name>,
+  ) -> Result<api::ResultsRecv, String> {
+    self.results_subs.retain(|(_, tx)| !tx.is_closed());
+    if self.results_subs.len() >= MAX_RESULTS_SUBSCRIBERS {
+      let err = format!(
+        "Too many subscribers; at most {} streams are served at once.",
+        MAX_RESULTS_SUBSCRIBERS
+      );
+      return Err(err);
+    }
+    let (tx, rx) = tokio::sync::mpsc::channel(RESULTS_STREAM_BUFFER);
+    self.results_subs.push((filter, tx));
+    Ok(rx)
+  }
+
+  // Sends a computed block's results to the subscribers interested in them,
+  // forgetting the ones that went away or fell behind, so that slow readers
+  // can't make the node buffer results without bound
+  fn publish_results(
+    &mut self,
+    block: &HashedBlock,
+    results: &[StatementResult],
+  ) {
+    if self.results_subs.is_empty() {
+      return;
+    }
+    let bhash = U256::from(block.get_hash());
+    let statements: Vec<Statement> = extract_transactions(&block.body)
+      .iter()
+      .filter_map(|tx| tx.to_statement())
+      .collect();
+    self.results_subs.retain(|(filter, tx)| {
+      for (statement, result) in statements.iter().zip(results) {
+        if let Some(name) = filter {
+          if !statement_mentions(statement, *name) {
+            continue;
+          }
+        }
+        if tx.try_send((bhash, result.clone())).is_err() {
+          return false;
+        }
+      }
+      !tx.is_closed()
+    });
+  }
+
+  // Keeps how long a block took to compute, logging it if it was slow
+  fn record_block_timing(
+    &mut self,
+    bhash: U256,
+    millis: u128,
+    results: &[StatementResult],
+  ) {
+    let height = self.height[&bhash] as u64;
+    let mana = results_mana(results);
+    if millis > SLOW_BLOCK_TIME {
+      eprintln!(
+        "WARN: block {:#34x} (height {}) took {} ms to compute, using {} mana.",
+        bhash, height, millis, mana
+      );
+    }
+    if self.block_timings.len() >= BLOCK_TIMINGS_KEPT {
+      self.block_timings.pop_front();
+    }
+    let millis = millis as u64;
+    let timing = api::BlockTiming { hash: bhash.into(), height, millis, mana };
+    self.block_timings.push_back(timing);
+  }
+
+  /// The `count` slowest blocks to compute among the latest ones, slowest
+  /// first.
+  pub fn get_slowest_blocks(&self, count: usize) -> Vec<api::BlockTiming> {
+    let mut timings: Vec<_> = self.block_timings.iter().cloned().collect();
+    timings.sort_by_key(|timing| std::cmp::Reverse(timing.millis));
+    timings.truncate(count);
+    timings
+  }
+
+  /// Hash of the first block of the period `phash` closes, walking back
+  /// `BLOCKS_PER_PERIOD - 1` blocks. Periods start at height 1 and chains
+  /// only start on period starts, so the walk shouldn't need to go past the
+  /// base. If it would, the walk stops there, using the base as the
+  /// checkpoint.
+  pub fn period_checkpoint(&self, phash: U256) -> U256 {
+    let mut checkpoint_hash = phash;
+    for _ in 0..BLOCKS_PER_PERIOD - 1 {
+      if checkpoint_hash == self.base {
+        break;
+      }
+      checkpoint_hash = self.block[&checkpoint_hash].prev;
+    }
+    checkpoint_hash
+  }
+
+  /// Whether a block, child of `phash` and with `work`, agrees with the
+  /// trusted checkpoint: at its height, only the checkpoint itself is
+  /// accepted, and only if the chain up to it has the expected work.
+  fn fits_checkpoint(&self, bhash: U256, phash: U256, work: U256) -> bool {
+    let checkpoint = match &self.net_config.trusted_checkpoint {
+      Some(checkpoint) => checkpoint,
+      None => return true,
+    };
+    let height = self.height[&phash].saturating_add(1);
+    if height as u64 != checkpoint.height {
+      return true;
+    }
+    if bhash != U256::from(checkpoint.hash) {
+      return false;
+    }
+    let total_work = self.work[&phash].saturating_add(work);
+    if total_work != U256::from(checkpoint.work) {
+      eprintln!(
+        "WARN: the trusted checkpoint doesn't have the configured work; \
+        refusing it."
+      );
+      return false;
+    }
+    true
+  }
+
+  /// Replays the main chain blocks up to height `end` on a fresh runtime,
+  /// stored at `heaps_path`, and returns it with the results of the blocks
+  /// from height `start` on. Blocks below `start` are run too, as the range
+  /// is computed on the state they leave. The live runtime is left
+  /// untouched, which helps reproducing consensus bugs.
+  pub fn replay_range(
+    &self,
+    start: u64,
+    end: u64,
+    heaps_path: PathBuf,
+  ) -> Result<(Runtime, Vec<Vec<StatementResult>>), String> {
+    let tip_height = self.height[&self.tip] as u64;
+    if start > end || end > tip_height {
+      return Err(format!(
+        "Invalid range {}..{}; the tip is at height {}.",
+        start, end, tip_height
+      ));
+    }
+    if self.base != self.genesis_hash {
+      return Err(format!(
+        "The node doesn't hold the blocks below height {}.",
+        self.prune_horizon()
+      ));
+    }
+    // Collects the chain up to `end`, walking back from the tip
+    let mut blocks = Vec::new();
+    let mut bhash = self.tip;
+    for height in (1..=tip_height).rev() {
+      let block = self.block.get(&bhash).ok_or_else(|| {
+        format!("The node doesn't hold the block at height {}.", height)
+      })?;
+      if height <= end {
+        blocks.push(block);
+      }
+      bhash = block.prev;
+    }
+    // The genesis block is already run by `init_runtime`
+    let genesis_stmts =
+      hvm::parse_code(constants::GENESIS_CODE).expect("Genesis code parses");
+    let mut runtime = init_runtime(heaps_path, &genesis_stmts)?;
+    runtime.set_statement_mana(self.net_config.statement_mana);
+    let mut results = Vec::new();
+    for (height, block) in (1..).zip(blocks.iter().rev()) {
+      let result = run_block(&mut runtime, block);
+      if height >= start {
+        results.push(result);
+      }
+    }
+    Ok((runtime, results))
   }
 
   // Get the current target
@@ -1051,6 +2697,104 @@ impl<C: ProtoComm> Node<C> {
     self.target[&self.tip]
   }
 
+  /// Whether the node is still catching up with the network, so mining
+  /// would likely produce orphans on a stale tip. That is the case until a
+  /// peer gossiped its tip (or the grace period for lone nodes ends), while
+  /// a tip recently gossiped by a peer has more work than ours and is more
+  /// than `SYNC_HEIGHT_MARGIN` blocks above it, and while blocks peers sent
+  /// wait for their missing ancestors, for up to `SYNC_TIMEOUT` after the
+  /// oldest of them arrived.
+  pub fn is_syncing(&self) -> bool {
+    self.is_syncing_at(get_time())
+  }
+
+  /// Like `is_syncing`, as of time `now`.
+  pub fn is_syncing_at(&self, now: u128) -> bool {
+    if !self.heard_tip {
+      return now.saturating_sub(self.started_at) < SYNC_GRACE_PERIOD;
+    }
+    let tip_height = self.height[&self.tip];
+    let tip_work = self.work[&self.tip];
+    let behind_peer = self.peer_tips.values().any(|(bhash, seen_at)| {
+      now.saturating_sub(*seen_at) <= PEER_TIP_WINDOW
+        && self.height.get(bhash).map_or(false, |height| {
+          *height > tip_height.saturating_add(SYNC_HEIGHT_MARGIN)
+            && self.work[bhash] > tip_work
+        })
+    });
+    if behind_peer {
+      return true;
+    }
+    match self.pending_at.values().min() {
+      Some(oldest) => now.saturating_sub(*oldest) < SYNC_TIMEOUT,
+      None => false,
+    }
+  }
+
+  /// Seconds elapsed since the tip last advanced.
+  pub fn seconds_since_last_block(&self) -> u64 {
+    (get_time().saturating_sub(self.tip_time) / 1000) as u64
+  }
+
+  /// Estimates the network hash rate, in hashes per second, by averaging the
+  /// difficulty of the last `window` blocks over the time they took to mine.
+  pub fn estimate_hash_rate(&self, window: usize) -> U256 {
+    let tip_time = self.block[&self.tip].time;
+    let mut bhash = self.tip;
+    let mut total_work = u256(0);
+    let mut count = 0;
+    // Stops before the genesis block, since its timestamp is not meaningful
+    while count < window
+      && bhash != self.base
+      && self.block[&bhash].prev != self.genesis_hash
+    {
+      let phash = self.block[&bhash].prev;
+      total_work =
+        total_work.saturating_add(target_to_difficulty(self.target[&phash]));
+      bhash = phash;
+      count += 1;
+    }
+    let base_time = self.block[&bhash].time;
+    if count == 0 || tip_time <= base_time {
+      return u256(0);
+    }
+    total_work.saturating_mul(u256(1000)) / u256(tip_time - base_time)
+  }
+
+  // Keeps `recent_blocks` in sync with the tip: extends it when the tip
+  // builds on the last cached block, and rebuilds it after a reorg.
+  fn update_recent_blocks(&mut self) {
+    let size = self.api_config.recent_blocks;
+    let prev = self.block[&self.tip].prev;
+    let last = self.recent_blocks.back().map(|(hash, _)| *hash);
+    if last == Some(prev) {
+      let info = self.get_block_info(&self.tip).expect("Missing block.");
+      self.recent_blocks.push_back((self.tip, info));
+      while self.recent_blocks.len() > size {
+        self.recent_blocks.pop_front();
+      }
+    } else {
+      self.recent_blocks = self
+        .get_longest_chain(Some(size))
+        .into_iter()
+        .map(|h| (h, self.get_block_info(&h).expect("Missing block.")))
+        .collect();
+    }
+  }
+
+  /// The last `num` blocks of the main chain, oldest first, if cached.
+  pub fn get_recent_blocks(&self, num: usize) -> Option<Vec<BlockInfo>> {
+    let cached = self.recent_blocks.len();
+    let chain_len = self.height[&self.tip] - self.height[&self.base] + 1;
+    let whole_chain = cached as u128 == chain_len;
+    if num > cached && !whole_chain {
+      return None;
+    }
+    let skip = cached.saturating_sub(num);
+    let infos = self.recent_blocks.iter().skip(skip).map(|(_, i)| i.clone());
+    Some(infos.collect())
+  }
+
   pub fn get_longest_chain(&self, max: Option<usize>) -> Vec<U256> {
     let mut longest = Vec::new();
     let mut bhash = self.tip;
@@ -1071,25 +2815,45 @@ impl<C: ProtoComm> Node<C> {
   }
 
   pub fn receive_message(&mut self) {
+    // The messages vector is taken out while they're handled, and put back
+    // empty, so its allocation is reused on the next call
+    let mut messages = std::mem::take(&mut self.recv_messages);
+    self.comm.proto_recv(&mut self.recv_buffer, &mut messages);
     let mut count = 0;
-    for (addr, msg) in self.comm.proto_recv() {
+    for (addr, msg) in messages.drain(..) {
       //if count < HANDLE_MESSAGE_LIMIT {  TODO: ???
       self.handle_message(addr, &msg);
       count = count + 1;
       //}
     }
+    self.recv_messages = messages;
   }
 
+  /// Handles the queued API requests, up to `api_config.requests_per_run`
+  /// of them, so a burst of requests can't starve block processing.
   fn receive_request(&mut self) {
-    if let Ok(request) = self.query_recv.try_recv() {
-      self.handle_request(request);
+    for _ in 0..self.api_config.requests_per_run {
+      match self.query_recv.try_recv() {
+        Ok(request) => self.handle_request(request),
+        Err(_) => break,
+      }
     }
   }
 
+  /// Work accumulated by the chain up to the block `hash`, if known.
+  pub fn get_block_work(&self, hash: &U256) -> Option<U256> {
+    self.work.get(hash).copied()
+  }
+
+  /// Target the block `hash` had to meet, if known.
+  pub fn get_block_target(&self, hash: &U256) -> Option<U256> {
+    self.target.get(hash).copied()
+  }
+
   pub fn get_block_hash_by_index(&self, index: u64) -> Option<U256> {
     let mut hsh = self.tip;
     let mut idx = self.height[&hsh] as u64;
-    if index > idx {
+    if index > idx || (index as u128) < self.height[&self.base] {
       return None;
     }
     while index < idx {
@@ -1099,21 +2863,612 @@ impl<C: ProtoComm> Node<C> {
     return Some(hsh);
   }
 
-  pub fn get_block_info(&self, hash: &U256) -> Option<BlockInfo> {
-    // TODO: cache
-    let block = self.block.get(hash)?;
-    let height = self.height.get(hash).expect("Missing block height.");
-    let height: u64 = (*height).try_into().expect("Block height is too big.");
-    let results = self.results.get(hash).map(|r| r.clone());
+  /// Hash of the main chain block `offset` blocks behind the tip, e.g. 6 for
+  /// a block with 6 confirmations. `None` if the chain isn't that long.
+  pub fn get_block_hash_from_tip(&self, offset: u64) -> Option<U256> {
+    let tip_height = self.height[&self.tip] as u64;
+    let index = tip_height.checked_sub(offset)?;
+    self.get_block_hash_by_index(index)
+  }
+
+  /// Total mana spent by the statements of the main chain blocks from height
+  /// `start` to `end`, inclusive. Heights past the tip are ignored.
+  pub fn get_mana_usage(&self, start: u64, end: u64) -> u64 {
+    let end = std::cmp::min(end, self.height[&self.tip] as u64);
+    let start = std::cmp::max(start, self.height[&self.base] as u64);
+    if start > end {
+      return 0;
+    }
+    let mut bhash = match self.get_block_hash_by_index(end) {
+      Some(bhash) => bhash,
+      None => return 0,
+    };
+    let mut total: u64 = 0;
+    for _ in start..=end {
+      if let Some(results) = self.results.get(&bhash) {
+        total = total.saturating_add(results_mana(results));
+      }
+      bhash = self.block[&bhash].prev;
+    }
+    total
+  }
+
+  pub fn get_block_info(&self, hash: &U256) -> Option<BlockInfo> {
+    // TODO: cache
+    let block = self.block.get(hash)?;
+    let height = self.height.get(hash).expect("Missing block height.");
+    let height: u64 = (*height).try_into().expect("Block height is too big.");
+    let results = self.results.get(hash).map(|r| r.clone());
+    let tick = self.ticks.get(hash).copied();
     let info = BlockInfo {
       block: (&**block).into(),
       hash: (*hash).into(),
       height,
       results,
+      tick,
     };
     Some(info)
   }
 
+  /// Finds the highest block that is an ancestor of both `a` and `b` (a block
+  /// counts as its own ancestor). Returns `None` if either block is unknown,
+  /// or has no path to the other, like refused blocks.
+  pub fn common_ancestor(&self, a: U256, b: U256) -> Option<U256> {
+    let mut a = a;
+    let mut b = b;
+    // Walks the highest one back until both have the same height
+    while self.height.get(&a)? > self.height.get(&b)? {
+      a = self.block.get(&a)?.prev;
+    }
+    while self.height.get(&b)? > self.height.get(&a)? {
+      b = self.block.get(&b)?.prev;
+    }
+    // Then walks both back until they meet
+    while a != b {
+      a = self.block.get(&a)?.prev;
+      b = self.block.get(&b)?.prev;
+    }
+    Some(a)
+  }
+
+  /// Makes `new_tip` the tip if it's better than the current one, see
+  /// `is_better_tip`, moving the runtime to its timeline. `new_tip` must be
+  /// an accepted block, with its work, height and target set.
+  pub fn maybe_reorg(&mut self, new_tip: U256) {
+    if self.is_better_tip(&new_tip, &self.tip) {
+      if let Err(err) = self.can_reorg_to(new_tip) {
+        eprintln!("WARN: not moving to a better tip. {}", err);
+        return;
+      }
+      self.reorg_to(new_tip);
+    }
+  }
+
+  /// Checks that the runtime can move to `new_tip`'s timeline. An imported
+  /// state can't be rolled back, so a fork branching off the current chain
+  /// below it would be computed on top of the wrong state. The runtime is
+  /// rolled back to its newest snapshot at or before the fork, so that
+  /// snapshot can't be below the imported state either, nor below the base,
+  /// as the blocks before it are gone. Extending the current chain is always
+  /// fine.
+  fn can_reorg_to(&self, new_tip: U256) -> Result<(), String> {
+    let common = self
+      .common_ancestor(self.tip, new_tip)
+      .ok_or_else(|| format!("Block {:#x} is not known.", new_tip))?;
+    if common == self.tip {
+      return Ok(());
+    }
+    let height = self.height[&common] as u64;
+    if height < self.state_floor {
+      return Err(format!(
+        "Block {:#x} forks from height {}, below the imported state, at \
+        height {}.",
+        new_tip, height, self.state_floor
+      ));
+    }
+    let tick = self.rollback_tick(self.tip, common);
+    let snapshot = self.runtime.get_rollback_tick(tick);
+    let lowest = if self.base != self.genesis_hash {
+      std::cmp::max(self.state_floor, self.block_tick(&self.base))
+    } else {
+      self.state_floor
+    };
+    if snapshot < lowest {
+      return Err(format!(
+        "Block {:#x} forks from height {}, but the runtime can only roll \
+        back to tick {}.",
+        new_tip, height, snapshot
+      ));
+    }
+    Ok(())
+  }
+
+  // Runtime tick after computing a block. Blocks reflected on an imported
+  // state weren't computed here, and none of their ticks were lost.
+  fn block_tick(&self, bhash: &U256) -> u64 {
+    match self.ticks.get(bhash) {
+      Some(tick) => *tick,
+      None => self.height[bhash] as u64,
+    }
+  }
+
+  // Tick to roll the runtime back to, so that it reflects `common` and none
+  // of the blocks after it on the timeline of `tip`. A block whose first
+  // statement fails leaves the tick where its parent did, so when the block
+  // after `common` does, their states can't be told apart and the runtime
+  // goes a tick further back.
+  fn rollback_tick(&self, tip: U256, common: U256) -> u64 {
+    let tick = self.block_tick(&common);
+    let mut bhash = tip;
+    while bhash != common {
+      if self.block_tick(&bhash) == tick {
+        return tick.saturating_sub(1);
+      }
+      bhash = self.block[&bhash].prev;
+    }
+    tick
+  }
+
+  /// Makes `new_tip` the tip, whatever its work, and moves the runtime to its
+  /// timeline, rolling back and computing blocks as needed.
+  fn reorg_to(&mut self, new_tip: U256) {
+    let cur_tip = self.tip;
+    // When the tip updates, stop mining the last built block, which is
+    // based on the outdated tip
+    self.send_to_miner(MinerMessage::Stop);
+    emit_event!(
+      self.event_emitter,
+      NodeEventType::stop_mining(),
+      tags = mining,
+      stopped
+    );
+    // Removes this block's transactions from mempool
+    for tx in extract_transactions(&self.block[&new_tip].body) {
+      self.pool.remove(&tx);
+    }
+    self.move_tip(new_tip);
+    // Block reorganization (* marks blocks for which we have runtime snapshots):
+    // tick: |  0 | *1 |  2 |  3 |  4 | *5 |  6 | *7 | *8 |
+    // hash: |  A |  B |  C |  D |  E |  F |  G |  H |    |  <- old timeline
+    // hash: |  A |  B |  C |  D |  P |  Q |  R |  S |  T |  <- new timeline
+    //               |         '-> highest common block shared by both timelines
+    //               '-----> highest runtime snapshot before block D
+    let mut must_compute = Vec::new();
+    // 1. Finds highest block shared by both timelines
+    //    On the example above, we'd have `D`
+    let old_bhash = self
+      .common_ancestor(cur_tip, new_tip)
+      .expect("Both tips are known blocks");
+    // 2. Collects the blocks of the new timeline above it
+    //    On the example above, we'd have `T, S, R, Q, P`
+    let mut new_bhash = new_tip;
+    while new_bhash != old_bhash {
+      must_compute.push(new_bhash);
+      new_bhash = self.block[&new_bhash].prev;
+    }
+    // Moves the transaction index to the new timeline
+    self.reindex_transactions(cur_tip, old_bhash, &must_compute);
+    // 3. Saves overwritten blocks to disk
+    // TODO: on separate thread
+    for bhash_comp in must_compute.iter().rev() {
+      self.save_block_file(*bhash_comp);
+    }
+    // 4. Reverts the runtime to a state older than that block
+    //    On the example above, we'd find `runtime.tick = 1`
+    let tick = self.rollback_tick(cur_tip, old_bhash);
+
+    let runtime_old_tick = self.runtime.get_tick();
+    //    An imported state is trusted and can't be reverted. Only extensions
+    //    of the current chain get here while it's ahead of the tip; see
+    //    `can_reorg_to`
+    let floor = self.state_floor;
+    self.runtime.rollback(std::cmp::max(tick, floor));
+    self.functions = None;
+
+    let old = (&self.block[&cur_tip], self.height[&cur_tip]);
+    let new = (&self.block[&new_tip], self.height[&new_tip]);
+    let common = (&self.block[&old_bhash], self.height[&old_bhash]); // common ancestor
+    let ticks = (runtime_old_tick as u128, self.runtime.get_tick() as u128);
+    let work = get_hash_work(new_tip);
+    emit_event!(
+      self.event_emitter,
+      NodeEventType::reorg(old, new, common, ticks, work),
+      tags = add_block,
+      reorg
+    );
+
+    // 5. Finds the last block included on the reverted runtime state
+    //    On the example above, we'd find `new_bhash = B`
+    while self.block_tick(&new_bhash) > self.runtime.get_tick() {
+      must_compute.push(new_bhash);
+      new_bhash = self.block[&new_bhash].prev;
+    }
+    emit_event!(
+      self.event_emitter,
+      NodeEventType::computed(
+        &self.block[&new_tip],
+        self.height[&self.tip],
+        &must_compute
+      ),
+      tags = add_block,
+      computed
+    ); // emitting computed blocks for measurement
+
+    // 6. Computes every block after that on the new timeline
+    //    On the example above, we'd compute `C, D, P, Q, R, S, T`
+    for bhash_comp in must_compute.iter().rev() {
+      // Skips blocks already reflected on an imported state
+      if self.height[bhash_comp] as u64 <= self.state_floor {
+        continue;
+      }
+      let block_comp = &self.block[bhash_comp];
+      self.compute_block(&block_comp.clone()); // TODO: avoid clone
+    }
+    self.update_recent_blocks();
+    self.prune();
+    debug_assert!(
+      self.tick_matches_tip(),
+      "runtime tick {} doesn't match the tip height {}",
+      self.runtime.get_tick(),
+      self.height[&self.tip]
+    );
+  }
+
+  /// On pruned nodes, moves the base up to about `prune_depth` blocks behind
+  /// the tip, dropping the blocks below it, with their results, and the
+  /// forks branching off below it. Like on truncated chains, the base is the
+  /// first block of a retarget period, see `suffix_start`, and it never
+  /// passes `rollback_floor`, so that rolling the runtime back never needs a
+  /// dropped block. The genesis block is kept, so forks from below the base
+  /// link up to it and are refused. Block files are removed below both the
+  /// base and the retarget period of the imported state, as the runtime is
+  /// rebuilt from that state on restarts.
+  pub fn prune(&mut self) {
+    if self.storage != StorageMode::Pruned {
+      return;
+    }
+    let tip_height = self.height[&self.tip] as u64;
+    let floor = self.rollback_floor();
+    let start = match suffix_start(tip_height, self.prune_depth, floor) {
+      Some(start) => start,
+      None => return,
+    };
+    if start as u128 <= self.height[&self.base] {
+      return;
+    }
+    let new_base = match self.get_block_hash_by_index(start) {
+      Some(bhash) => bhash,
+      None => return,
+    };
+    let old_start = self.height[&self.base] as u64;
+    // Every block not descending from the new base is dropped, but genesis
+    let mut dropped = Vec::new();
+    let mut stack = vec![self.base];
+    while let Some(bhash) = stack.pop() {
+      if bhash == new_base {
+        continue;
+      }
+      if let Some(children) = self.children.remove(&bhash) {
+        stack.extend(children);
+      }
+      if bhash != self.genesis_hash {
+        dropped.push(bhash);
+      }
+    }
+    self.children.insert(self.genesis_hash, vec![]);
+    for bhash in &dropped {
+      self.block.remove(bhash);
+      self.work.remove(bhash);
+      self.target.remove(bhash);
+      self.height.remove(bhash);
+      self.results.remove(bhash);
+      self.ticks.remove(bhash);
+      self.lost_ticks.remove(bhash);
+    }
+    let block = &self.block;
+    self.tx_index.retain(|_, bhash| block.contains_key(bhash));
+    self.unsaved_blocks = self.unsaved_blocks.split_off(&(start as u128));
+    self.base = new_base;
+    // `load_blocks` starts on the imported state's period, so the files below
+    // it are never read again
+    let end = std::cmp::min(start, period_start(self.state_floor));
+    for height in std::cmp::max(old_start, 1)..end {
+      let path = self.get_block_file_path(height as u128);
+      if let Err(err) = std::fs::remove_file(&path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+          eprintln!(
+            "WARN: Could not remove block file '{}': {}",
+            path.display(),
+            err
+          );
+        }
+      }
+    }
+  }
+
+  // Lowest tick the runtime can roll back to without being recomputed from
+  // genesis: that of its oldest snapshot, or of the imported state, which
+  // can't be rolled back
+  fn rollback_floor(&self) -> u64 {
+    let oldest = self.runtime.get_oldest_snapshot_tick().unwrap_or(0);
+    std::cmp::max(oldest, self.state_floor)
+  }
+
+  /// Height of the oldest block the node holds. Blocks, results and replays
+  /// below it can't be served: they were pruned or never loaded.
+  pub fn prune_horizon(&self) -> u64 {
+    self.height[&self.base] as u64
+  }
+
+  pub fn get_chain_params(&self) -> api::ChainParams {
+    let prune_depth = match self.storage {
+      StorageMode::Archive => None,
+      StorageMode::Pruned => Some(self.prune_depth),
+    };
+    api::ChainParams {
+      network_id: self.network_id,
+      genesis_hash: self.genesis_hash.into(),
+      storage: self.storage,
+      prune_depth,
+      prune_horizon: self.prune_horizon(),
+    }
+  }
+
+  /// Whether the runtime has computed exactly the main chain up to the tip.
+  /// An imported state can be ahead of the tip while the node catches up.
+  pub fn tick_matches_tip(&self) -> bool {
+    let tip_height = self.height[&self.tip] as u64;
+    // Blocks up to the imported state weren't computed here, so they have no
+    // count and none of them were lost
+    let lost = self.lost_ticks.get(&self.tip).copied().unwrap_or(0);
+    let expected = tip_height.saturating_sub(lost);
+    self.runtime.get_tick() == std::cmp::max(expected, self.state_floor)
+  }
+
+  pub fn get_health(&self) -> api::Health {
+    api::Health {
+      tick: self.runtime.get_tick(),
+      tip_height: self.height[&self.tip] as u64,
+      state_floor: self.state_floor,
+      consistent: self.tick_matches_tip(),
+      seconds_since_last_block: self.seconds_since_last_block(),
+    }
+  }
+
+  /// Forces the tip to a known block, even one with less work than the
+  /// current tip. Meant for recovering a node stuck on a bad fork. A heavier
+  /// chain still takes over once one of its blocks arrives. Refused while
+  /// block processing is paused.
+  pub fn set_tip(&mut self, bhash: U256) -> Result<(), String> {
+    if self.is_paused() {
+      return Err("Block processing is paused.".to_string());
+    }
+    let height = match self.height.get(&bhash) {
+      Some(height) if self.block.contains_key(&bhash) => *height as u64,
+      _ => return Err(format!("Block {:#x} is not known.", bhash)),
+    };
+    if bhash != self.tip {
+      self.can_reorg_to(bhash)?;
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::tip_forced(bhash, height),
+        tags = status,
+        tip_forced
+      );
+      self.reorg_to(bhash);
+    }
+    Ok(())
+  }
+
+  // Whether `new_tip` should replace `cur_tip`: the one with more work wins; on
+  // equal work, the configured tiebreak rule decides.
+  fn is_better_tip(&self, new_tip: &U256, cur_tip: &U256) -> bool {
+    let (new_work, cur_work) = (self.work[new_tip], self.work[cur_tip]);
+    if new_work != cur_work {
+      return new_work > cur_work;
+    }
+    match self.net_config.tip_tiebreak {
+      TipTiebreak::FirstSeen => false,
+      TipTiebreak::LowestHash => new_tip < cur_tip,
+    }
+  }
+
+  // Updates `tx_index` when the tip moves from `old_tip` to a new timeline.
+  // - Transactions of blocks orphaned by the reorg (from `old_tip` down to
+  //   `common`, exclusive) are removed from the index and, unless the new
+  //   timeline also includes them, moved back to the pool.
+  // - Transactions of `new_blocks` (the new timeline above `common`) are
+  //   indexed and removed from the pool.
+  // Each index entry costs two U256 (64 bytes) plus the map overhead.
+  fn reindex_transactions(
+    &mut self,
+    old_tip: U256,
+    common: U256,
+    new_blocks: &[U256],
+  ) {
+    let mut orphaned = Vec::new();
+    let mut bhash = old_tip;
+    while bhash != common {
+      for tx in extract_transactions(&self.block[&bhash].body) {
+        self.tx_index.remove(&tx.hash);
+        orphaned.push(tx);
+      }
+      bhash = self.block[&bhash].prev;
+    }
+    for bhash in new_blocks {
+      for tx in extract_transactions(&self.block[bhash].body) {
+        self.pool.remove(&tx);
+        self.tx_index.insert(tx.hash, *bhash);
+      }
+    }
+    for tx in orphaned {
+      if !self.tx_index.contains_key(&tx.hash) {
+        let _ = self.add_transaction(tx);
+      }
+    }
+  }
+
+  /// Summarizes the priority scores on the mempool, simulating which
+  /// transactions would fill the next two blocks.
+  pub fn get_fee_estimate(&self) -> api::FeeEstimate {
+    let mut txs: Vec<(u64, usize)> =
+      self.pool.iter().map(|(tx, score)| (*score, tx.data.len())).collect();
+    txs.sort_by_key(|tx| std::cmp::Reverse(tx.0));
+    let percentile = |p: usize| {
+      if txs.is_empty() {
+        0
+      } else {
+        txs[(txs.len() - 1) * (100 - p) / 100].0
+      }
+    };
+    // Fills the next blocks in priority order
+    let body_size = self.mine_config.target_body_bytes.unwrap_or(MAX_BODY_SIZE);
+    let mut thresholds = [0u64; 2];
+    let mut block = 0;
+    let mut used = 1; // transaction count byte
+    let mut last_score = 0;
+    for (score, len) in &txs {
+      if used + 2 + len > body_size {
+        thresholds[block] = last_score;
+        block += 1;
+        used = 1;
+        if block == thresholds.len() {
+          break;
+        }
+      }
+      used += 2 + len;
+      last_score = *score;
+    }
+    api::FeeEstimate {
+      pool_size: txs.len(),
+      min: percentile(0),
+      p25: percentile(25),
+      median: percentile(50),
+      p75: percentile(75),
+      max: percentile(100),
+      next_block: thresholds[0],
+      second_block: thresholds[1],
+    }
+  }
+
+  /// Finds whether a transaction is on the mempool or on the main chain.
+  pub fn get_transaction_status(&self, hash: &U256) -> api::TxStatus {
+    if let Some(bhash) = self.tx_index.get(hash) {
+      let height = self.height[bhash] as u64;
+      return api::TxStatus::Confirmed { block: (*bhash).into(), height };
+    }
+    if self.pool.iter().any(|(tx, _)| tx.hash == *hash) {
+      return api::TxStatus::Pending;
+    }
+    api::TxStatus::Unknown
+  }
+
+  /// Difficulty of the last `count` retarget periods of the main chain,
+  /// paired with the height each started at, oldest first. At most
+  /// `MAX_DIFFICULTY_HISTORY` periods are listed.
+  pub fn get_difficulty_history(&self, count: usize) -> Vec<(u64, U256)> {
+    let count = std::cmp::min(count, MAX_DIFFICULTY_HISTORY);
+    let mut history = Vec::new();
+    let mut bhash = self.tip;
+    while history.len() < count {
+      let height = self.height[&bhash];
+      if height == 0 {
+        break;
+      }
+      if height % BLOCKS_PER_PERIOD == 1 {
+        let difficulty = target_to_difficulty(self.target[&bhash]);
+        history.push((height as u64, difficulty));
+      }
+      if bhash == self.base {
+        break;
+      }
+      bhash = self.block[&bhash].prev;
+    }
+    history.reverse();
+    history
+  }
+
+  /// Finds a transaction by its hash, on the main chain or the mempool.
+  pub fn get_transaction(&self, hash: &U256) -> Option<Transaction> {
+    if let Some(bhash) = self.tx_index.get(hash) {
+      let block = self.block.get(bhash)?;
+      let mut txs = extract_transactions(&block.body).into_iter();
+      return txs.find(|tx| tx.hash == *hash);
+    }
+    self.pool.iter().map(|(tx, _)| tx).find(|tx| tx.hash == *hash).cloned()
+  }
+
+  /// Proves that a transaction is included in a main chain block, see
+  /// `api::TransactionProof`. `None` if it isn't on the main chain, or its
+  /// block is in a format version older than 1.
+  pub fn get_transaction_proof(
+    &self,
+    hash: &U256,
+  ) -> Option<api::TransactionProof> {
+    let bhash = self.tx_index.get(hash)?;
+    let block = self.block.get(bhash)?;
+    if block.version < 1 {
+      return None;
+    }
+    let transactions = extract_transactions(&block.body);
+    let index = transactions.iter().position(|tx| tx.hash == *hash)?;
+    let path = transaction_path(&block.body, index)?;
+    let header = block.header();
+    Some(api::TransactionProof {
+      transaction: (*hash).into(),
+      block: (*bhash).into(),
+      height: self.height[bhash] as u64,
+      version: header.version,
+      prev: header.prev.into(),
+      time: header.time,
+      meta: header.meta,
+      transactions_root: header.transactions_root.into(),
+      body_hash: header.body_hash.into(),
+      path: path.into_iter().map(|(hash, left)| (hash.into(), left)).collect(),
+    })
+  }
+
+  /// Lists the deployed functions whose names start with `prefix`, skipping
+  /// the first `offset` ones and returning at most `limit`. Names are sorted
+  /// alphabetically, so consecutive pages don't overlap nor skip names, as
+  /// long as no function sorting before the page is deployed in between.
+  pub fn get_functions(
+    &mut self,
+    offset: usize,
+    limit: Option<usize>,
+    prefix: Option<&str>,
+  ) -> Vec<Name> {
+    if self.functions.is_none() {
+      self.functions = Some(self.scan_functions());
+    }
+    let names = self.functions.as_ref().unwrap();
+    let limit = limit.unwrap_or(usize::MAX);
+    names
+      .iter()
+      .filter(|(text, _)| prefix.map_or(true, |p| text.starts_with(p)))
+      .skip(offset)
+      .take(limit)
+      .map(|(_, name)| *name)
+      .collect()
+  }
+
+  // Collects the deployed function names from the runtime, sorted
+  fn scan_functions(&self) -> Vec<(String, Name)> {
+    let mut funcs: HashSet<u128> = HashSet::new();
+    self.runtime.reduce_with(&mut funcs, |acc, heap| {
+      for func in heap.disk.links.keys() {
+        acc.insert(**func);
+      }
+    });
+    let mut names: Vec<(String, Name)> = funcs
+      .into_iter()
+      .map(Name::new_unsafe)
+      .map(|name| (name.to_string(), name))
+      .collect();
+    names.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    names
+  }
+
   pub fn get_func_info(&self, name: &Name) -> Option<FuncInfo> {
     let comp_func = self.runtime.read_file(name)?;
     let func = comp_func.func;
@@ -1166,6 +3521,7 @@ impl<C: ProtoComm> Node<C> {
         self.runtime.reduce_with(&mut reg_count, |acc, heap| {
           *acc += heap.get_ns_count();
         });
+        let hash_rate = self.estimate_hash_rate(HASH_RATE_WINDOW).low_u64();
         let stats = api::Stats {
           tick,
           mana,
@@ -1173,6 +3529,14 @@ impl<C: ProtoComm> Node<C> {
           fun_count,
           ctr_count,
           reg_count,
+          hash_rate,
+          seconds_since_last_block: self.seconds_since_last_block(),
+          syncing: self.is_syncing(),
+          chain_split: self.is_chain_split(get_time()),
+          degraded: self.is_degraded(),
+          messages: self.message_counts.clone(),
+          message_rate: self.message_rate,
+          slowest_blocks: self.get_slowest_blocks(SLOWEST_BLOCKS_REPORTED),
         };
         handle_ans_err("GetStats", tx.send(stats));
       }
@@ -1181,28 +3545,90 @@ impl<C: ProtoComm> Node<C> {
         debug_assert!(start <= end);
         debug_assert!(end == -1);
         let num = (end - start + 1) as usize;
-        let hashes = self.get_longest_chain(Some(num));
-        let infos = hashes
-          .iter()
-          .map(|h| self.get_block_info(h).expect("Missing block."))
-          .collect();
+        let infos = self.get_recent_blocks(num).unwrap_or_else(|| {
+          let hashes = self.get_longest_chain(Some(num));
+          hashes
+            .iter()
+            .map(|h| self.get_block_info(h).expect("Missing block."))
+            .collect()
+        });
         handle_ans_err("GetBlocks", tx.send(infos));
       }
       NodeRequest::GetBlock { hash, tx } => {
         let info = self.get_block_info(&hash);
         handle_ans_err("GetBlock", tx.send(info));
       }
+      NodeRequest::GetBlockFromTip { offset, tx } => {
+        let info = self
+          .get_block_hash_from_tip(offset)
+          .and_then(|hash| self.get_block_info(&hash));
+        handle_ans_err("GetBlockFromTip", tx.send(info));
+      }
+      NodeRequest::GetManaUsage { range: (start, end), tx } => {
+        let mana = self.get_mana_usage(start, end);
+        handle_ans_err("GetManaUsage", tx.send(mana));
+      }
+      NodeRequest::GetHealth { tx } => {
+        handle_ans_err("GetHealth", tx.send(self.get_health()));
+      }
+      NodeRequest::GetChainParams { tx } => {
+        handle_ans_err("GetChainParams", tx.send(self.get_chain_params()));
+      }
       NodeRequest::GetBlockHash { index, tx } => {
         let info = self.get_block_hash_by_index(index);
         handle_ans_err("GetBlockHash", tx.send(info));
       }
-      NodeRequest::GetFunctions { tx } => {
-        let mut funcs: HashSet<u128> = HashSet::new();
-        self.runtime.reduce_with(&mut funcs, |acc, heap| {
-          for func in heap.disk.links.keys() {
-            acc.insert(**func);
-          }
-        });
+      NodeRequest::GetTip { tx } => {
+        let tip = (self.tip, self.height[&self.tip] as u64);
+        handle_ans_err("GetTip", tx.send(tip));
+      }
+      #[cfg(any(test, feature = "test-api"))]
+      NodeRequest::AddBlock { block, tx } => {
+        self.add_block(&block.hashed());
+        handle_ans_err("AddBlock", tx.send(()));
+      }
+      NodeRequest::GetBlockRaw { hash, tx } => {
+        let bytes = self.block.get(&hash).map(|block| block.to_bytes());
+        handle_ans_err("GetBlockRaw", tx.send(bytes));
+      }
+      NodeRequest::GetChildren { hash, tx } => {
+        let children = self.children.get(&hash).cloned();
+        handle_ans_err("GetChildren", tx.send(children));
+      }
+      NodeRequest::GetBlockSource { hash, tx } => {
+        let source = self.block_sources.get(&hash).copied();
+        handle_ans_err("GetBlockSource", tx.send(source));
+      }
+      NodeRequest::RequestBlock { tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't request blocks on demand.".to_string();
+        handle_ans_err("RequestBlock", tx.send(Err(err)));
+      }
+      NodeRequest::RequestBlock { bhash, tx } => {
+        let result = self.request_block_from_peers(bhash);
+        handle_ans_err("RequestBlock", tx.send(result));
+      }
+      NodeRequest::GetBlockWork { hash, tx } => {
+        let work = self.get_block_target(&hash).zip(self.get_block_work(&hash));
+        handle_ans_err("GetBlockWork", tx.send(work));
+      }
+      NodeRequest::GetTransactionStatus { hash, tx } => {
+        let status = self.get_transaction_status(&hash);
+        handle_ans_err("GetTransactionStatus", tx.send(status));
+      }
+      NodeRequest::GetTransaction { hash, tx } => {
+        let transaction = self.get_transaction(&hash);
+        handle_ans_err("GetTransaction", tx.send(transaction));
+      }
+      NodeRequest::GetTransactionProof { hash, tx } => {
+        let proof = self.get_transaction_proof(&hash);
+        handle_ans_err("GetTransactionProof", tx.send(proof));
+      }
+      NodeRequest::GetFeeEstimate { tx } => {
+        let estimate = self.get_fee_estimate();
+        handle_ans_err("GetFeeEstimate", tx.send(estimate));
+      }
+      NodeRequest::GetFunctions { offset, limit, prefix, tx } => {
+        let funcs = self.get_functions(offset, limit, prefix.as_deref());
         handle_ans_err("GetFunctions", tx.send(funcs));
       }
       NodeRequest::GetFunction { name, tx } => {
@@ -1210,14 +3636,29 @@ impl<C: ProtoComm> Node<C> {
         handle_ans_err("GetFunction", tx.send(info));
       }
       NodeRequest::GetState { name, tx } => {
-        let state = self.runtime.read_disk_as_term(name.into(), Some(1 << 16));
+        let limit = Some(self.api_config.max_term_size);
+        let state = self.runtime.read_disk_as_term(name.into(), limit);
         handle_ans_err("GetState", tx.send(state));
       }
+      NodeRequest::GetStates { names, tx } => {
+        let states = names
+          .into_iter()
+          .map(|name| {
+            let limit = Some(self.api_config.max_term_size);
+            self.runtime.read_disk_as_term(name.into(), limit)
+          })
+          .collect();
+        handle_ans_err("GetStates", tx.send(states));
+      }
       NodeRequest::GetPeers { all, tx } => {
         let peers =
           if all { self.peers.get_all() } else { self.peers.get_all_active() };
         handle_ans_err("GetPeers", tx.send(peers));
       }
+      NodeRequest::GetPeerCounts { tx } => {
+        let counts = self.peers.get_counts();
+        handle_ans_err("GetPeerCounts", tx.send(counts));
+      }
       NodeRequest::GetConstructor { name, tx } => {
         let info = self.get_ctr_info(&name);
         handle_ans_err("GetConstructor", tx.send(info));
@@ -1230,6 +3671,39 @@ impl<C: ProtoComm> Node<C> {
         let result = self.runtime.test_statements_from_code(&code);
         handle_ans_err("RunCode", tx.send(result));
       }
+      NodeRequest::PublishCode { tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't accept transactions.".to_string();
+        handle_ans_err("PublishCode", tx.send(Err(err)));
+      }
+      NodeRequest::Publish { code, tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't accept transactions.";
+        let result = code.iter().map(|_| Err(err.to_string())).collect();
+        handle_ans_err("Publish", tx.send(result));
+      }
+      NodeRequest::SetMining { tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't mine.".to_string();
+        handle_ans_err("SetMining", tx.send(Err(err)));
+      }
+      NodeRequest::SubmitBlock { tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't accept mined blocks.".to_string();
+        handle_ans_err("SubmitBlock", tx.send(Err(err)));
+      }
+      NodeRequest::ExportState { tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't export states.".to_string();
+        handle_ans_err("ExportState", tx.send(Err(err)));
+      }
+      NodeRequest::SetTip { tx, .. } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes don't accept a forced tip.".to_string();
+        handle_ans_err("SetTip", tx.send(Err(err)));
+      }
+      NodeRequest::Pause { tx } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes can't be paused.".to_string();
+        handle_ans_err("Pause", tx.send(Err(err)));
+      }
+      NodeRequest::Resume { tx } if self.mode == NodeMode::Follower => {
+        let err = "Follower nodes can't be resumed.".to_string();
+        handle_ans_err("Resume", tx.send(Err(err)));
+      }
       NodeRequest::PublishCode { code, tx } => {
         let statements =
           hvm::read_statements(&code).map_err(|err| err.erro).map(|(_, s)| s);
@@ -1253,17 +3727,157 @@ impl<C: ProtoComm> Node<C> {
         let result = self.runtime.test_statements(&code);
         handle_ans_err("Run", tx.send(result));
       }
-      NodeRequest::Publish { code, tx } => {
+      NodeRequest::DryRun { code, tx } => {
+        let term_limit = self.api_config.max_term_size;
+        // Asks for one extra change, to tell if the list was cut
+        let (results, mut diffs) = self.runtime.diff_statements(
+          &code,
+          MAX_STATE_DIFFS + 1,
+          term_limit,
+        );
+        let truncated = diffs.len() > MAX_STATE_DIFFS;
+        diffs.truncate(MAX_STATE_DIFFS);
+        let info = DryRunInfo { results, diffs, truncated };
+        handle_ans_err("DryRun", tx.send(info));
+      }
+      NodeRequest::Publish { code, priority, tx } => {
         let result: Vec<_> = code
           .into_iter()
           .map(|stmt| {
             let bytes = bitvec_to_bytes(&stmt.proto_serialized());
             let t = Transaction::new(bytes);
-            self.add_transaction(t)
+            self.add_transaction_with_priority(t, priority)
           })
           .collect();
         handle_ans_err("Publish", tx.send(result));
       }
+      NodeRequest::ExportState { name, tx } => {
+        let result = self.export_state(&name);
+        handle_ans_err("ExportState", tx.send(result));
+      }
+      NodeRequest::SetMining { enabled, tx } => {
+        let result = self.set_mining(enabled);
+        handle_ans_err("SetMining", tx.send(result));
+      }
+      NodeRequest::SubscribeResults { filter, tx } => {
+        let rx = self.subscribe_results(filter);
+        handle_ans_err("SubscribeResults", tx.send(rx));
+      }
+      NodeRequest::SetTip { tx, .. } if !self.api_config.allow_set_tip => {
+        let err = "Setting the tip is disabled; see `allow_set_tip`.";
+        handle_ans_err("SetTip", tx.send(Err(err.to_string())));
+      }
+      NodeRequest::SetTip { bhash, tx } => {
+        handle_ans_err("SetTip", tx.send(self.set_tip(bhash)));
+      }
+      NodeRequest::Pause { tx } => {
+        handle_ans_err("Pause", tx.send(self.pause(get_time())));
+      }
+      NodeRequest::Resume { tx } => {
+        handle_ans_err("Resume", tx.send(self.resume()));
+      }
+      NodeRequest::GetExpectedBlockTime { hashes_per_second, tx } => {
+        let target = self.get_tip_target();
+        let secs = expected_block_time(target, hashes_per_second);
+        handle_ans_err("GetExpectedBlockTime", tx.send(secs));
+      }
+      NodeRequest::GetWork { tx } => {
+        let version = self.block_version_after(self.tip);
+        let body = self.build_body_from_pool();
+        let work = (self.tip, version, body, self.get_tip_target());
+        handle_ans_err("GetWork", tx.send(work));
+      }
+      NodeRequest::SubmitBlock { block, tx } => {
+        handle_ans_err("SubmitBlock", tx.send(self.submit_block(block)));
+      }
+      NodeRequest::GetTarget { tx } => {
+        handle_ans_err("GetTarget", tx.send(self.get_tip_target()));
+      }
+      NodeRequest::GetDifficultyHistory { count, tx } => {
+        let history = self.get_difficulty_history(count);
+        handle_ans_err("GetDifficultyHistory", tx.send(history));
+      }
+    }
+  }
+
+  /// Enables or disables mining at runtime. When disabled, the node keeps
+  /// processing and relaying blocks, but stops asking the miner for new ones.
+  pub fn set_mining(&mut self, enabled: bool) -> Result<(), String> {
+    if enabled && self.miner_comm.is_none() {
+      return Err("Node was started without a mining thread.".to_string());
+    }
+    self.mining_enabled = enabled;
+    if !enabled {
+      self.send_to_miner(MinerMessage::Stop);
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::stop_mining(),
+        tags = mining,
+        stopped
+      );
+    }
+    Ok(())
+  }
+
+  // Keeps `addr` as the sender of a block, unless it is known already. Only
+  // the last `BLOCK_SOURCES_KEPT` blocks are remembered.
+  fn record_block_source(&mut self, bhash: U256, addr: C::Address) {
+    if self.block.contains_key(&bhash) || self.block_sources.contains_key(&bhash)
+    {
+      return;
+    }
+    self.block_sources.insert(bhash, addr);
+    self.block_source_order.push_back(bhash);
+    while self.block_source_order.len() > BLOCK_SOURCES_KEPT {
+      if let Some(old) = self.block_source_order.pop_front() {
+        self.block_sources.remove(&old);
+      }
+    }
+  }
+
+  /// Stops accepting blocks, so that nothing is written to the data
+  /// directory and it can be backed up. Read queries are still answered.
+  /// Blocks received meanwhile are dropped, and processing resumes on its own
+  /// after `MAX_PAUSE_TIME`.
+  pub fn pause(&mut self, now: u128) -> Result<(), String> {
+    if self.is_paused() {
+      return Err("Block processing is already paused.".to_string());
+    }
+    self.paused_since = Some(now);
+    self.send_to_miner(MinerMessage::Stop);
+    emit_event!(
+      self.event_emitter,
+      NodeEventType::stop_mining(),
+      tags = mining,
+      stopped
+    );
+    Ok(())
+  }
+
+  /// Accepts blocks again after a `pause`.
+  pub fn resume(&mut self) -> Result<(), String> {
+    if self.paused_since.take().is_none() {
+      return Err("Block processing is not paused.".to_string());
+    }
+    Ok(())
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused_since.is_some()
+  }
+
+  /// Resumes block processing if it has been paused for too long.
+  pub fn expire_pause(&mut self, now: u128) {
+    if let Some(since) = self.paused_since {
+      if now.saturating_sub(since) >= MAX_PAUSE_TIME {
+        emit_event!(
+          self.event_emitter,
+          NodeEventType::pause_expired(MAX_PAUSE_TIME / 1000),
+          tags = status,
+          pause_expired
+        );
+        self.paused_since = None;
+      }
     }
   }
 
@@ -1276,10 +3890,146 @@ impl<C: ProtoComm> Node<C> {
     blocks: Vec<Block>,
     share_peers: u128,
   ) {
-    let magic = self.network_id;
+    let magic = self.magic;
     let peers = self.peers.get_random_active(share_peers);
     let msg = Message::NoticeTheseBlocks { magic, gossip, blocks, peers };
-    self.comm.proto_send(addrs, &msg);
+    self.send_message(addrs, &msg);
+  }
+
+  // Sends a message, backing off gossip while the transport is congested
+  fn send_message(
+    &mut self,
+    addrs: Vec<C::Address>,
+    message: &Message<C::Address>,
+  ) {
+    self.peers.mark_contacted(&addrs);
+    let status = self.comm.proto_send(addrs, message);
+    self.note_send_status(status);
+  }
+
+  // Pauses gossip while the transport is congested, backing off further on
+  // each failure, and resumes it once sends go out again
+  fn note_send_status(&mut self, status: SendStatus) {
+    match status {
+      SendStatus::Queued => {}
+      SendStatus::Sent => {
+        if self.congestion_backoff > 0 {
+          emit_event!(
+            self.event_emitter,
+            NodeEventType::uncongested(),
+            tags = status,
+            uncongested
+          );
+          self.congestion_backoff = 0;
+        }
+      }
+      SendStatus::Congested => {
+        if self.congestion_backoff == 0 {
+          emit_event!(
+            self.event_emitter,
+            NodeEventType::congested(),
+            tags = status,
+            congested
+          );
+        }
+        self.congestion_backoff = (self.congestion_backoff * 2)
+          .clamp(CONGESTION_BACKOFF_MIN, CONGESTION_BACKOFF_MAX);
+        self.congested_until =
+          get_time().saturating_add(self.congestion_backoff);
+      }
+    }
+  }
+
+  /// How many blocks, at most, are sent back when `addr` asks for `bhash`.
+  /// Peers whose last gossiped tip is far below the requested block get up to
+  /// `SEND_BLOCK_ANCESTORS_BOOST` times the configured amount, and each
+  /// doubling of the congestion backoff halves it. Never lower than
+  /// `SEND_BLOCK_ANCESTORS_MIN`.
+  pub fn send_block_count(&self, addr: &C::Address, bhash: &U256) -> usize {
+    let base = self.net_config.send_block_ancestors;
+    let behind = self
+      .peer_tips
+      .get(addr)
+      .and_then(|(tip, _)| self.height.get(tip))
+      .zip(self.height.get(bhash))
+      .map(|(tip, wanted)| wanted.saturating_sub(*tip) as usize)
+      .unwrap_or(0);
+    let mut count =
+      behind.clamp(base, base.saturating_mul(SEND_BLOCK_ANCESTORS_BOOST));
+    let mut backoff = self.congestion_backoff;
+    while backoff >= CONGESTION_BACKOFF_MIN {
+      count /= 2;
+      backoff /= 2;
+    }
+    std::cmp::max(count, SEND_BLOCK_ANCESTORS_MIN)
+  }
+
+  /// Heights at which the tips recently gossiped by peers disagree. Tips
+  /// whose height is still unknown are left out.
+  pub fn competing_tip_heights(&self, now: u128) -> Vec<u128> {
+    let mut tips: HashMap<u128, HashSet<U256>> = HashMap::new();
+    for (bhash, seen_at) in self.peer_tips.values() {
+      if now.saturating_sub(*seen_at) > PEER_TIP_WINDOW {
+        continue;
+      }
+      if let Some(height) = self.height.get(bhash) {
+        tips.entry(*height).or_default().insert(*bhash);
+      }
+    }
+    let mut heights: Vec<u128> = tips
+      .into_iter()
+      .filter(|(_, hashes)| hashes.len() > 1)
+      .map(|(height, _)| height)
+      .collect();
+    heights.sort_unstable();
+    heights
+  }
+
+  /// Whether peers have been reporting competing tips for a while, which
+  /// hints that the network is splitting.
+  pub fn is_chain_split(&self, now: u128) -> bool {
+    match self.split_since {
+      Some(since) => now.saturating_sub(since) >= CHAIN_SPLIT_DELAY,
+      None => false,
+    }
+  }
+
+  // Tracks since when peers report competing tips, warning once it persists
+  fn check_chain_split(&mut self, now: u128) {
+    self
+      .peer_tips
+      .retain(|_, (_, at)| now.saturating_sub(*at) <= PEER_TIP_WINDOW);
+    let heights = self.competing_tip_heights(now);
+    if heights.is_empty() {
+      if self.split_warned {
+        emit_event!(
+          self.event_emitter,
+          NodeEventType::chain_split_over(),
+          tags = status,
+          chain_split_over
+        );
+      }
+      self.split_since = None;
+      self.split_warned = false;
+      return;
+    }
+    if self.split_since.is_none() {
+      self.split_since = Some(now);
+    }
+    if self.is_chain_split(now) && !self.split_warned {
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::chain_split(heights),
+        tags = status,
+        chain_split
+      );
+      self.split_warned = true;
+    }
+  }
+
+  /// Whether gossip is paused due to recent congestion.
+  pub fn is_congested(&self) -> bool {
+    get_time() < self.congested_until
   }
 
   // Returns the block inclusion state
@@ -1296,6 +4046,42 @@ impl<C: ProtoComm> Node<C> {
     return InclusionState::UNSEEN;
   }
 
+  /// Drops pending blocks that have waited for their ancestors longer than
+  /// `net_config.pending_block_timeout`, as of `now`, so abandoned syncs
+  /// don't hold memory forever. Returns how many were dropped.
+  pub fn evict_stale_pending(&mut self, now: u128) -> usize {
+    let timeout = self.net_config.pending_block_timeout;
+    let stale: Vec<U256> = self
+      .pending_at
+      .iter()
+      .filter(|(_, at)| now.saturating_sub(**at) > timeout)
+      .map(|(bhash, _)| *bhash)
+      .collect();
+    for bhash in &stale {
+      self.pending_at.remove(bhash);
+      self.ancestor.remove(bhash);
+      if let Some(block) = self.pending.remove(bhash) {
+        // Forgets it on its parent's wait_list, and the parent too if no
+        // other block waits for it
+        if let Some(waiting) = self.wait_list.get_mut(&block.prev) {
+          waiting.retain(|h| h != bhash);
+          if waiting.is_empty() {
+            self.wait_list.remove(&block.prev);
+          }
+        }
+      }
+    }
+    if !stale.is_empty() {
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::pending_evicted(stale.len()),
+        tags = status,
+        pending_evicted
+      );
+    }
+    stale.len()
+  }
+
   // Finds the most recent missing ancestor of a pending block
   pub fn find_missing_ancestor(&mut self, bhash: &U256) -> Option<U256> {
     if self.inclusion_state(bhash) == InclusionState::PENDING {
@@ -1314,19 +4100,111 @@ impl<C: ProtoComm> Node<C> {
       self.ancestor.insert(*bhash, ancestor);
       return Some(ancestor);
     }
-    // This should NOT happen, since we only call find_missing_ancestor after add_block.
-    debug_assert!(self.inclusion_state(bhash) != InclusionState::MISSING);
-    debug_assert!(self.inclusion_state(bhash) != InclusionState::UNSEEN);
-    return None;
+    // This should NOT happen, since we only call find_missing_ancestor after add_block.
+    debug_assert!(self.inclusion_state(bhash) != InclusionState::MISSING);
+    debug_assert!(self.inclusion_state(bhash) != InclusionState::UNSEEN);
+    return None;
+  }
+
+  // Requests the most recent missing ancestor. If it was already requested,
+  // the retries in `retry_block_requests` take care of it, so its attempts
+  // keep counting towards `BLOCK_REQUEST_MAX_ATTEMPTS`.
+  pub fn request_missing_ancestor(&mut self, addr: C::Address, bhash: &U256) {
+    if let Some(missing_ancestor) = self.find_missing_ancestor(bhash) {
+      if self.block_requests.contains_key(&missing_ancestor) {
+        return;
+      }
+      self.request_block(addr, missing_ancestor);
+      let now = get_time();
+      let request = BlockRequest { peer: addr, sent_at: now, attempts: 1 };
+      self.block_requests.insert(missing_ancestor, request);
+    }
+  }
+
+  fn request_block(&mut self, addr: C::Address, bhash: U256) {
+    let magic = self.magic;
+    let msg = &Message::GiveMeThatBlock { magic, bhash };
+    self.send_message(vec![addr], msg);
+  }
+
+  /// Asks a random active peer for the block `bhash`. Meant for operators
+  /// nudging a stalled sync; the request is then retried like any other.
+  pub fn request_block_from_peers(
+    &mut self,
+    bhash: U256,
+  ) -> Result<(), String> {
+    if self.inclusion_state(&bhash) == InclusionState::INCLUDED {
+      return Err("The block is already included.".to_string());
+    }
+    let peer = match self.peers.get_random_active(1).pop() {
+      Some(peer) => peer,
+      None => return Err("There are no active peers.".to_string()),
+    };
+    let addr = peer.address;
+    self.request_block(addr, bhash);
+    let now = get_time();
+    let request = BlockRequest { peer: addr, sent_at: now, attempts: 1 };
+    self.block_requests.insert(bhash, request);
+    Ok(())
+  }
+
+  // Requests again the blocks that didn't arrive in time, in case the request
+  // or the answer was lost. After `BLOCK_REQUEST_RETRIES` attempts, asks
+  // another peer, if there is one; after `BLOCK_REQUEST_MAX_ATTEMPTS`, gives
+  // up. A given up request is remembered for `PENDING_BLOCK_TIMEOUT`, so
+  // gossip doesn't start it over.
+  pub fn retry_block_requests(&mut self) {
+    let now = get_time();
+    let expired: Vec<U256> = self
+      .block_requests
+      .iter()
+      .filter(|(_, req)| {
+        now >= req.sent_at.saturating_add(BLOCK_REQUEST_TIMEOUT)
+      })
+      .map(|(bhash, _)| *bhash)
+      .collect();
+    for bhash in expired {
+      let mut req = self.block_requests.remove(&bhash).unwrap();
+      if req.attempts >= BLOCK_REQUEST_MAX_ATTEMPTS {
+        if now < req.sent_at.saturating_add(PENDING_BLOCK_TIMEOUT) {
+          self.block_requests.insert(bhash, req);
+        }
+        continue;
+      }
+      if req.attempts.is_multiple_of(BLOCK_REQUEST_RETRIES) {
+        let other = self
+          .peers
+          .get_random_active(8)
+          .into_iter()
+          .find(|peer| peer.address != req.peer);
+        if let Some(peer) = other {
+          req.peer = peer.address;
+        }
+      }
+      self.request_block(req.peer, bhash);
+      req.sent_at = now;
+      req.attempts += 1;
+      self.block_requests.insert(bhash, req);
+    }
   }
 
-  // Requests the most recent missing ancestor
-  pub fn request_missing_ancestor(&mut self, addr: C::Address, bhash: &U256) {
-    if let Some(missing_ancestor) = self.find_missing_ancestor(bhash) {
-      let magic = self.network_id;
-      let msg = &Message::GiveMeThatBlock { magic, bhash: missing_ancestor };
-      self.comm.proto_send(vec![addr], msg);
-    }
+  /// Drops blocks whose hash doesn't meet `net_config.min_block_difficulty`.
+  /// Needs no parent, so obvious junk is filtered before any map is touched.
+  pub fn drop_blocks_below_min_work(
+    &self,
+    blocks: Vec<HashedBlock>,
+  ) -> Vec<HashedBlock> {
+    let min_difficulty = match self.net_config.min_block_difficulty {
+      Some(min_difficulty) => min_difficulty,
+      None => return blocks,
+    };
+    let min_target = difficulty_to_target(u256(min_difficulty));
+    blocks
+      .into_iter()
+      .filter(|block| {
+        hash_hits_target(U256::from(block.get_hash()), min_target)
+      })
+      .collect()
   }
 
   pub fn handle_message(
@@ -1338,15 +4216,20 @@ impl<C: ProtoComm> Node<C> {
       match msg {
         Message::GiveMeThatBlock { magic, .. }
         | Message::NoticeTheseBlocks { magic, .. }
-        | Message::PleaseMineThisTransaction { magic, .. } => {
-          if magic != &self.network_id {
+        | Message::PleaseMineThisTransaction { magic, .. }
+        | Message::Ping { magic }
+        | Message::Pong { magic } => {
+          if magic != &self.magic {
             return;
           }
         }
       }
 
+      self.message_counts.count(msg);
+
       self.peers.see_peer(
         Peer { address: addr, seen_at: get_time() },
+        true,
         #[cfg(feature = "events")]
         self.event_emitter.clone(),
       );
@@ -1360,12 +4243,32 @@ impl<C: ProtoComm> Node<C> {
             tags = handle_message,
             give_me_block
           );
-          // Sends the requested block, plus some of its ancestors
+          // Ignores peers asking too much, and everyone past the byte budget
+          let allowed = self.block_serving.allow(
+            addr,
+            get_time(),
+            self.net_config.block_serve_peer_rate,
+            self.net_config.block_serve_bytes_rate,
+          );
+          if !allowed {
+            return;
+          }
+          // Sends the requested block, plus some of its ancestors. Pending
+          // blocks are served too, so they propagate while we wait for their
+          // missing ancestors
+          let max_count = self.send_block_count(&addr, bhash);
           let mut bhash = bhash;
           let mut chunk = vec![];
           let mut tsize = 0; // total size of the corresponding "NoticeTheseBlocks" message
           loop {
-            if !self.block.contains_key(&bhash) {
+            let block = match self.block.get(bhash) {
+              Some(block) => block,
+              None => match self.pending.get(bhash) {
+                Some(block) => block,
+                None => break,
+              },
+            };
+            if chunk.len() >= max_count {
               break;
             }
             if *bhash == zero_hash() {
@@ -1373,7 +4276,6 @@ impl<C: ProtoComm> Node<C> {
               // Stops when it reaches genesis block non-existing parent
               break;
             }
-            let block = &self.block[bhash];
             let bsize = serialized_block_size(block) as usize;
             if tsize + bsize > MAX_UDP_SIZE_SLOW {
               break;
@@ -1382,12 +4284,14 @@ impl<C: ProtoComm> Node<C> {
             tsize += bsize;
             bhash = &block.prev;
           }
+          self.block_serving.record(tsize);
           self.send_blocks_to(vec![addr], false, chunk, 0);
         }
         // Someone sent us some blocks
         Message::NoticeTheseBlocks { magic, gossip, blocks, peers } => {
           let blocks: Vec<_> =
             blocks.iter().cloned().map(|block| block.hashed()).collect();
+          let blocks = self.drop_blocks_below_min_work(blocks);
           emit_event!(
             self.event_emitter,
             NodeEventType::notice_blocks(*magic, *gossip, &blocks, peers),
@@ -1401,16 +4305,37 @@ impl<C: ProtoComm> Node<C> {
           for peer in peers {
             self.peers.see_peer(
               *peer,
+              false,
               #[cfg(feature = "events")]
               self.event_emitter.clone(),
             );
           }
 
+          // Leaves the disk untouched while paused; the blocks are fetched
+          // again after resuming
+          if self.is_paused() {
+            return;
+          }
+
+          // Remembers who delivered each block first, so that invalid ones
+          // can be attributed to a peer
+          for block in &blocks {
+            self.record_block_source(U256::from(block.get_hash()), addr);
+          }
+
           // Adds the block to the database
           for block in &blocks {
             self.add_block(&block);
           }
 
+          // The first gossiped block is the sender's tip
+          if *gossip && blocks.len() > 0 {
+            let bhash = U256::from(&blocks[0].keccak256());
+            self.heard_tip = true;
+            self.peer_tips.insert(addr, (bhash, get_time()));
+            self.check_chain_split(get_time());
+          }
+
           // Requests missing ancestors
           if *gossip && blocks.len() > 0 {
             let bhash = U256::from(&blocks[0].keccak256());
@@ -1425,29 +4350,204 @@ impl<C: ProtoComm> Node<C> {
             tags = handle_message,
             mine_trans
           );
-          if self.pool.get(&tx).is_none() {
-            self.pool.push(tx.clone(), tx.hash.low_u64());
+          // Followers never accept transactions
+          let accepts_txs = self.mode != NodeMode::Follower;
+          if accepts_txs && self.add_transaction(tx.clone()).is_ok() {
             self.gossip(5, msg);
           }
         }
+        // Someone checked whether we are alive
+        Message::Ping { magic } => {
+          let pong = Message::Pong { magic: *magic };
+          self.send_message(vec![addr], &pong);
+        }
+        // Someone answered our ping; seeing them above is all that's needed
+        Message::Pong { .. } => {}
       }
     }
   }
 
+  // Pings the peers we haven't heard from for half the peer timeout, so quiet
+  // but alive peers aren't forgotten
+  fn send_keepalives(&mut self) {
+    let quiet_for = self.peers.get_timeout() / 2;
+    let addrs =
+      self.peers.get_quiet(quiet_for).iter().map(|x| x.address).collect();
+    let ping = Message::Ping { magic: self.magic };
+    self.send_message(addrs, &ping);
+  }
+
   pub fn gossip(&mut self, peer_count: u128, message: &Message<C::Address>) {
+    if self.is_congested() {
+      return;
+    }
     let addrs = self
       .peers
       .get_random_active(peer_count)
       .iter()
       .map(|x| x.address)
       .collect();
-    self.comm.proto_send(addrs, message);
+    self.send_message(addrs, message);
+  }
+
+  /// Directory where runtime states are exported to. Exports can't be
+  /// written anywhere else, so API clients can't touch other files.
+  pub fn get_exports_path(&self) -> PathBuf {
+    self.data_path.join("exports")
+  }
+
+  /// Exports the runtime state to the directory `name`, on the exports
+  /// directory, with the hash of the main chain block it was taken at; see
+  /// `read_state_block`. `name` must be a plain directory name, not a path.
+  pub fn export_state(&self, name: &str) -> Result<(), String> {
+    let plain = !name.is_empty()
+      && name != "."
+      && name != ".."
+      && !name.contains(|c| std::path::is_separator(c) || c == '\0');
+    if !plain {
+      return Err(format!("Invalid export name '{}'.", name));
+    }
+    // The export holds the runtime's snapshots, the newest being the state
+    // it is imported at
+    let tick = self.runtime.get_rollback_tick(u64::MAX);
+    let bhash = self.get_block_hash_by_index(tick).ok_or_else(|| {
+      format!("The block at height {} is not known.", tick)
+    })?;
+    let path = self.get_exports_path().join(name);
+    std::fs::create_dir_all(self.get_exports_path())
+      .and_then(|()| self.runtime.export_state(&path))
+      .and_then(|()| {
+        std::fs::write(path.join(STATE_BLOCK_FILE), u256_to_bytes(bhash))
+      })
+      .map_err(|err| {
+        format!("Could not export state to '{}': {}", path.display(), err)
+      })
   }
 
   pub fn get_blocks_path(&self) -> PathBuf {
     self.data_path.join("blocks")
   }
 
+  /// Path of the file storing the main chain block at `height`.
+  pub fn get_block_file_path(&self, height: u128) -> PathBuf {
+    self.get_blocks_path().join(format!("{:0>16x}.kindelia_block.bin", height))
+  }
+
+  /// Writes the main chain block `bhash` to its file. If that fails, e.g. on
+  /// a full disk, the block stays in memory only and is written again by
+  /// `retry_unsaved_blocks`; meanwhile the node is degraded and doesn't mine.
+  fn save_block_file(&mut self, bhash: U256) {
+    let height = self.height[&bhash];
+    let file_path = self.get_block_file_path(height);
+    let file_buff = bitvec_to_bytes(&self.block[&bhash].proto_serialized());
+    match std::fs::write(&file_path, file_buff) {
+      Ok(()) => {
+        self.unsaved_blocks.remove(&height);
+      }
+      Err(err) => {
+        if self.unsaved_blocks.is_empty() {
+          emit_event!(
+            self.event_emitter,
+            NodeEventType::save_failed(&file_path, &err),
+            tags = status,
+            save_failed
+          );
+        }
+        self.unsaved_blocks.insert(height, bhash);
+      }
+    }
+  }
+
+  /// Tries again to write the blocks that couldn't be saved. Returns how many
+  /// are still unsaved.
+  pub fn retry_unsaved_blocks(&mut self) -> usize {
+    // Blocks above the tip were left behind by a reorg to a shorter chain
+    let tip_height = self.height[&self.tip];
+    self.unsaved_blocks.retain(|height, _| *height <= tip_height);
+    if self.unsaved_blocks.is_empty() {
+      return 0;
+    }
+    let unsaved: Vec<U256> = self.unsaved_blocks.values().copied().collect();
+    for bhash in unsaved {
+      self.save_block_file(bhash);
+    }
+    if self.unsaved_blocks.is_empty() {
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::save_recovered(),
+        tags = status,
+        save_recovered
+      );
+    }
+    self.unsaved_blocks.len()
+  }
+
+  /// Copies the node's whole chain state, see `NodeSnapshot`.
+  pub fn snapshot(&self) -> NodeSnapshot {
+    NodeSnapshot {
+      version: NODE_SNAPSHOT_VERSION,
+      genesis_hash: self.genesis_hash,
+      base: self.base,
+      tip: self.tip,
+      state_floor: self.state_floor,
+      block: self.block.clone(),
+      pending: self.pending.clone(),
+      pending_at: self.pending_at.clone(),
+      ancestor: self.ancestor.clone(),
+      wait_list: self.wait_list.clone(),
+      children: self.children.clone(),
+      work: self.work.clone(),
+      target: self.target.clone(),
+      height: self.height.clone(),
+      results: self.results.clone(),
+      ticks: self.ticks.clone(),
+      lost_ticks: self.lost_ticks.clone(),
+      tx_index: self.tx_index.clone(),
+      runtime: self.runtime.image(),
+    }
+  }
+
+  /// Replaces the node's chain state by `snapshot`, all at once. Fails,
+  /// leaving the node untouched, on snapshots of another format version or
+  /// network. The block files on disk are not rewritten.
+  pub fn restore(&mut self, snapshot: NodeSnapshot) -> Result<(), String> {
+    check_snapshot_version(snapshot.version)?;
+    if snapshot.genesis_hash != self.genesis_hash {
+      return Err("Node snapshot is from another network.".to_string());
+    }
+    self.send_to_miner(MinerMessage::Stop);
+    self.base = snapshot.base;
+    self.move_tip(snapshot.tip);
+    self.state_floor = snapshot.state_floor;
+    self.block = snapshot.block;
+    self.pending = snapshot.pending;
+    self.pending_at = snapshot.pending_at;
+    self.ancestor = snapshot.ancestor;
+    self.wait_list = snapshot.wait_list;
+    self.children = snapshot.children;
+    self.work = snapshot.work;
+    self.target = snapshot.target;
+    self.height = snapshot.height;
+    self.results = snapshot.results;
+    self.ticks = snapshot.ticks;
+    self.lost_ticks = snapshot.lost_ticks;
+    self.tx_index = snapshot.tx_index;
+    self.runtime.restore_image(snapshot.runtime);
+    // Drops what was derived from the replaced state
+    self.functions = None;
+    self.block_requests.clear();
+    self.unsaved_blocks.clear();
+    self.recent_blocks.clear();
+    self.update_recent_blocks();
+    Ok(())
+  }
+
+  /// Whether some main chain blocks only live in memory, as their files
+  /// couldn't be written. The node keeps validating, but doesn't mine.
+  pub fn is_degraded(&self) -> bool {
+    !self.unsaved_blocks.is_empty()
+  }
+
   fn broadcast_tip_block(&mut self) {
     let addrs: Vec<C::Address> =
       self.peers.get_all_active().iter().map(|x| x.address).collect();
@@ -1456,44 +4556,301 @@ impl<C: ProtoComm> Node<C> {
   }
 
   fn gossip_tip_block(&mut self, peer_count: u128) {
-    let addrs: Vec<C::Address> = self
-      .peers
-      .get_random_active(peer_count)
-      .iter()
-      .map(|x| x.address)
-      .collect();
+    if self.is_congested() {
+      return;
+    }
+    let now = get_time();
+    let addrs: Vec<C::Address> = if self.gossiped_tip != self.tip {
+      // When the tip advances, every peer receives the new one right away
+      self.gossiped_tip = self.tip;
+      self.tip_sent_at.clear();
+      self.peers.get_all_active().iter().map(|x| x.address).collect()
+    } else {
+      // Otherwise, skips peers that recently received this same tip; the
+      // longer it is stable, the less often it is sent
+      let interval = tip_resend_interval(now.saturating_sub(self.tip_time));
+      let sent_at = &self.tip_sent_at;
+      self
+        .peers
+        .get_random_active(peer_count)
+        .iter()
+        .map(|x| x.address)
+        .filter(|addr| match sent_at.get(addr) {
+          Some(time) => now >= time.saturating_add(interval),
+          None => true,
+        })
+        .collect()
+    };
+    if addrs.is_empty() {
+      return;
+    }
+    for addr in &addrs {
+      self.tip_sent_at.insert(*addr, now);
+    }
+    // The tip is cloned once per round; `proto_send` serializes the message
+    // once and sends the same bytes to every peer.
     let blocks = vec![(*self.block[&self.tip]).clone()];
     self.send_blocks_to(addrs, true, blocks, 3);
   }
 
-  fn load_blocks(&mut self) {
+  /// Loads the chain stored on the blocks directory. Run once, on start.
+  pub fn load_blocks(&mut self) {
     let blocks_dir = self.get_blocks_path();
     std::fs::create_dir_all(&blocks_dir).ok();
-    let mut file_paths: Vec<(u64, PathBuf)> = vec![];
-    for entry in std::fs::read_dir(&blocks_dir).unwrap() {
-      // Extract block height from block file path for fast sort
-      let path = entry.unwrap().path();
-      let name = path.file_name().unwrap().to_str().unwrap();
-      let bnum = name.split('.').nth(0).unwrap();
-      let bnum = u64::from_str_radix(bnum, 16).unwrap();
-      file_paths.push((bnum, path));
-    }
-    file_paths.sort_unstable();
+    let mut file_paths = match list_block_files(&blocks_dir) {
+      Ok(file_paths) => file_paths,
+      Err(err) => {
+        eprintln!("WARN: Could not list the block files. {}", err);
+        vec![]
+      }
+    };
+    let heights: Vec<u64> = file_paths.iter().map(|(h, _)| *h).collect();
+    let (missing, repeated) = check_block_heights(&heights);
+    for (from, to) in missing {
+      // Pruned nodes remove the oldest block files
+      if from == 1 && self.storage == StorageMode::Pruned {
+        continue;
+      }
+      eprintln!(
+        "WARN: Block files for heights {} to {} are missing; the chain will stop at height {}.",
+        from,
+        to,
+        from - 1
+      );
+    }
+    for height in repeated {
+      eprintln!("WARN: There is more than one block file for height {}.", height);
+    }
+    // Pruned nodes load no more than they keep
+    let max_load_blocks = match self.storage {
+      StorageMode::Archive => self.max_load_blocks,
+      StorageMode::Pruned => {
+        Some(self.max_load_blocks.unwrap_or(self.prune_depth))
+      }
+    };
+    if let Some(max) = max_load_blocks {
+      file_paths = self.truncate_block_files(file_paths, max);
+    }
     let num_blocks = file_paths.len();
     eprintln!("Loading {} blocks from disk...", num_blocks);
-    for (_, file_path) in file_paths {
-      let buffer = std::fs::read(&file_path).unwrap();
-      let block = Block::proto_deserialized(&bytes_to_bitvec(&buffer));
-      if let Some(block) = block {
-        self.add_block(&block.hashed());
-      } else {
+    let start = std::time::Instant::now();
+    // Files are read one at a time, so only the list of paths (and the hashes
+    // of the loaded blocks) is kept in memory
+    let mut loaded = Vec::with_capacity(num_blocks);
+    let mut migrated = 0;
+    for (i, (bnum, file_path)) in file_paths.into_iter().enumerate() {
+      if i > 0 && i % LOAD_BLOCKS_LOG_INTERVAL == 0 {
+        let elapsed = start.elapsed().as_secs_f64();
+        let rate = i as f64 / elapsed;
+        let eta = (num_blocks - i) as f64 / rate;
         eprintln!(
-          "WARN: Could not load block from file '{}'",
-          file_path.display()
+          "Loaded {}/{} blocks ({:.0} blocks/s, ~{:.0}s left)...",
+          i, num_blocks, rate, eta
         );
       }
+      let buffer = match std::fs::read(&file_path) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+          eprintln!(
+            "WARN: Could not read block file '{}': {}",
+            file_path.display(),
+            err
+          );
+          continue;
+        }
+      };
+      match Block::from_file_bytes(&buffer) {
+        Ok((block, legacy)) => {
+          if legacy && migrate_block_file(&file_path, &block) {
+            migrated += 1;
+          }
+          let block = block.hashed();
+          self.add_block(&block);
+          loaded.push((bnum, file_path, U256::from(block.get_hash())));
+        }
+        Err(err) => eprintln!(
+          "WARN: Could not load block from file '{}': {}",
+          file_path.display(),
+          err
+        ),
+      }
     }
     eprintln!("Loaded {} blocks from disk.", num_blocks);
+    if migrated > 0 {
+      eprintln!("Migrated {} block files to the versioned encoding.", migrated);
+    }
+    self.repair_block_files(loaded);
+  }
+
+  // Drops the block files below the trusted checkpoint's retarget period,
+  // when `suffix_start` allows skipping blocks for `max`, and starts the
+  // chain on the oldest block left. Loads every file when the chain can't be
+  // truncated. Pruned nodes also remove the dropped files.
+  fn truncate_block_files(
+    &mut self,
+    file_paths: Vec<(u64, PathBuf)>,
+    max: u64,
+  ) -> Vec<(u64, PathBuf)> {
+    let last = file_paths.last().map(|(height, _)| *height).unwrap_or(0);
+    let start = match suffix_start(last, max, self.state_floor) {
+      Some(start) => start,
+      None => {
+        if last > max {
+          eprintln!(
+            "WARN: Loading all {} blocks; loading only the last {} needs an \
+            imported state past them.",
+            last, max
+          );
+        }
+        return file_paths;
+      }
+    };
+    // Targets can't be recomputed without the blocks before the base, so the
+    // base's target is taken from the trusted checkpoint, and the chain
+    // starts on the checkpoint's period
+    let checkpoint = self.net_config.trusted_checkpoint.clone();
+    let (start, target, checkpoint) = match checkpoint {
+      Some(checkpoint) if period_start(checkpoint.height) >= start => {
+        match checkpoint.target {
+          Some(target) => {
+            (period_start(checkpoint.height), U256::from(target), checkpoint)
+          }
+          None => {
+            eprintln!(
+              "WARN: Loading all {} blocks; loading only the last {} needs a \
+              trusted checkpoint with its target.",
+              last, max
+            );
+            return file_paths;
+          }
+        }
+      }
+      _ => {
+        eprintln!(
+          "WARN: Loading all {} blocks; loading only the last {} needs a \
+          trusted checkpoint with its target.",
+          last, max
+        );
+        return file_paths;
+      }
+    };
+    let path = file_paths.iter().find(|(height, _)| *height == start);
+    let read = |path: &PathBuf| {
+      let buffer = std::fs::read(path).map_err(|err| err.to_string())?;
+      Block::from_file_bytes(&buffer).map(|(block, _)| block.hashed())
+    };
+    let base = match path.map(|(_, path)| (path, read(path))) {
+      Some((_, Ok(block))) => block,
+      Some((path, Err(err))) => {
+        eprintln!(
+          "WARN: Loading all blocks, as '{}' could not be read: {}",
+          path.display(),
+          err
+        );
+        return file_paths;
+      }
+      None => return file_paths,
+    };
+    let bhash = U256::from(base.get_hash());
+    if start == checkpoint.height && bhash != U256::from(checkpoint.hash) {
+      eprintln!(
+        "WARN: Loading all blocks, as the block at height {} isn't the \
+        trusted checkpoint.",
+        start
+      );
+      return file_paths;
+    }
+    // The base's accumulated work is that of the checkpoint, less the work
+    // of the blocks between them, so the checkpoint's work is still checked
+    let mut work = U256::from(checkpoint.work);
+    for (height, path) in &file_paths {
+      if *height <= start || *height > checkpoint.height {
+        continue;
+      }
+      match read(path) {
+        Ok(block) => {
+          work = work.saturating_sub(get_hash_work(block.get_hash().into()));
+        }
+        Err(err) => {
+          eprintln!(
+            "WARN: Loading all blocks, as '{}' could not be read: {}",
+            path.display(),
+            err
+          );
+          return file_paths;
+        }
+      }
+    }
+    eprintln!(
+      "Starting on the chain from height {}; older blocks are not loaded.",
+      start
+    );
+    self.block.insert(bhash, base);
+    self.children.insert(bhash, vec![]);
+    self.work.insert(bhash, work);
+    self.height.insert(bhash, start as u128);
+    self.target.insert(bhash, target);
+    self.base = bhash;
+    self.move_tip(bhash);
+    // Pruned nodes never load the files below the base again
+    if self.storage == StorageMode::Pruned {
+      for (height, path) in &file_paths {
+        if *height < start {
+          if let Err(err) = std::fs::remove_file(path) {
+            eprintln!(
+              "WARN: Could not remove block file '{}': {}",
+              path.display(),
+              err
+            );
+          }
+        }
+      }
+    }
+    file_paths.into_iter().filter(|(height, _)| *height > start).collect()
+  }
+
+  /// Fixes the block files whose name disagrees with the height of the block
+  /// they hold, given the files loaded from disk, as `(height on the name,
+  /// path, block hash)`. Misnamed files are renamed, or removed if the block
+  /// is already stored under the right name.
+  pub fn repair_block_files(&self, loaded: Vec<(u64, PathBuf, U256)>) {
+    for (bnum, file_path, bhash) in loaded {
+      // Blocks that didn't connect to the chain have no known height
+      let height = match self.height.get(&bhash) {
+        Some(&height) if height > 0 => height,
+        _ => continue,
+      };
+      if height == bnum as u128 {
+        continue;
+      }
+      // Loading rewrites the main chain under the right names, which may
+      // have replaced the misnamed file already
+      let buffer = bitvec_to_bytes(&self.block[&bhash].proto_serialized());
+      if std::fs::read(&file_path).ok().as_ref() != Some(&buffer) {
+        continue;
+      }
+      eprintln!(
+        "WARN: Block file '{}' holds the block at height {}; repairing it.",
+        file_path.display(),
+        height
+      );
+      let right_path = self.get_block_file_path(height);
+      let result = if !right_path.exists() {
+        std::fs::rename(&file_path, &right_path)
+      } else if std::fs::read(&right_path).ok().as_ref() == Some(&buffer) {
+        std::fs::remove_file(&file_path)
+      } else {
+        // Another block is stored for that height; keeps both
+        continue;
+      };
+      if let Err(err) = result {
+        eprintln!(
+          "WARN: Could not repair block file '{}': {}",
+          file_path.display(),
+          err
+        );
+      }
+    }
   }
 
   fn send_to_miner(&mut self, msg: MinerMessage) {
@@ -1510,10 +4867,21 @@ impl<C: ProtoComm> Node<C> {
       tags = mining,
       ask_mine
     );
-    self.send_to_miner(MinerMessage::Request { prev: self.tip, body, targ });
+    let version = self.block_version_after(self.tip);
+    let prev = self.tip;
+    self.send_to_miner(MinerMessage::Request { version, prev, body, targ });
+  }
+
+  /// Format version a child of `bhash` must be in.
+  pub fn block_version_after(&self, bhash: U256) -> u64 {
+    let height = self.height[&bhash].saturating_add(1);
+    block_version_at(height, self.block_v1_height)
   }
 
   fn do_handle_mined_block(&mut self) {
+    if self.is_paused() {
+      return;
+    }
     if let Some(miner_comm) = &mut self.miner_comm {
       if let MinerMessage::Answer { block } = miner_comm.read() {
         self.add_block(&block);
@@ -1522,11 +4890,116 @@ impl<C: ProtoComm> Node<C> {
     }
   }
 
-  /// Builds the body to be mined.
+  /// Adds a block mined outside of the node, e.g. from `GetWork`, and
+  /// broadcasts it if it becomes the tip. Unlike blocks from peers, it must
+  /// extend the tip or one of its last `MAX_WORK_AGE` ancestors, have a
+  /// well-formed body and hit its target, so that miners learn right away
+  /// that stale or malformed work was refused.
+  pub fn submit_block(&mut self, block: Block) -> Result<(), String> {
+    if self.is_paused() {
+      return Err("Block processing is paused.".to_string());
+    }
+    let block = block.hashed();
+    let bhash = U256::from(block.get_hash());
+    if self.block.contains_key(&bhash) {
+      return Err(format!("Block {:#x} is already known.", bhash));
+    }
+    let target = match self.target.get(&block.prev) {
+      Some(target) if !target.is_zero() => *target,
+      _ => {
+        return Err(format!("Parent block {:#x} is not accepted.", block.prev))
+      }
+    };
+    let stale = match self.common_ancestor(block.prev, self.tip) {
+      Some(ancestor) if ancestor == block.prev => {
+        self.height[&self.tip] - self.height[&block.prev] > MAX_WORK_AGE
+      }
+      _ => true,
+    };
+    if stale {
+      let err = format!("Parent block {:#x} is stale work.", block.prev);
+      return Err(err);
+    }
+    validate_body(&block.body)
+      .map_err(|err| format!("Invalid block body: {}", err))?;
+    if !hash_hits_target(bhash, target) {
+      return Err("Block doesn't hit its parent's target.".to_string());
+    }
+    if block.time <= self.block[&block.prev].time {
+      return Err("Block isn't newer than its parent.".to_string());
+    }
+    let version = self.block_version_after(block.prev);
+    if block.version != version {
+      return Err(format!("Block must be in format version {}.", version));
+    }
+    if !self.add_block(&block) {
+      return Err(format!(
+        "Block {:#x} was refused, e.g. for not fitting the trusted \
+        checkpoint or being too far into the future.",
+        bhash
+      ));
+    }
+    if self.tip == bhash {
+      self.broadcast_tip_block();
+    }
+    Ok(())
+  }
+
+  /// Builds the body to be mined, starting with the mining template.
   /// To convert back to a vector of transactions, use `extract_transactions()`.
+  /// Transactions that aren't valid statements are left out, as
+  /// `validate_body` would refuse the block.
   pub fn build_body_from_pool(&self) -> Body {
-    let txs = self.pool.iter().map(|(tx, _score)| tx.clone());
-    Body::fill_from(txs)
+    let pool_txs = self.pool.sorted().into_iter().cloned();
+    let txs = self.mine_template.iter().cloned().chain(pool_txs);
+    let txs = txs.filter(|tx| tx.to_exact_statement().is_some());
+    let max_size = self.mine_config.target_body_bytes.unwrap_or(MAX_BODY_SIZE);
+    let max_txs = self.mine_config.max_txs_per_block.unwrap_or(MAX_BODY_TXS);
+    Body::fill_with_limits(txs, max_size, max_txs)
+  }
+
+  // Sets the tip, noting when it moved, which ends a stall of the tip
+  fn move_tip(&mut self, bhash: U256) {
+    self.tip = bhash;
+    self.tip_time = get_time();
+    if self.stale_warned {
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::tip_advanced(),
+        tags = status,
+        tip_advanced
+      );
+      self.stale_warned = false;
+    }
+  }
+
+  // Warns once if the tip hasn't advanced for too long
+  pub fn check_stale_tip(&mut self, now: u128) {
+    let since_last_block = now.saturating_sub(self.tip_time);
+    if since_last_block > STALE_TIP_FACTOR * TIME_PER_BLOCK
+      && !self.stale_warned
+    {
+      emit_event!(
+        self.event_emitter,
+        NodeEventType::stale_tip(since_last_block / 1000),
+        tags = status,
+        stale_tip
+      );
+      self.stale_warned = true;
+    }
+  }
+
+  /// Updates `message_rate` with the messages handled since the previous
+  /// sample, taken at each heartbeat.
+  pub fn sample_message_rate(&mut self, now: u128) {
+    let (then, last_total) = self.message_sample;
+    let total = self.message_counts.total();
+    let elapsed = now.saturating_sub(then);
+    if elapsed > 0 {
+      let handled = total.saturating_sub(last_total) as f64;
+      self.message_rate = handled * 1000.0 / elapsed as f64;
+    }
+    self.message_sample = (now, total);
   }
 
   fn log_heartbeat(&self) {
@@ -1565,12 +5038,16 @@ impl<C: ProtoComm> Node<C> {
     while block.prev != u256(0) && count < 10 {
       tip_blocks.push(block.get_hash().into());
       // eprintln!("prev {}", block.prev);
-      block = &self.block[&block.prev];
+      block = match self.block.get(&block.prev) {
+        Some(prev) => prev,
+        None => break,
+      };
       count += 1;
     }
     tip_blocks.reverse();
 
     let event = heartbeat! {
+      name: self.name.clone(),
       peers: { num: peers_num },
       tip: {
         height: tip_height,
@@ -1593,6 +5070,10 @@ impl<C: ProtoComm> Node<C> {
           available: size_avail,
         }
       },
+      messages: {
+        counts: self.message_counts.clone(),
+        per_second: self.message_rate,
+      },
       tip_blocks: tip_blocks
     };
 
@@ -1638,6 +5119,50 @@ impl<C: ProtoComm> Node<C> {
           node.receive_request();
         },
       },
+      // Requests again blocks that didn't arrive
+      Task {
+        delay: BLOCK_REQUEST_TIMEOUT / 2,
+        action: |node| {
+          node.retry_block_requests();
+        },
+      },
+      // Pings quiet peers
+      Task {
+        delay: KEEPALIVE_DELAY,
+        action: |node| {
+          node.send_keepalives();
+        },
+      },
+      // Re-checks for chain splits, as gossiped tips go stale
+      Task {
+        delay: 5_000,
+        action: |node| {
+          node.check_chain_split(get_time());
+        },
+      },
+      // Writes blocks that couldn't be saved before, e.g. on a full disk
+      Task {
+        delay: 5_000,
+        action: |node| {
+          if !node.is_paused() {
+            node.retry_unsaved_blocks();
+          }
+        },
+      },
+      // Resumes block processing after a pause that took too long
+      Task {
+        delay: 1_000,
+        action: |node| {
+          node.expire_pause(get_time());
+        },
+      },
+      // Drops blocks whose ancestors never arrived
+      Task {
+        delay: 5_000,
+        action: |node| {
+          node.evict_stale_pending(get_time());
+        },
+      },
       // Forgets inactive peers
       Task {
         delay: 5_000,
@@ -1653,7 +5178,9 @@ impl<C: ProtoComm> Node<C> {
       Task {
         delay: 5_000,
         action: |node| {
+          node.sample_message_rate(get_time());
           node.log_heartbeat();
+          node.check_stale_tip(get_time());
         },
       },
     ];
@@ -1664,6 +5191,13 @@ impl<C: ProtoComm> Node<C> {
         Task {
           delay: 25,
           action: |node| {
+            if !node.mining_enabled
+              || node.is_syncing()
+              || node.is_degraded()
+              || node.is_paused()
+            {
+              return;
+            }
             if let Some(comm) = &mut node.miner_comm {
               if let MinerMessage::Stop { .. } = comm.read() {
                 node.do_ask_mine(node.build_body_from_pool());
@@ -1694,16 +5228,155 @@ impl<C: ProtoComm> Node<C> {
           last_tick_time[i] = system_time;
         }
       }
+      // Sends what the tasks queued, on transports that batch messages
+      if let Some(status) = self.comm.flush() {
+        self.note_send_status(status);
+      }
       let elapsed = now.elapsed();
-      let extra = std::time::Duration::from_millis(1).checked_sub(elapsed);
-      // If the elapsed time is less than 1ms, sleep for the remaining time
-      if let Some(extra) = extra {
-        std::thread::sleep(extra);
+      let tick = std::time::Duration::from_millis(TICK_DURATION);
+      // If the tick took less than its duration, sleep for the remaining time;
+      // otherwise, skip the sleep to catch up
+      match tick.checked_sub(elapsed) {
+        Some(extra) => std::thread::sleep(extra),
+        None => {
+          if elapsed.as_millis() >= TICK_OVERRUN_WARNING {
+            eprintln!(
+              "WARN: node is falling behind: tick took {}ms (expected {}ms).",
+              elapsed.as_millis(),
+              TICK_DURATION
+            );
+          }
+        }
       }
     }
   }
 }
 
+// Node builder
+// ------------
+
+/// Builds a `Node` with chainable setters. Only the data path and the
+/// transport are required; every other option starts at its default.
+pub struct NodeBuilder<C: ProtoComm> {
+  data_path: PathBuf,
+  comm: C,
+  network_id: u32,
+  name: Option<String>,
+  initial_peers: Vec<C::Address>,
+  pinned_peers: Vec<C::Address>,
+  mode: NodeMode,
+  state_snapshot: Option<PathBuf>,
+  api_config: ApiConfig,
+  net_config: NetworkConfig,
+  mine_config: MineConfig,
+  miner_comm: Option<MinerCommunication>,
+  max_load_blocks: Option<u64>,
+  storage: StorageMode,
+  prune_depth: Option<u64>,
+  #[cfg(feature = "events")]
+  event_emitter: mpsc::Sender<NodeEventEmittedInfo>,
+}
+
+impl<C: ProtoComm> NodeBuilder<C> {
+  pub fn new(
+    data_path: PathBuf,
+    comm: C,
+    #[cfg(feature = "events")] event_emitter: mpsc::Sender<
+      NodeEventEmittedInfo,
+    >,
+  ) -> Self {
+    NodeBuilder {
+      data_path,
+      comm,
+      network_id: 0,
+      name: None,
+      initial_peers: vec![],
+      pinned_peers: vec![],
+      mode: NodeMode::default(),
+      state_snapshot: None,
+      api_config: ApiConfig::default(),
+      net_config: NetworkConfig::default(),
+      mine_config: MineConfig::default(),
+      miner_comm: None,
+      max_load_blocks: None,
+      storage: StorageMode::default(),
+      prune_depth: None,
+      #[cfg(feature = "events")]
+      event_emitter,
+    }
+  }
+
+  pub fn network_id(mut self, network_id: u32) -> Self {
+    self.network_id = network_id;
+    self
+  }
+
+  pub fn name(mut self, name: Option<String>) -> Self {
+    self.name = name;
+    self
+  }
+
+  pub fn initial_peers(mut self, peers: Vec<C::Address>) -> Self {
+    self.initial_peers = peers;
+    self
+  }
+
+  pub fn pinned_peers(mut self, peers: Vec<C::Address>) -> Self {
+    self.pinned_peers = peers;
+    self
+  }
+
+  pub fn mode(mut self, mode: NodeMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  pub fn state_snapshot(mut self, path: Option<PathBuf>) -> Self {
+    self.state_snapshot = path;
+    self
+  }
+
+  pub fn api_config(mut self, config: ApiConfig) -> Self {
+    self.api_config = config;
+    self
+  }
+
+  pub fn net_config(mut self, config: NetworkConfig) -> Self {
+    self.net_config = config;
+    self
+  }
+
+  pub fn mine_config(mut self, config: MineConfig) -> Self {
+    self.mine_config = config;
+    self
+  }
+
+  /// Without a miner, the node never mines.
+  pub fn miner_comm(mut self, miner_comm: Option<MinerCommunication>) -> Self {
+    self.miner_comm = miner_comm;
+    self
+  }
+
+  /// See `NodeConfig::max_load_blocks`.
+  pub fn max_load_blocks(mut self, max: Option<u64>) -> Self {
+    self.max_load_blocks = max;
+    self
+  }
+
+  /// See `NodeConfig::storage` and `NodeConfig::prune_depth`.
+  pub fn storage(mut self, storage: StorageMode, depth: Option<u64>) -> Self {
+    self.storage = storage;
+    self.prune_depth = depth;
+    self
+  }
+
+  pub fn build(
+    self,
+  ) -> Result<(mpsc::SyncSender<NodeRequest<C>>, Node<C>), String> {
+    Node::new(self)
+  }
+}
+
 // Main Thread
 // ===========
 
@@ -1712,7 +5385,8 @@ pub fn start<C: ProtoComm + 'static>(
   config: NodeConfig,
   comm: C,
   initial_peers: Vec<C::Address>,
-) {
+  pinned_peers: Vec<C::Address>,
+) -> Result<(), String> {
   eprintln!("Starting Kindelia node...");
   eprintln!("Store path: {:?}", config.data_path);
   eprintln!("Network ID: {:#X}", config.network_id);
@@ -1733,20 +5407,35 @@ pub fn start<C: ProtoComm + 'static>(
     event_tx
   };
 
-  // Mining
-  let (miner_comm, miner_thrds) = spawn_miner(config.mining, event_tx.clone());
+  // Mining (followers never mine)
+  let mut mine_config = config.mining.clone();
+  if config.mode == NodeMode::Follower {
+    mine_config.enabled = false;
+  }
+  let (miner_comm, miner_thrds) =
+    spawn_miner(mine_config, event_tx.clone());
   threads.extend(miner_thrds.into_iter());
 
   // Node state object
-  let (node_query_sender, node) = Node::new(
+  let (node_query_sender, node) = NodeBuilder::new(
     config.data_path,
-    config.network_id,
-    initial_peers,
     comm,
-    miner_comm,
     #[cfg(feature = "events")]
     event_tx,
-  );
+  )
+  .network_id(config.network_id)
+  .name(config.name)
+  .initial_peers(initial_peers)
+  .pinned_peers(pinned_peers)
+  .mode(config.mode)
+  .state_snapshot(config.import_state)
+  .api_config(config.api.clone().unwrap_or_default())
+  .net_config(config.network)
+  .mine_config(config.mining)
+  .miner_comm(miner_comm)
+  .max_load_blocks(config.max_load_blocks)
+  .storage(config.storage, config.prune_depth)
+  .build()?;
 
   // Spawns the API thread
   if let Some(api_config) = config.api {
@@ -1766,6 +5455,8 @@ pub fn start<C: ProtoComm + 'static>(
   for thread in threads {
     thread.join().unwrap();
   }
+
+  Ok(())
 }
 
 fn spawn_miner(
@@ -1781,6 +5472,7 @@ fn spawn_miner(
       miner_loop(
         miner_comm_0,
         mine_config.slow_mining,
+        mine_config.intensity,
         #[cfg(feature = "events")]
         event_tx,
       );
@@ -172,7 +172,7 @@ impl DiskSer for CompFunc {
         return Err(Error::from(ErrorKind::UnexpectedEof));
       }
       let func = &Func::proto_deserialized(&bit_vec::BitVec::from_bytes(&buf))
-        .ok_or_else(|| Error::from(ErrorKind::InvalidData))?; // invalid data? which error is better?
+        .map_err(|_| Error::from(ErrorKind::InvalidData))?; // invalid data? which error is better?
       let func = compile_func(func, false)
         .map_err(|_| Error::from(ErrorKind::InvalidData))?; // TODO: return error in deserialization? 
       Ok(Some(func))
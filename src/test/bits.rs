@@ -83,3 +83,106 @@ pub fn test_serializer_2() {
   let gots = deserialize_list(&bits, &mut index, &mut g_names).unwrap();
   assert_eq!(vals, gots);
 }
+
+#[test]
+pub fn proto_deserialized_errors() {
+  use crate::bits::ProtoDeserializeError;
+  use crate::node::{Block, Body};
+  let block = Block::new(1, u256(1), 2, 3, Body { data: vec![0; 100] });
+  let bits = block.proto_serialized();
+  // truncated input
+  let mut short = bits.clone();
+  short.truncate(bits.len() - 8);
+  assert!(matches!(
+    Block::proto_deserialized(&short),
+    Err(ProtoDeserializeError::Truncated { .. })
+  ));
+  // invalid tag
+  let mut invalid = bits.clone();
+  invalid.set(7, true);
+  assert!(matches!(
+    Block::proto_deserialized(&invalid),
+    Err(ProtoDeserializeError::Invalid { .. })
+  ));
+  // trailing garbage is ignored, as by older nodes, unless asked not to
+  let mut long = bits.clone();
+  long.push(true);
+  assert!(Block::proto_deserialized(&long).is_ok());
+  assert!(matches!(
+    Block::proto_deserialized_exact(&long),
+    Err(ProtoDeserializeError::TrailingBits { .. })
+  ));
+  // zero padding is accepted
+  let mut padded = bits.clone();
+  padded.grow(7, false);
+  assert!(Block::proto_deserialized_exact(&padded).is_ok());
+}
+
+#[test]
+pub fn blocks_start_with_their_format_version() {
+  use crate::bits::{serialized_block_size, BLOCK_FORMAT_VERSION};
+  use crate::node::{Block, Body};
+  let body = Body { data: vec![7; 10] };
+  let block = Block::new(BLOCK_FORMAT_VERSION, u256(1), 2, 3, body);
+  let bits = block.proto_serialized();
+  assert_eq!(bits.len() as u128, serialized_block_size(&block) * 8);
+  let mut index = 0;
+  let version = crate::bits::deserialize_fixlen(8, &bits, &mut index);
+  assert_eq!(version, Some(BLOCK_FORMAT_VERSION));
+  // Blocks keep the version they were read with
+  let mut old = block.clone();
+  old.version = 0;
+  let read = Block::proto_deserialized(&old.proto_serialized()).unwrap();
+  assert_eq!(read.version, 0);
+  // Unknown versions are refused
+  let mut future = bits.clone();
+  future.set(7, true);
+  assert!(Block::proto_deserialized(&future).is_err());
+}
+
+#[test]
+pub fn legacy_block_files_are_told_apart() {
+  use crate::crypto::Keccakable;
+  use crate::node::{Block, Body};
+  // Its parent's hash starts with a byte that reads as a valid version
+  let block = Block::new(0, u256(1), 2, 3, Body { data: vec![7; 10] });
+  let bytes = block.to_bytes();
+  let (read, legacy) = Block::from_file_bytes(&bytes).unwrap();
+  assert!(!legacy);
+  assert_eq!(read.keccak256(), block.keccak256());
+  // Earlier builds wrote the same layout, without the version byte
+  let (read, legacy) = Block::from_file_bytes(&bytes[1..]).unwrap();
+  assert!(legacy);
+  assert_eq!(read.version, 0);
+  assert_eq!(read.keccak256(), block.keccak256());
+  assert!(Block::from_file_bytes(&bytes[2..]).is_err());
+}
+
+#[test]
+pub fn version_0_blocks_keep_the_old_message_layout() {
+  use crate::bits::serialize_fixlen;
+  use crate::node::{Block, Body};
+  let mut block = Block::new(0, u256(1), 2, 3, Body { data: vec![7; 10] });
+  let blocks = vec![block.clone()];
+  let message: Message<net::Address> =
+    Message::NoticeTheseBlocks { magic: 9, gossip: true, blocks, peers: vec![] };
+  // As written by nodes without block versions
+  let mut old = BitVec::new();
+  serialize_fixlen(32, 9, &mut old);
+  serialize_fixlen(4, 0, &mut old);
+  serialize_fixlen(1, 1, &mut old);
+  old.push(true);
+  old.extend(block.proto_serialized().iter().skip(8));
+  old.push(false);
+  old.push(false);
+  assert_eq!(message.proto_serialized(), old);
+  // Version 1 blocks need the versioned layout, which reads back the same
+  block.version = 1;
+  let blocks = vec![block];
+  let message: Message<net::Address> =
+    Message::NoticeTheseBlocks { magic: 9, gossip: true, blocks, peers: vec![] };
+  let bits = message.proto_serialized();
+  assert_ne!(bits.len(), old.len());
+  let read: Message<net::Address> = Message::proto_deserialized(&bits).unwrap();
+  assert_eq!(format!("{:?}", read), format!("{:?}", message));
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+use primitive_types::U256;
+
+use crate::config::NetworkConfig;
+use crate::crypto::Keccakable;
+#[cfg(feature = "events")]
+use crate::events::NodeEventEmittedInfo;
+use crate::net::{ProtoComm, SendStatus};
+use crate::node::{self, HashedBlock, Message, Node, NodeBuilder};
+
+use super::util::{temp_dir, TempPath};
+
+// In-process network
+// ==================
+
+type Mailboxes = Arc<Mutex<HashMap<u32, VecDeque<(u32, Message<u32>)>>>>;
+
+/// An in-memory network connecting nodes on the same process, addressed by
+/// plain `u32` values. Sent messages wait on the receiver's mailbox until it
+/// polls it, so tests decide exactly when each node handles its messages.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+  mailboxes: Mailboxes,
+  congested: Arc<AtomicBool>,
+}
+
+impl MemoryNetwork {
+  pub fn new() -> Self {
+    MemoryNetwork::default()
+  }
+
+  /// Registers `addr` on the network, returning its transport.
+  pub fn connect(&self, addr: u32) -> MemoryComm {
+    self.mailboxes.lock().unwrap().insert(addr, VecDeque::new());
+    MemoryComm {
+      addr,
+      mailboxes: self.mailboxes.clone(),
+      congested: self.congested.clone(),
+    }
+  }
+
+  /// While congested, every send is dropped and reported as such.
+  pub fn set_congested(&self, congested: bool) {
+    self.congested.store(congested, Ordering::SeqCst);
+  }
+
+  /// How many messages are waiting to be received.
+  pub fn pending(&self) -> usize {
+    self.mailboxes.lock().unwrap().values().map(|m| m.len()).sum()
+  }
+}
+
+/// `ProtoComm` implementation over a `MemoryNetwork` mailbox.
+pub struct MemoryComm {
+  addr: u32,
+  mailboxes: Mailboxes,
+  congested: Arc<AtomicBool>,
+}
+
+impl ProtoComm for MemoryComm {
+  type Address = u32;
+
+  fn proto_send(
+    &mut self,
+    addresses: Vec<u32>,
+    message: &Message<u32>,
+  ) -> SendStatus {
+    if self.congested.load(Ordering::SeqCst) {
+      return SendStatus::Congested;
+    }
+    let mut mailboxes = self.mailboxes.lock().unwrap();
+    for addr in addresses {
+      if let Some(mailbox) = mailboxes.get_mut(&addr) {
+        mailbox.push_back((self.addr, message.clone()));
+      }
+    }
+    SendStatus::Sent
+  }
+
+  fn proto_recv(
+    &mut self,
+    _buffer: &mut [u8],
+    messages: &mut Vec<(u32, Message<u32>)>,
+  ) {
+    let mut mailboxes = self.mailboxes.lock().unwrap();
+    if let Some(mailbox) = mailboxes.get_mut(&self.addr) {
+      messages.extend(mailbox.drain(..));
+    }
+  }
+
+  fn get_addr(&self) -> u32 {
+    self.addr
+  }
+}
+
+// Test nodes
+// ==========
+
+/// Network settings for test nodes. Test networks use block format version 1
+/// right after genesis.
+pub fn test_net_config() -> NetworkConfig {
+  NetworkConfig { block_v1_height: Some(1), ..NetworkConfig::default() }
+}
+
+/// A node wired to a `MemoryNetwork`, plus what must live as long as it.
+pub struct TestNode {
+  pub node: Node<MemoryComm>,
+  #[cfg(feature = "events")]
+  pub events: mpsc::Receiver<NodeEventEmittedInfo>,
+  _dir: TempPath,
+}
+
+impl TestNode {
+  pub fn new(network: &MemoryNetwork, addr: u32, peers: Vec<u32>) -> Self {
+    TestNode::with_net_config(network, addr, peers, test_net_config())
+  }
+
+  pub fn with_net_config(
+    network: &MemoryNetwork,
+    addr: u32,
+    peers: Vec<u32>,
+    net_config: NetworkConfig,
+  ) -> Self {
+    let dir = temp_dir();
+    std::fs::create_dir_all(dir.path.join("blocks")).unwrap();
+    #[cfg(feature = "events")]
+    let (event_tx, events) = mpsc::channel();
+    let (_, node) = NodeBuilder::new(
+      dir.path.clone(),
+      network.connect(addr),
+      #[cfg(feature = "events")]
+      event_tx,
+    )
+    .initial_peers(peers)
+    .net_config(net_config)
+    .build()
+    .unwrap();
+    TestNode {
+      node,
+      #[cfg(feature = "events")]
+      events,
+      _dir: dir,
+    }
+  }
+}
+
+/// Delivers messages until every mailbox is empty. Panics if the nodes keep
+/// talking for more than `max_rounds` rounds.
+pub fn run_until_idle(
+  network: &MemoryNetwork,
+  nodes: &mut [TestNode],
+  max_rounds: usize,
+) {
+  for _ in 0..max_rounds {
+    if network.pending() == 0 {
+      return;
+    }
+    for node in nodes.iter_mut() {
+      node.node.receive_message();
+    }
+  }
+  panic!("Nodes still exchanging messages after {} rounds.", max_rounds);
+}
+
+/// Mines an empty block on top of `prev`, at `time`, with the initial target.
+pub fn mine_block(prev: U256, time: u128) -> HashedBlock {
+  mine_block_with(prev, time, vec![])
+}
+
+/// Like `mine_block`, but including `transactions` on the block body.
+pub fn mine_block_with(
+  prev: U256,
+  time: u128,
+  transactions: Vec<node::Transaction>,
+) -> HashedBlock {
+  // Test networks use version 1 right after genesis; see `test_net_config`
+  mine_block_in(1, prev, time, transactions)
+}
+
+/// Like `mine_block_with`, but in the block format `version`.
+pub fn mine_block_in(
+  version: u64,
+  prev: U256,
+  time: u128,
+  transactions: Vec<node::Transaction>,
+) -> HashedBlock {
+  let body = node::Body::fill_from(transactions);
+  let block = node::Block::new(version, prev, time, 0, body);
+  mine(block)
+}
+
+/// Time of the block at `height` on chains built by `build_chain`.
+pub fn chain_time(height: u128) -> u128 {
+  1_650_000_000_000 + (height - 1) * node::TIME_PER_BLOCK
+}
+
+/// Mines `count` empty blocks on top of the node's tip, one block interval
+/// apart, and adds them. Returns the hashes of the main chain from its base,
+/// indexed by height above it. Fixed timestamps keep the chain, and so its
+/// work, the same on every run.
+pub fn build_chain(node: &mut Node<MemoryComm>, count: u128) -> Vec<U256> {
+  let start = node.height[&node.tip];
+  for height in start + 1..=start + count {
+    let block = mine_block(node.tip, chain_time(height));
+    node.add_block(&block);
+  }
+  let mut chain = vec![node.tip];
+  while *chain.last().unwrap() != node.base {
+    chain.push(node.block[chain.last().unwrap()].prev);
+  }
+  chain.reverse();
+  chain
+}
+
+// Bumps the block's `meta` until its hash meets the initial target
+fn mine(mut block: node::Block) -> HashedBlock {
+  let target = node::initial_target();
+  loop {
+    let hashed = block.hashed();
+    if U256::from(hashed.get_hash()) >= target {
+      return hashed;
+    }
+    block = hashed.take();
+    block.meta = block.meta.wrapping_add(1);
+  }
+}
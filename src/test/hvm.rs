@@ -8,6 +8,7 @@ use rstest::rstest;
 use rstest_reuse::{apply, template};
 
 use crate::common::{Name, U120};
+use crate::constants;
 use crate::hvm::{
   self, init_u128_map, read_statements, readback_term, show_term, view_statements,
   view_term, Rollback, Runtime, StatementInfo, Term, Heap
@@ -144,6 +145,39 @@ pub fn stack_overflow2(temp_dir: TempPath) {
   rt.run_statements_from_code(COUNTER_STACKOVERFLOW, false, true);
 }
 
+#[apply(hvm_cases)]
+pub fn exported_state_imports_to_the_same_state(
+  fn_names: &[&str],
+  pre_code: &str,
+  code: &str,
+  validators: &[util::Validator],
+  temp_dir: TempPath,
+) {
+  let mut rt = init_runtime(&temp_dir.path.join("source"));
+  rt.run_statements_from_code(pre_code, true, true);
+  advance(&mut rt, 1000, Some(code), validators);
+  let state = temp_dir.path.join("state");
+  rt.export_state(&state).unwrap();
+  // Exports hold the state of the latest snapshot, not the current heap
+  let last = match *rt.get_back() {
+    Rollback::Cons { head, .. } => rt.get_heap(head).tick,
+    Rollback::Nil => 0,
+  };
+  rt.rollback(last);
+  let expected = RuntimeStateTest::new(fn_names, &mut rt);
+  let mut imported = init_runtime(&temp_dir.path.join("imported"));
+  imported.import_state(&state).unwrap();
+  assert_eq!(imported.get_tick(), last);
+  assert_eq!(RuntimeStateTest::new(fn_names, &mut imported), expected);
+  // The imported runtime keeps going like the source
+  advance(&mut rt, 1100, Some(code), validators);
+  advance(&mut imported, 1100, Some(code), validators);
+  assert_eq!(
+    RuntimeStateTest::new(fn_names, &mut imported),
+    RuntimeStateTest::new(fn_names, &mut rt)
+  );
+}
+
 #[apply(hvm_cases)]
 #[ignore = "heap persistence disabled"]
 pub fn persistence1(
@@ -673,6 +707,115 @@ fn operators_cases(#[case] code: &str, #[case] expected: u128) {
   })
 }
 
+#[rstest]
+fn statement_mana_cap(temp_dir: TempPath) {
+  let code = "
+    fun (Loop n) {
+      (Loop #0) = #0
+      (Loop n) = (Loop (- n #1))
+    }
+    run {
+      (Done (Loop #1000))
+    }
+  ";
+  let mut rt = init_runtime(&temp_dir.path);
+  rt.set_statement_mana(Some(100));
+  let results = rt.run_statements_from_code(code, true, false);
+  assert!(results[0].is_ok());
+  assert!(results[1].is_err());
+  // The same statement fits a larger cap
+  rt.set_statement_mana(Some(1_000_000));
+  let results = rt.run_statements_from_code(code, true, false);
+  assert!(results[1].is_ok());
+}
+
+#[rstest]
+fn read_state_size_limit(temp_dir: TempPath) {
+  let code = "
+    ctr {Cons h t}
+    ctr {Nil}
+    fun (List) {
+      (List) = {Nil}
+    } with {
+      {Cons #1 {Cons #2 {Cons #3 {Cons #4 {Nil}}}}}
+    }
+  ";
+  let mut rt = init_runtime(&temp_dir.path);
+  rt.run_statements_from_code(code, true, false);
+  let name = Name::from_str("List").unwrap();
+  assert!(rt.read_disk_as_term(name.into(), Some(4)).is_none());
+  assert!(rt.read_disk_as_term(name.into(), Some(1 << 16)).is_some());
+}
+
+#[rstest]
+fn init_runtime_reports_unusable_heaps_path(temp_file: TempPath) {
+  let genesis_stmts =
+    hvm::parse_code(constants::GENESIS_CODE).expect("Genesis code parses.");
+  let heaps_path = temp_file.path.join("heaps");
+  assert!(hvm::init_runtime(heaps_path, &genesis_stmts).is_err());
+}
+
+#[rstest]
+fn dry_run_reports_state_diffs_without_committing(temp_dir: TempPath) {
+  let mut rt = init_runtime(&temp_dir.path);
+  rt.run_statements_from_code(PRE_COUNTER, true, false);
+  let code = "
+    run {
+      ask (Call 'Store' {StoreAdd});
+      (Done #0)
+    }
+  ";
+  let statements = hvm::parse_code(code).unwrap();
+  let (results, diffs) = rt.diff_statements(&statements, 16, 1 << 16);
+  assert!(results.iter().all(|r| r.is_ok()));
+  assert_eq!(diffs.len(), 1);
+  assert_eq!(diffs[0].name, Name::from_str("Store").unwrap());
+  let old = view_term(diffs[0].old.as_ref().unwrap());
+  let new = view_term(diffs[0].new.as_ref().unwrap());
+  assert_eq!(old.matches("Succ").count(), 0);
+  assert_eq!(new.matches("Succ").count(), 1);
+  // The state is left as it was
+  let store = U120::from(Name::from_str("Store").unwrap());
+  let state = rt.read_disk_as_term(store, None).unwrap();
+  assert_eq!(view_term(&state), old);
+  // And the number of changes reported is bounded
+  let (_, diffs) = rt.diff_statements(&statements, 0, 1 << 16);
+  assert!(diffs.is_empty());
+}
+
+#[rstest]
+fn panicking_statements_fail_with_their_changes_undone(temp_dir: TempPath) {
+  let code = "
+    ctr {Cell_Break}
+    fun (Cell action) {
+      (Cell {Cell_Break}) =
+        ask x = (Take);
+        ask (Save #7);
+        (Done (% x #0))
+    } with {
+      #1
+    }
+  ";
+  let mut rt = init_runtime(&temp_dir.path);
+  rt.run_statements_from_code(code, true, false);
+  let code = "
+    run {
+      ask x = (Call 'Cell' {Cell_Break});
+      (Done x)
+    }
+  ";
+  let results = rt.run_statements_from_code(code, true, false);
+  // Taking the remainder by zero panics the runtime
+  let err = results[0].as_ref().unwrap_err();
+  assert!(err.err.starts_with("Statement panicked"), "{}", err.err);
+  let cell = U120::from(Name::from_str("Cell").unwrap());
+  let state = rt.read_disk_as_term(cell, None).unwrap();
+  assert_eq!(view_term(&state), "#1");
+  // And the runtime is still usable
+  let results = rt.run_statements_from_code("run { (Done #2) }", true, false);
+  assert!(results[0].is_ok());
+}
+
 // ===========================================================
 // Codes
 pub const PRE_COUNTER: &'static str = "
@@ -7,6 +7,7 @@
 #![allow(clippy::single_component_path_imports)]
 
 // aux modules
+mod harness;
 mod strategies;
 mod util;
 
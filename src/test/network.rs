@@ -8,11 +8,17 @@ use std::thread;
 
 use crate::bits;
 use crate::config;
+use crate::crypto::Keccakable;
 #[cfg(feature = "events")]
 use crate::events;
 use crate::net;
 use crate::node;
+use crate::util::U256;
 
+use super::harness::{
+  mine_block, run_until_idle, test_net_config, MemoryComm, MemoryNetwork,
+  TestNode,
+};
 use super::util::temp_dir;
 
 #[test]
@@ -65,13 +71,20 @@ fn network() {
 
       let node_cfg = config::NodeConfig {
         network_id: 0,
+        name: None,
         data_path,
+        mode: config::NodeMode::Full,
+        import_state: None,
+        max_load_blocks: None,
+        storage: config::StorageMode::Archive,
+        prune_depth: None,
+        network: config::NetworkConfig::default(),
         mining: mine_cfg,
         ui: Some(config::UiConfig { json: true, tags: vec![] }),
         api: None,
         ws: Some(ws_config), // Some(ws_config),
       };
-      node::start(node_cfg, socket, initial_peers);
+      node::start(node_cfg, socket, initial_peers, vec![]).unwrap();
     });
     threads.push(socket_thread);
   }
@@ -92,6 +105,335 @@ fn network() {
   }
 }
 
+#[test]
+fn gossiped_block_reaches_peer() {
+  let network = MemoryNetwork::new();
+  let mut nodes =
+    [TestNode::new(&network, 1, vec![2]), TestNode::new(&network, 2, vec![1])];
+  let genesis = nodes[0].node.genesis_hash;
+  let block = mine_block(genesis, crate::util::get_time());
+  nodes[0].node.add_block(&block);
+  nodes[0].node.send_blocks_to(vec![2], true, vec![(*block).clone()], 0);
+  run_until_idle(&network, &mut nodes, 16);
+  assert_eq!(nodes[1].node.tip, nodes[0].node.tip);
+  assert_ne!(nodes[1].node.tip, genesis);
+  assert_eq!(nodes[1].node.message_counts.notice_these_blocks, 1);
+}
+
+#[test]
+fn message_rate_is_sampled_per_interval() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  node.message_sample = (1000, 0);
+  node.message_counts.ping = 10;
+  node.message_counts.pong = 10;
+  node.sample_message_rate(6000);
+  assert_eq!(node.message_rate, 4.0);
+  // Only the messages since the previous sample count
+  node.message_counts.ping = 25;
+  node.sample_message_rate(11000);
+  assert_eq!(node.message_rate, 3.0);
+}
+
+#[test]
+fn node_requests_missing_ancestors() {
+  let network = MemoryNetwork::new();
+  let mut nodes =
+    [TestNode::new(&network, 1, vec![2]), TestNode::new(&network, 2, vec![1])];
+  let mut prev = nodes[0].node.genesis_hash;
+  let mut tip_block = None;
+  let time = crate::util::get_time() - 1000;
+  for i in 0..5 {
+    let block = mine_block(prev, time + i);
+    nodes[0].node.add_block(&block);
+    prev = U256::from(block.get_hash());
+    tip_block = Some((*block).clone());
+  }
+  // Only the tip is sent; the rest must be requested
+  nodes[0].node.send_blocks_to(vec![2], true, vec![tip_block.unwrap()], 0);
+  run_until_idle(&network, &mut nodes, 16);
+  assert_eq!(nodes[1].node.tip, nodes[0].node.tip);
+  assert_eq!(nodes[1].node.height[&nodes[1].node.tip], 5);
+}
+
+#[test]
+fn node_syncs_before_mining() {
+  let network = MemoryNetwork::new();
+  let mut nodes =
+    [TestNode::new(&network, 1, vec![2]), TestNode::new(&network, 2, vec![1])];
+  // A fresh node waits to hear from the network
+  assert!(nodes[1].node.is_syncing());
+  let mut prev = nodes[0].node.genesis_hash;
+  let mut tip_block = None;
+  let time = crate::util::get_time() - 1000;
+  for i in 0..3 {
+    let block = mine_block(prev, time + i);
+    nodes[0].node.add_block(&block);
+    prev = U256::from(block.get_hash());
+    tip_block = Some((*block).clone());
+  }
+  nodes[0].node.send_blocks_to(vec![2], true, vec![tip_block.unwrap()], 0);
+  // Received the tip, but still missing its ancestors
+  nodes[1].node.receive_message();
+  assert!(nodes[1].node.is_syncing());
+  run_until_idle(&network, &mut nodes, 16);
+  assert!(!nodes[1].node.is_syncing());
+}
+
+#[test]
+fn syncing_lasts_until_the_node_catches_up_with_a_peer_tip() {
+  let network = MemoryNetwork::new();
+  let mut nodes =
+    [TestNode::new(&network, 1, vec![2]), TestNode::new(&network, 2, vec![1])];
+  let mut chain = vec![nodes[0].node.genesis_hash];
+  let time = crate::util::get_time() - 1000;
+  for i in 0..6 {
+    let block = mine_block(*chain.last().unwrap(), time + i);
+    nodes[0].node.add_block(&block);
+    chain.push(U256::from(block.get_hash()));
+  }
+  let block = |node: &TestNode, height: usize| {
+    (*node.node.block[&chain[height]]).clone()
+  };
+  // A block that isn't a peer's tip doesn't end the grace period
+  let first = block(&nodes[0], 1);
+  nodes[0].node.send_blocks_to(vec![2], false, vec![first], 0);
+  nodes[1].node.receive_message();
+  assert_eq!(nodes[1].node.tip, chain[1]);
+  assert!(nodes[1].node.is_syncing());
+  let tip = block(&nodes[0], 6);
+  nodes[0].node.send_blocks_to(vec![2], true, vec![tip], 0);
+  run_until_idle(&network, &mut nodes, 16);
+  assert!(!nodes[1].node.is_syncing());
+  // Far below the peer's tip, the node syncs again
+  nodes[1].node.set_tip(chain[2]).unwrap();
+  assert!(nodes[1].node.is_syncing());
+  // But not within the margin
+  let height = 6 - node::SYNC_HEIGHT_MARGIN as usize;
+  nodes[1].node.set_tip(chain[height]).unwrap();
+  assert!(!nodes[1].node.is_syncing());
+}
+
+#[test]
+fn syncing_is_bounded_in_time() {
+  let network = MemoryNetwork::new();
+  let mut nodes =
+    [TestNode::new(&network, 1, vec![2]), TestNode::new(&network, 2, vec![1])];
+  let time = crate::util::get_time() - 1000;
+  let a1 = mine_block(nodes[0].node.genesis_hash, time);
+  let a2 = mine_block(U256::from(a1.get_hash()), time + 1);
+  // An orphan arrives, and its parent is never found
+  nodes[0].node.send_blocks_to(vec![2], true, vec![(*a2).clone()], 0);
+  nodes[1].node.receive_message();
+  let now = crate::util::get_time();
+  assert!(nodes[1].node.is_syncing_at(now));
+  // Giving up on the ancestor doesn't end the sync early
+  nodes[1].node.block_requests.clear();
+  assert!(nodes[1].node.is_syncing_at(now));
+  // But it ends on its own, even if peers keep sending orphans
+  let later = now + node::SYNC_TIMEOUT;
+  let a3 = mine_block(U256::from(a2.get_hash()), time + 2);
+  nodes[0].node.send_blocks_to(vec![2], true, vec![(*a3).clone()], 0);
+  nodes[1].node.receive_message();
+  assert!(!nodes[1].node.is_syncing_at(later));
+}
+
+#[test]
+fn gossip_backs_off_while_congested() {
+  let network = MemoryNetwork::new();
+  let mut nodes =
+    [TestNode::new(&network, 1, vec![2]), TestNode::new(&network, 2, vec![1])];
+  let ping = node::Message::Ping { magic: 0 };
+  network.set_congested(true);
+  nodes[0].node.gossip(1, &ping);
+  assert!(nodes[0].node.is_congested());
+  // Even if the socket recovers, gossip waits for the backoff to end
+  network.set_congested(false);
+  nodes[0].node.gossip(1, &ping);
+  assert_eq!(network.pending(), 0);
+  nodes[0].node.congested_until = 0;
+  nodes[0].node.gossip(1, &ping);
+  assert_eq!(network.pending(), 1);
+  // Both changes are reported
+  #[cfg(feature = "events")]
+  {
+    let status: Vec<_> = nodes[0]
+      .events
+      .try_iter()
+      .filter_map(|(event, _)| match event {
+        events::NodeEventType::Status { event } => Some(event),
+        _ => None,
+      })
+      .collect();
+    assert!(matches!(
+      status[..],
+      [events::StatusEvent::Congested, events::StatusEvent::Uncongested]
+    ));
+  }
+}
+
+#[test]
+fn competing_peer_tips_flag_a_chain_split() {
+  let network = MemoryNetwork::new();
+  let mut nodes = [
+    TestNode::new(&network, 1, vec![2, 3]),
+    TestNode::new(&network, 2, vec![1]),
+    TestNode::new(&network, 3, vec![1]),
+  ];
+  let genesis = nodes[0].node.genesis_hash;
+  let time = crate::util::get_time() - 1000;
+  let a1 = mine_block(genesis, time);
+  let b1 = mine_block(genesis, time + 1);
+  // Each peer gossips a different tip at the same height
+  nodes[1].node.send_blocks_to(vec![1], true, vec![(*a1).clone()], 0);
+  nodes[2].node.send_blocks_to(vec![1], true, vec![(*b1).clone()], 0);
+  nodes[0].node.receive_message();
+  let node = &nodes[0].node;
+  let now = crate::util::get_time();
+  assert_eq!(node.competing_tip_heights(now), vec![1]);
+  // Only a split that persists is reported
+  assert!(!node.is_chain_split(now));
+  assert!(node.is_chain_split(now + node::CHAIN_SPLIT_DELAY));
+  // Stale reports don't count
+  let later = now + node::PEER_TIP_WINDOW + 1;
+  assert!(node.competing_tip_heights(later).is_empty());
+}
+
+#[test]
+fn node_serves_pending_blocks() {
+  use crate::net::ProtoComm;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = crate::util::get_time() - 1000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a2 = mine_block(U256::from(a1.get_hash()), time + 1);
+  // Without its parent, the block waits on the pending set
+  node.add_block(&a2);
+  let a2_hash = U256::from(a2.get_hash());
+  assert!(node.pending.contains_key(&a2_hash));
+  let mut peer = network.connect(2);
+  let request = node::Message::GiveMeThatBlock { magic: 0, bhash: a2_hash };
+  peer.proto_send(vec![1], &request);
+  node.receive_message();
+  let mut answers = vec![];
+  peer.proto_recv(&mut [], &mut answers);
+  assert_eq!(answers.len(), 1);
+  match &answers[0].1 {
+    node::Message::NoticeTheseBlocks { blocks, .. } => {
+      assert_eq!(blocks.len(), 1);
+      assert_eq!(U256::from(blocks[0].clone().hashed().get_hash()), a2_hash);
+    }
+    _ => panic!("Expected the requested block"),
+  }
+}
+
+#[test]
+fn operators_can_request_a_block() {
+  use crate::net::ProtoComm;
+  let network = MemoryNetwork::new();
+  let mut peer = network.connect(2);
+  let mut test_node = TestNode::new(&network, 1, vec![2]);
+  let node = &mut test_node.node;
+  let bhash = U256::from(12345);
+  node.request_block_from_peers(bhash).unwrap();
+  let mut messages = vec![];
+  peer.proto_recv(&mut [], &mut messages);
+  let asked = messages.iter().any(|(from, msg)| match msg {
+    node::Message::GiveMeThatBlock { bhash: h, .. } => {
+      *from == 1 && *h == bhash
+    }
+    _ => false,
+  });
+  assert!(asked);
+  // It is retried like any other request
+  assert!(node.block_requests.contains_key(&bhash));
+  // Blocks already included aren't requested
+  let genesis_hash = node.genesis_hash;
+  assert!(node.request_block_from_peers(genesis_hash).is_err());
+}
+
+#[test]
+fn block_requests_are_retried_up_to_their_limit() {
+  use crate::net::ProtoComm;
+  let network = MemoryNetwork::new();
+  let mut peer = network.connect(2);
+  let mut test_node = TestNode::new(&network, 1, vec![2]);
+  let node = &mut test_node.node;
+  let time = crate::util::get_time() - 1000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a2 = mine_block(U256::from(a1.get_hash()), time + 1);
+  let a1_hash = U256::from(a1.get_hash());
+  let a2_hash = U256::from(a2.get_hash());
+  node.add_block(&a2);
+  let expire = |node: &mut node::Node<MemoryComm>, sent_at: u128| {
+    node.block_requests.get_mut(&a1_hash).unwrap().sent_at = sent_at;
+    node.retry_block_requests();
+  };
+  node.request_missing_ancestor(2, &a2_hash);
+  for _ in 1..node::BLOCK_REQUEST_MAX_ATTEMPTS {
+    // Gossip of the orphan again doesn't restart the count
+    node.request_missing_ancestor(2, &a2_hash);
+    expire(node, 0);
+  }
+  // The only peer is asked again instead of the request being dropped
+  let mut messages = vec![];
+  peer.proto_recv(&mut [], &mut messages);
+  let asked = messages
+    .iter()
+    .filter(|(_, msg)| match msg {
+      node::Message::GiveMeThatBlock { bhash, .. } => *bhash == a1_hash,
+      _ => false,
+    })
+    .count();
+  assert_eq!(asked as u32, node::BLOCK_REQUEST_MAX_ATTEMPTS);
+  // Once given up, it isn't asked again, but is remembered until orphans
+  // would expire
+  let now = crate::util::get_time();
+  expire(node, now - node::BLOCK_REQUEST_TIMEOUT);
+  node.request_missing_ancestor(2, &a2_hash);
+  assert_eq!(network.pending(), 0);
+  expire(node, 0);
+  assert!(!node.block_requests.contains_key(&a1_hash));
+}
+
+#[test]
+fn node_reports_its_address() {
+  let network = MemoryNetwork::new();
+  let node = TestNode::new(&network, 7, vec![]);
+  assert_eq!(node.node.get_addr(), 7);
+}
+
+#[test]
+fn parse_address_accepts_valid_addresses() {
+  let addr = net::Address::IPv4 { val0: 1, val1: 2, val2: 3, val3: 4, port: 5 };
+  assert_eq!(net::parse_address("1.2.3.4:5"), Ok(addr));
+  let addr = net::Address::IPv4 {
+    val0: 1,
+    val1: 2,
+    val2: 3,
+    val3: 4,
+    port: net::UDP_PORT,
+  };
+  assert_eq!(net::parse_address("1.2.3.4"), Ok(addr));
+}
+
+#[test]
+fn parse_address_rejects_malformed_addresses() {
+  use net::AddrParseError::*;
+  assert_eq!(net::parse_address("1.2.3.4:"), Err(MissingPort));
+  assert_eq!(net::parse_address("1.2.3"), Err(WrongOctetCount { count: 3 }));
+  assert_eq!(
+    net::parse_address("1.2.3.256"),
+    Err(InvalidOctet { octet: "256".to_string() })
+  );
+  assert_eq!(
+    net::parse_address("1.2.3.4:70000"),
+    Err(InvalidPort { port: "70000".to_string() })
+  );
+}
+
 // Simulation implementation
 // ================================
 
@@ -137,20 +479,20 @@ impl net::ProtoComm for SocketMock {
   type Address = u32;
   fn proto_recv(
     &mut self,
-  ) -> Vec<(Self::Address, node::Message<Self::Address>)> {
-    let mut messages = Vec::new();
+    _buffer: &mut [u8],
+    messages: &mut Vec<(Self::Address, node::Message<Self::Address>)>,
+  ) {
     while let Ok(RouterMessage { to_addr, from_addr, msg }) = self.rx.try_recv()
     {
       messages.push((from_addr, msg))
     }
-    messages
   }
 
   fn proto_send(
     &mut self,
     addresses: Vec<Self::Address>,
     message: &node::Message<Self::Address>,
-  ) {
+  ) -> net::SendStatus {
     for addr in addresses {
       self
         .tx
@@ -161,6 +503,7 @@ impl net::ProtoComm for SocketMock {
         })
         .unwrap()
     }
+    net::SendStatus::Sent
   }
 
   fn get_addr(&self) -> Self::Address {
@@ -299,3 +642,168 @@ impl RouterMock {
     }
   }
 }
+
+#[test]
+fn blocks_below_min_work_are_dropped_on_receipt() {
+  use crate::net::ProtoComm;
+  let network = MemoryNetwork::new();
+  let mut peer = network.connect(2);
+  let net_config = config::NetworkConfig {
+    min_block_difficulty: Some(1 << 100),
+    ..test_net_config()
+  };
+  let mut strict = TestNode::with_net_config(&network, 1, vec![], net_config);
+  let mut lenient = TestNode::new(&network, 3, vec![]);
+  let time = crate::util::get_time() - 1000;
+  // Mined with the initial difficulty, way below the floor
+  let block = mine_block(strict.node.genesis_hash, time);
+  let bhash = U256::from(block.get_hash());
+  let notice = node::Message::NoticeTheseBlocks {
+    magic: 0,
+    gossip: false,
+    blocks: vec![block.take()],
+    peers: vec![],
+  };
+  peer.proto_send(vec![1, 3], &notice);
+  strict.node.receive_message();
+  lenient.node.receive_message();
+  let strict_state = strict.node.inclusion_state(&bhash);
+  assert_eq!(strict_state, node::InclusionState::UNSEEN);
+  let lenient_state = lenient.node.inclusion_state(&bhash);
+  assert_eq!(lenient_state, node::InclusionState::INCLUDED);
+}
+
+#[test]
+fn sent_block_count_adapts_to_the_peer_and_congestion() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![2]);
+  let node = &mut test_node.node;
+  node.net_config.send_block_ancestors = 8;
+  let genesis = node.genesis_hash;
+  let time = crate::util::get_time() - 1000;
+  let mut prev = genesis;
+  for i in 0..19 {
+    let block = mine_block(prev, time + i);
+    node.add_block(&block);
+    prev = U256::from(block.get_hash());
+  }
+  // Unknown peers get the configured amount
+  assert_eq!(node.send_block_count(&2, &prev), 8);
+  // Peers far behind get more, up to a bound
+  node.peer_tips.insert(2, (genesis, crate::util::get_time()));
+  assert_eq!(node.send_block_count(&2, &prev), 19);
+  node.net_config.send_block_ancestors = 4;
+  assert_eq!(node.send_block_count(&2, &prev), 16);
+  // Congestion shrinks it, but never below the minimum
+  node.net_config.send_block_ancestors = 8;
+  node.congestion_backoff = node::CONGESTION_BACKOFF_MIN;
+  assert_eq!(node.send_block_count(&2, &prev), 9);
+  node.congestion_backoff = node::CONGESTION_BACKOFF_MAX;
+  let count = node.send_block_count(&2, &prev);
+  assert_eq!(count, node::SEND_BLOCK_ANCESTORS_MIN);
+}
+
+#[test]
+fn paused_nodes_drop_incoming_blocks() {
+  use crate::net::ProtoComm;
+  let network = MemoryNetwork::new();
+  let mut peer = network.connect(2);
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let time = crate::util::get_time() - 1000;
+  let block = mine_block(test_node.node.genesis_hash, time);
+  let bhash = U256::from(block.get_hash());
+  let notice = node::Message::NoticeTheseBlocks {
+    magic: 0,
+    gossip: false,
+    blocks: vec![block.clone().take()],
+    peers: vec![],
+  };
+  test_node.node.pause(time).unwrap();
+  assert!(test_node.node.pause(time).is_err());
+  // Nor are blocks written through the API
+  let genesis = test_node.node.genesis_hash;
+  assert!(test_node.node.set_tip(genesis).is_err());
+  assert!(test_node.node.submit_block(block.take()).is_err());
+  peer.proto_send(vec![1], &notice);
+  test_node.node.receive_message();
+  let state = test_node.node.inclusion_state(&bhash);
+  assert_eq!(state, node::InclusionState::UNSEEN);
+  test_node.node.resume().unwrap();
+  assert!(test_node.node.resume().is_err());
+  peer.proto_send(vec![1], &notice);
+  test_node.node.receive_message();
+  let state = test_node.node.inclusion_state(&bhash);
+  assert_eq!(state, node::InclusionState::INCLUDED);
+}
+
+#[test]
+fn long_pauses_expire() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  node.pause(0).unwrap();
+  node.expire_pause(node::MAX_PAUSE_TIME - 1);
+  assert!(node.is_paused());
+  node.expire_pause(node::MAX_PAUSE_TIME);
+  assert!(!node.is_paused());
+}
+
+#[test]
+fn block_senders_are_remembered() {
+  use crate::net::ProtoComm;
+  let network = MemoryNetwork::new();
+  let mut first = network.connect(2);
+  let mut second = network.connect(3);
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let time = crate::util::get_time() - 1000;
+  let block = mine_block(test_node.node.genesis_hash, time);
+  let bhash = U256::from(block.get_hash());
+  let notice = node::Message::NoticeTheseBlocks {
+    magic: 0,
+    gossip: false,
+    blocks: vec![block.take()],
+    peers: vec![],
+  };
+  first.proto_send(vec![1], &notice);
+  second.proto_send(vec![1], &notice);
+  test_node.node.receive_message();
+  test_node.node.receive_message();
+  assert_eq!(test_node.node.block_sources.get(&bhash), Some(&2));
+  let genesis = test_node.node.genesis_hash;
+  assert_eq!(test_node.node.block_sources.get(&genesis), None);
+}
+
+#[test]
+fn batched_udp_sends_packets_together() {
+  use crate::net::ProtoComm;
+  let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+  receiver.set_nonblocking(true).unwrap();
+  let mut receiver = net::BatchedUdp::new(receiver);
+  // A blocking sender waits for room on its buffer, so no packet is refused
+  // and the number of calls only depends on the batching
+  let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+  let mut sender = net::BatchedUdp::new(sender);
+  let count = 2 * net::SEND_BATCH_SIZE + 1;
+  let addrs = vec![receiver.get_addr(); count];
+  let ping = node::Message::Ping { magic: 7 };
+  assert_eq!(sender.proto_send(addrs, &ping), net::SendStatus::Queued);
+  assert_eq!(sender.send_calls, 0);
+  assert_eq!(sender.flush(), Some(net::SendStatus::Sent));
+  assert_eq!(sender.flush(), None);
+  assert_eq!(sender.sent_packets, count as u64);
+  let expected_calls = if cfg!(target_os = "linux") {
+    count.div_ceil(net::SEND_BATCH_SIZE)
+  } else {
+    count
+  };
+  assert_eq!(sender.send_calls, expected_calls as u64);
+  // Some packets may not fit on the receiver's buffer, so only their content
+  // is checked
+  let mut buffer = vec![0; net::RECV_BUFFER_SIZE];
+  let mut messages = vec![];
+  receiver.proto_recv(&mut buffer, &mut messages);
+  assert!(!messages.is_empty());
+  assert!(messages
+    .iter()
+    .all(|(_, msg)| matches!(msg, node::Message::Ping { magic: 7 })));
+}
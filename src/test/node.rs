@@ -2,10 +2,16 @@ use proptest::collection::vec;
 use proptest::proptest;
 
 use crate::bits::ProtoSerialize;
+use crate::crypto::Keccakable;
 use crate::node;
 use crate::test::strategies::statement;
 use crate::util;
 
+use super::harness::{
+  build_chain, chain_time, mine_block, mine_block_in, mine_block_with,
+  test_net_config, MemoryComm, MemoryNetwork, TestNode,
+};
+
 proptest! {
   #[test]
   fn serialize_deserialize_transaction_into_statement(statements in statement()) {
@@ -16,4 +22,2023 @@ proptest! {
     let s2 = format!("{:?}", statements);
     assert_eq!(s1, s2);
   }
+
+  #[test]
+  fn transaction_from_statement_to_statement(statement in statement()) {
+    let s1 = format!("{:?}", statement);
+    let transaction = node::Transaction::from_statement(&statement);
+    let s2 = format!("{:?}", transaction.to_statement().unwrap());
+    assert_eq!(s1, s2);
+  }
+}
+
+#[test]
+fn fill_up_to_respects_size_cap() {
+  let txs = vec![node::Transaction::new(vec![1; 100]); 20];
+  let body = node::Body::fill_up_to(txs, 500);
+  assert!(body.data.len() <= 500);
+  assert_eq!(node::extract_transactions(&body).len(), 4);
+}
+
+#[test]
+fn fill_with_limits_respects_count_cap() {
+  let txs = vec![node::Transaction::new(vec![1; 10]); 20];
+  let body = node::Body::fill_with_limits(txs.clone(), usize::MAX, 3);
+  assert_eq!(node::extract_transactions(&body).len(), 3);
+  // Space permitting, the protocol limit is the ceiling
+  let body = node::Body::fill_with_limits(txs, usize::MAX, 1000);
+  assert_eq!(node::extract_transactions(&body).len(), 20);
+}
+
+#[test]
+fn block_hex_and_base64_roundtrip() {
+  let stmts = crate::hvm::parse_code(crate::constants::GENESIS_CODE).unwrap();
+  let block = node::build_genesis_block(&stmts);
+  let hash = block.clone().hashed().get_hash().clone();
+  let from_hex = node::Block::from_hex(&block.to_hex()).unwrap();
+  let from_b64 = node::Block::from_base64(&block.to_base64()).unwrap();
+  assert_eq!(from_hex.hashed().get_hash(), &hash);
+  assert_eq!(from_b64.hashed().get_hash(), &hash);
+}
+
+#[test]
+fn peers_store_stays_bounded() {
+  use crate::net::Address;
+  let (event_tx, _event_rx) = std::sync::mpsc::channel();
+  let mut peers = node::PeersStore::with_limits(16, 8);
+  let now = util::get_time();
+  for i in 0..1000_u32 {
+    let [_, val1, val2, val3] = i.to_be_bytes();
+    let address = Address::IPv4 { val0: 10, val1, val2, val3, port: 42000 };
+    let peer = node::Peer { address, seen_at: now + i as u128 };
+    peers.see_peer(peer, i % 2 == 0, event_tx.clone());
+  }
+  let counts = peers.get_counts();
+  assert_eq!(counts.total, 16);
+  assert_eq!(counts.inbound, 8);
+  // Addresses we only heard of aren't outbound until we contact them
+  assert_eq!(counts.outbound, 0);
+  let all: Vec<_> = peers.get_all().iter().map(|peer| peer.address).collect();
+  peers.mark_contacted(&all);
+  assert_eq!(peers.get_counts().outbound, 8);
+}
+
+#[test]
+fn peers_timeout_handles_small_clock() {
+  use crate::net::Address;
+  let (event_tx, _event_rx) = std::sync::mpsc::channel();
+  let mut peers = node::PeersStore::new();
+  let seen_at = util::get_time();
+  let address = Address::IPv4 { val0: 10, val1: 0, val2: 0, val3: 1, port: 1 };
+  peers.see_peer(node::Peer { address, seen_at }, false, event_tx.clone());
+  // A clock smaller than the timeout must not underflow
+  peers.timeout_at(1, event_tx.clone());
+  assert_eq!(peers.get_all_active().len(), 1);
+  peers.timeout_at(seen_at + node::PEER_TIMEOUT + 1, event_tx);
+  assert_eq!(peers.get_all_active().len(), 0);
+}
+
+#[test]
+fn pinned_peer_survives_timeout_and_eviction() {
+  use crate::net::Address;
+  let (event_tx, _event_rx) = std::sync::mpsc::channel();
+  let mut peers = node::PeersStore::with_limits(4, 4);
+  let now = util::get_time();
+  let pinned = Address::IPv4 { val0: 10, val1: 0, val2: 0, val3: 0, port: 1 };
+  peers.pin(pinned);
+  let peer = node::Peer { address: pinned, seen_at: now };
+  peers.see_peer(peer, false, event_tx.clone());
+  // Fills the store with fresher peers, forcing evictions
+  for i in 1..16_u8 {
+    let address =
+      Address::IPv4 { val0: 10, val1: 0, val2: 0, val3: i, port: 1 };
+    let peer = node::Peer { address, seen_at: now + i as u128 };
+    peers.see_peer(peer, false, event_tx.clone());
+  }
+  peers.timeout_at(now + node::PEER_TIMEOUT * 10, event_tx);
+  let active = peers.get_all_active();
+  assert_eq!(active.len(), 1);
+  assert_eq!(active[0].address, pinned);
+}
+
+#[test]
+fn seeded_peers_store_picks_reproducibly() {
+  use crate::net::Address;
+  let (event_tx, _event_rx) = std::sync::mpsc::channel();
+  let now = util::get_time();
+  let seeded_store = |order: Vec<u8>| {
+    let mut peers = node::PeersStore::new();
+    peers.set_seed(42);
+    for i in order {
+      let address =
+        Address::IPv4 { val0: 10, val1: 0, val2: 0, val3: i, port: 1 };
+      let peer = node::Peer { address, seen_at: now };
+      peers.see_peer(peer, false, event_tx.clone());
+    }
+    peers
+  };
+  // Insertion order doesn't matter, only the seed does
+  let mut a = seeded_store((0..32).collect());
+  let mut b = seeded_store((0..32).rev().collect());
+  for _ in 0..8 {
+    let picked_a: Vec<_> =
+      a.get_random_active(4).iter().map(|p| p.address).collect();
+    let picked_b: Vec<_> =
+      b.get_random_active(4).iter().map(|p| p.address).collect();
+    assert_eq!(picked_a.len(), 4);
+    assert_eq!(picked_a, picked_b);
+  }
+}
+
+#[test]
+fn mempool_replaces_lower_priority_transaction() {
+  use crate::crypto::Account;
+  let tx_for = |n: u64, key: Option<u8>| {
+    let code = format!("fun (Foo) {{ (Foo) = #{} }}", n);
+    let stmt = crate::hvm::parse_code(&code).unwrap().remove(0);
+    let stmt = match key {
+      Some(key) => {
+        let account = Account::from_private_key(&[key; 32]);
+        let sign = account.sign(&crate::hvm::hash_statement(&stmt));
+        crate::hvm::set_sign(&stmt, sign)
+      }
+      None => stmt,
+    };
+    node::Transaction::from_statement(&stmt)
+  };
+  let (low, high) = (tx_for(1, Some(1)), tx_for(2, Some(1)));
+  let mut pool = node::Mempool::new();
+  pool.insert(low.clone(), 1).unwrap();
+  assert!(pool.insert(high.clone(), 1).is_err());
+  pool.insert(high.clone(), 2).unwrap();
+  assert_eq!(pool.len(), 1);
+  assert!(pool.contains(&high));
+  assert!(!pool.contains(&low));
+  assert!(pool.insert(low, 3).is_ok());
+  // Only the same signer replaces a deploy, and unsigned ones have no owner
+  // to do it
+  let other = tx_for(3, Some(2));
+  let unsigned = tx_for(4, None);
+  pool.insert(other.clone(), 9).unwrap();
+  pool.insert(unsigned.clone(), 0).unwrap();
+  pool.insert(tx_for(5, None), 9).unwrap();
+  assert_eq!(pool.len(), 4);
+  assert!(pool.contains(&other) && pool.contains(&unsigned));
+}
+
+#[test]
+fn check_block_heights_finds_gaps_and_repeats() {
+  let (missing, repeated) = node::check_block_heights(&[1, 2, 3, 4]);
+  assert!(missing.is_empty() && repeated.is_empty());
+  let (missing, repeated) = node::check_block_heights(&[2, 3, 3, 7, 8, 8, 8]);
+  assert_eq!(missing, vec![(1, 1), (4, 6)]);
+  assert_eq!(repeated, vec![3, 8]);
+}
+
+#[test]
+fn common_ancestor_finds_fork_point() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let genesis = node.genesis_hash;
+  let time = util::get_time() - 1000;
+  //  genesis -> a1 -> a2
+  //              '--> b2 -> b3
+  let mut add = |prev, time| {
+    let block = mine_block(prev, time);
+    node.add_block(&block);
+    U256::from(block.get_hash())
+  };
+  let a1 = add(genesis, time);
+  let a2 = add(a1, time + 1);
+  let b2 = add(a1, time + 2);
+  let b3 = add(b2, time + 3);
+  // Refused blocks are known, but stay at height 0, off the block tree
+  let refused = add(a1, time - 1);
+  assert_eq!(node.common_ancestor(a2, b3), Some(a1));
+  assert_eq!(node.common_ancestor(b3, a2), Some(a1));
+  assert_eq!(node.common_ancestor(a2, a1), Some(a1));
+  assert_eq!(node.common_ancestor(b3, b3), Some(b3));
+  assert_eq!(node.common_ancestor(a2, genesis), Some(genesis));
+  assert_eq!(node.common_ancestor(a2, U256::from(1)), None);
+  assert_eq!(node.height[&refused], 0);
+  assert_eq!(node.common_ancestor(a2, refused), None);
+  assert_eq!(node.common_ancestor(genesis, refused), None);
+}
+
+#[test]
+fn difficulty_math_saturates_at_the_boundaries() {
+  use crate::util::U256;
+  // The highest hash has the highest difficulty instead of dividing by zero
+  let max_work = node::get_hash_work(U256::MAX);
+  assert_eq!(max_work, node::get_hash_work(U256::MAX - 1));
+  // Scaling the highest difficulty up saturates instead of overflowing
+  let scale = U256::from(u64::MAX);
+  let target = node::compute_next_target(U256::MAX - 1, scale);
+  let difficulty = U256::MAX / U256::from(1_u64 << 32) + U256::from(1);
+  assert_eq!(target, node::difficulty_to_target(difficulty));
+  // A period that took no time doesn't divide by zero
+  node::next_period_target(node::initial_target(), 0);
+}
+
+#[test]
+fn expected_block_time_scales_with_hash_rate() {
+  use crate::util::U256;
+  let target = node::difficulty_to_target(U256::from(1000));
+  assert_eq!(node::expected_block_time(target, 100), 10.0);
+  assert_eq!(node::expected_block_time(target, 4000), 0.25);
+  let initial = node::initial_target();
+  assert_eq!(node::expected_block_time(initial, 1), 256.0);
+  assert_eq!(node::expected_block_time(target, 0), f64::INFINITY);
+}
+
+#[test]
+fn initial_difficulty_sets_target_and_magic() {
+  // Lower difficulties have lower targets, which are easier to hit
+  assert!(node::initial_target_for(16) < node::initial_target());
+  // Only a non-default difficulty changes the messages' magic number
+  assert_eq!(node::network_magic(7, node::INITIAL_DIFFICULTY), 7);
+  assert_ne!(node::network_magic(7, 16), 7);
+  assert_ne!(node::network_magic(7, 16), node::network_magic(7, 32));
+}
+
+#[test]
+fn invalid_consensus_settings_are_refused() {
+  use super::util::temp_dir;
+  use crate::config::{MineConfig, NetworkConfig};
+  use crate::node::NodeBuilder;
+  let network = MemoryNetwork::new();
+  let build = |network_id, difficulty, statement_mana| {
+    let dir = temp_dir();
+    let mine_config =
+      MineConfig { initial_difficulty: difficulty, ..MineConfig::default() };
+    let net_config = NetworkConfig { statement_mana, ..Default::default() };
+    #[cfg(feature = "events")]
+    let (event_tx, _events) = std::sync::mpsc::channel();
+    NodeBuilder::new(
+      dir.path.clone(),
+      network.connect(1),
+      #[cfg(feature = "events")]
+      event_tx,
+    )
+    .network_id(network_id)
+    .mine_config(mine_config)
+    .net_config(net_config)
+    .build()
+    .map(|_| ())
+  };
+  assert!(build(7, Some(0), None).is_err());
+  assert!(build(7, None, Some(0)).is_err());
+  assert!(build(7, Some(16), Some(1000)).is_ok());
+  // The public network's nodes must agree on them
+  let public = node::PUBLIC_NETWORK_ID;
+  assert!(build(public, None, None).is_ok());
+  assert!(build(public, Some(node::INITIAL_DIFFICULTY), None).is_ok());
+  assert!(build(public, Some(16), None).is_err());
+  assert!(build(public, None, Some(1000)).is_err());
+}
+
+#[test]
+fn mine_template_loads_statements() {
+  let file = super::util::temp_file();
+  std::fs::write(&file.path, "run { (Done #1) }\nrun { (Done #2) }").unwrap();
+  let txs = node::load_mine_template(&file.path).unwrap();
+  assert_eq!(txs.len(), 2);
+  std::fs::write(&file.path, "run {").unwrap();
+  assert!(node::load_mine_template(&file.path).is_err());
+  let missing = file.path.with_extension("missing");
+  assert!(node::load_mine_template(&missing).is_err());
+}
+
+#[test]
+fn mining_pause_keeps_to_the_intensity() {
+  use std::time::Duration;
+  let elapsed = Duration::from_millis(100);
+  assert_eq!(node::mining_pause(elapsed, 100), Duration::ZERO);
+  assert_eq!(node::mining_pause(elapsed, 0), Duration::ZERO);
+  assert_eq!(node::mining_pause(elapsed, 50), elapsed);
+  assert_eq!(node::mining_pause(elapsed, 25), elapsed * 3);
+  assert_eq!(node::mining_pause(elapsed, 80), Duration::from_millis(25));
+}
+
+#[test]
+fn replay_range_rebuilds_the_runtime() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let code = "fun (Replayed) { (Replayed) = #7 } run { (Done (Replayed)) }";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let deploy = node::Transaction::from_statement(&stmts[0]);
+  let run = node::Transaction::from_statement(&stmts[1]);
+  let first = mine_block_with(node.genesis_hash, chain_time(1), vec![deploy]);
+  node.add_block(&first);
+  build_chain(node, 1);
+  let last = mine_block_with(node.tip, chain_time(3), vec![run]);
+  node.add_block(&last);
+  let heaps = super::util::temp_dir();
+  let (runtime, results) =
+    node.replay_range(1, 3, heaps.path.clone()).unwrap();
+  assert_eq!(runtime.get_tick(), node.runtime.get_tick());
+  assert_eq!(results.len(), 3);
+  // Later ranges are computed on the state the earlier blocks leave
+  let heaps = super::util::temp_dir();
+  let (_, results) = node.replay_range(3, 3, heaps.path.clone()).unwrap();
+  assert_eq!(results.len(), 1);
+  assert!(results[0][0].is_ok());
+  assert!(node.replay_range(2, 4, heaps.path.clone()).is_err());
+  assert!(node.replay_range(3, 2, heaps.path.clone()).is_err());
+}
+
+#[test]
+fn recent_blocks_follow_the_tip() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let genesis = node.genesis_hash;
+  // Fixed timestamps, as block work depends on the hash
+  let time = 1_650_000_000_000;
+  let mut add = |prev, time| {
+    let block = mine_block(prev, time);
+    node.add_block(&block);
+    U256::from(block.get_hash())
+  };
+  //  genesis -> a1 -> a2 -> a3
+  //              '--> b2 -> b3 -> b4 -> b5 -> b6
+  let a1 = add(genesis, time);
+  let a2 = add(a1, time + 1);
+  let a3 = add(a2, time + 2);
+  let b2 = add(a1, time + 3);
+  let b3 = add(b2, time + 4);
+  let b4 = add(b3, time + 5);
+  let b5 = add(b4, time + 6);
+  let b6 = add(b5, time + 7);
+  let node = &test_node.node;
+  let hashes = |infos: Vec<crate::api::BlockInfo>| {
+    infos.into_iter().map(|i| U256::from(i.hash)).collect::<Vec<_>>()
+  };
+  assert_ne!(node.tip, a3);
+  assert_eq!(hashes(node.get_recent_blocks(3).unwrap()), vec![b4, b5, b6]);
+  assert_eq!(node.get_recent_blocks(10).unwrap().len(), 7);
+}
+
+#[test]
+fn functions_are_listed_in_pages() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let code = "
+    fun (Pgc) { (Pgc) = #0 } with { #0 }
+    fun (Pga) { (Pga) = #0 } with { #0 }
+    fun (Pgb) { (Pgb) = #0 } with { #0 }
+  ";
+  node.runtime.run_statements_from_code(code, true, false);
+  let names = |funcs: Vec<crate::common::Name>| {
+    funcs.iter().map(|name| name.to_string()).collect::<Vec<_>>()
+  };
+  let first = names(node.get_functions(0, Some(2), Some("Pg")));
+  let second = names(node.get_functions(2, Some(2), Some("Pg")));
+  assert_eq!(first, vec!["Pga", "Pgb"]);
+  assert_eq!(second, vec!["Pgc"]);
+  let all = node.get_functions(0, None, None);
+  assert!(all.len() >= 3);
+}
+
+#[test]
+fn deploying_a_function_updates_the_cached_names() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  assert!(node.get_functions(0, None, Some("Cached")).is_empty());
+  assert!(node.functions.is_some());
+  let code = "fun (Cached) { (Cached) = #0 } with { #0 }";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let txs = stmts.iter().map(node::Transaction::from_statement).collect();
+  let block = mine_block_with(node.genesis_hash, util::get_time() - 1000, txs);
+  node.add_block(&block);
+  let names = node.get_functions(0, None, Some("Cached"));
+  assert_eq!(names.len(), 1);
+  assert_eq!(names[0].to_string(), "Cached");
+}
+
+#[test]
+fn blocks_whose_first_statement_fails_lose_their_tick() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let stmts = crate::hvm::parse_code("run { #0 }").unwrap();
+  let txs = stmts.iter().map(node::Transaction::from_statement).collect();
+  let time = util::get_time() - 1000;
+  let block = mine_block_with(node.genesis_hash, time, txs);
+  node.add_block(&block);
+  let bhash = U256::from(block.get_hash());
+  assert_eq!(node.tip, bhash);
+  assert!(node.results[&bhash][0].is_err());
+  // The failure undoes the block's tick too, as it always did
+  assert_eq!(node.runtime.get_tick(), 0);
+  assert_eq!(node.get_block_info(&bhash).unwrap().tick, Some(0));
+  assert!(node.tick_matches_tip());
+  assert!(node.get_health().consistent);
+}
+
+#[test]
+fn blocks_after_a_lost_tick_are_computed_once() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let txs = |code: &str| {
+    let stmts = crate::hvm::parse_code(code).unwrap();
+    stmts.iter().map(node::Transaction::from_statement).collect()
+  };
+  let lost = mine_block_with(node.genesis_hash, chain_time(1), txs("run { #0 }"));
+  node.add_block(&lost);
+  let code = "fun (Base) { (Base) = #0 } with { #0 }";
+  let base = mine_block_with(node.tip, chain_time(2), txs(code));
+  let base_hash = U256::from(base.get_hash());
+  node.add_block(&base);
+  build_chain(node, 2);
+  // Each block after the lost tick is a tick behind its height, and is
+  // computed only once
+  assert_eq!(node.height[&node.tip], 4);
+  assert_eq!(node.runtime.get_tick(), 3);
+  assert!(node.results[&base_hash][0].is_ok());
+  assert_eq!(node.get_block_info(&base_hash).unwrap().tick, Some(1));
+  assert!(node.tick_matches_tip());
+}
+
+#[test]
+fn reorgs_across_a_lost_tick_recompute_the_fork() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let txs = |code: &str| {
+    let stmts = crate::hvm::parse_code(code).unwrap();
+    stmts.iter().map(node::Transaction::from_statement).collect()
+  };
+  // Mines empty blocks on top of `fork` until it becomes the tip
+  let grow = |node: &mut node::Node<MemoryComm>, mut fork: U256| {
+    while node.tip != fork {
+      let block = mine_block(fork, chain_time(node.height[&fork] + 1) + 1);
+      node.add_block(&block);
+      fork = U256::from(block.get_hash());
+    }
+  };
+  //  genesis -> a1 -> lost -> a3 -> ...
+  //              |      '---> b3 -> ...
+  //              '---> c2 -> ...
+  // `lost` fails its first statement, but still deploys `Lost`, on the same
+  // tick as `a1`
+  let a1 = mine_block(node.genesis_hash, chain_time(1));
+  let a1_hash = U256::from(a1.get_hash());
+  node.add_block(&a1);
+  let code = "run { #0 }\nfun (Lost) { (Lost) = #0 } with { #0 }";
+  let lost = mine_block_with(a1_hash, chain_time(2), txs(code));
+  let lost_hash = U256::from(lost.get_hash());
+  node.add_block(&lost);
+  assert!(node.results[&lost_hash][1].is_ok());
+  assert_eq!(node.runtime.get_tick(), 1);
+  // A fork before it undoes `Lost`, though the tick is still `a1`'s
+  let code = "fun (Lost) { (Lost) = #0 } with { #0 }";
+  let c2 = mine_block_with(a1_hash, chain_time(2) + 1, txs(code));
+  let c2_hash = U256::from(c2.get_hash());
+  node.add_block(&c2);
+  grow(node, c2_hash);
+  assert!(node.results[&c2_hash][0].is_ok());
+  assert_eq!(node.runtime.get_tick() as u128, node.height[&node.tip]);
+  assert!(node.tick_matches_tip());
+  // Moving back over it computes `lost` again, and each block after it once
+  let code = "fun (Fork) { (Fork) = #0 } with { #0 }";
+  let a3 = mine_block_with(lost_hash, chain_time(3), txs(code));
+  let a3_hash = U256::from(a3.get_hash());
+  node.add_block(&a3);
+  grow(node, a3_hash);
+  assert!(node.results[&a3_hash][0].is_ok());
+  assert_eq!(node.runtime.get_tick() as u128, node.height[&node.tip] - 1);
+  assert!(node.tick_matches_tip());
+  // A fork after it undoes `a3`, and only it
+  let b3 = mine_block_with(lost_hash, chain_time(3) + 1, txs(code));
+  let b3_hash = U256::from(b3.get_hash());
+  node.add_block(&b3);
+  grow(node, b3_hash);
+  assert!(node.results[&b3_hash][0].is_ok());
+  assert_eq!(node.runtime.get_tick() as u128, node.height[&node.tip] - 1);
+  assert!(node.tick_matches_tip());
+}
+
+#[test]
+fn trusted_checkpoint_skips_computing_and_pins_its_height() {
+  use super::util::temp_dir;
+  use crate::config::NetworkConfig;
+  use crate::node::NodeBuilder;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  let code = "fun (Trusted) { (Trusted) = #0 } with { #0 }";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let txs = stmts.iter().map(node::Transaction::from_statement).collect();
+  let first = mine_block_with(source.node.genesis_hash, chain_time(1), txs);
+  source.node.add_block(&first);
+  let chain = build_chain(&mut source.node, 19);
+  let dir = temp_dir();
+  std::fs::create_dir_all(&dir.path).unwrap();
+  source.node.export_state("state").unwrap();
+  let state = source.node.get_exports_path().join("state");
+  let checkpoint = exported_state_checkpoint(&source.node);
+  let height = checkpoint.height as usize;
+  let net_config =
+    NetworkConfig { trusted_checkpoint: Some(checkpoint), ..test_net_config() };
+  let build = |name: &str, state| {
+    #[cfg(feature = "events")]
+    let (event_tx, _events) = std::sync::mpsc::channel();
+    NodeBuilder::new(
+      dir.path.join(name),
+      network.connect(2),
+      #[cfg(feature = "events")]
+      event_tx,
+    )
+    .state_snapshot(state)
+    .net_config(net_config.clone())
+    .build()
+  };
+  // Without the state at the checkpoint, the node would serve a state
+  // lacking the statements it skips
+  assert!(build("bare", None).is_err());
+  let (_, mut node) = build("node", Some(state.clone())).unwrap();
+  for bhash in &chain[1..height] {
+    assert!(node.add_block(&source.node.block[bhash].clone().hashed()));
+  }
+  // Other blocks at the checkpoint height are refused, also as mined work
+  let other = mine_block(chain[height - 1], chain_time(height as u128) + 1);
+  let err = node.submit_block(other.clone().take()).unwrap_err();
+  assert!(err.contains("refused"), "{}", err);
+  assert!(!node.add_block(&other));
+  for bhash in &chain[height..] {
+    assert!(node.add_block(&source.node.block[bhash].clone().hashed()));
+  }
+  assert_eq!(node.tip, chain[20]);
+  // The statements before the checkpoint weren't run, and their effects
+  // come from the imported state
+  assert!(!source.node.results[&chain[1]].is_empty());
+  assert!(!node.results.contains_key(&chain[1]));
+  assert!(!node.get_functions(0, None, Some("Trusted")).is_empty());
+}
+
+#[test]
+fn forks_below_an_imported_state_are_refused() {
+  use super::util::temp_dir;
+  use crate::node::NodeBuilder;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  let chain = build_chain(&mut source.node, 30);
+  let dir = temp_dir();
+  std::fs::create_dir_all(&dir.path).unwrap();
+  source.node.export_state("state").unwrap();
+  let state = source.node.get_exports_path().join("state");
+  #[cfg(feature = "events")]
+  let (event_tx, _events) = std::sync::mpsc::channel();
+  let (_, mut node) = NodeBuilder::new(
+    dir.path.clone(),
+    network.connect(2),
+    #[cfg(feature = "events")]
+    event_tx,
+  )
+  .state_snapshot(Some(state))
+  .net_config(test_net_config())
+  .build()
+  .unwrap();
+  let floor = node.state_floor as usize;
+  assert!(floor > 10);
+  for i in 0..30 {
+    let block = source.node.block[&chain[i + 1]].clone();
+    node.add_block(&block.hashed());
+  }
+  assert_eq!(node.tip, chain[30]);
+  // A fork from below the imported state would be computed on top of the
+  // wrong state, so its block at the state's height is refused and it never
+  // gains work
+  let mut fork = chain[10];
+  for height in 11..=40 {
+    let block = mine_block(fork, chain_time(height) + 1);
+    node.add_block(&block);
+    fork = U256::from(block.get_hash());
+  }
+  assert_eq!(node.work[&fork], U256::from(0));
+  assert_eq!(node.tip, chain[30]);
+  assert!(node.set_tip(fork).is_err());
+  assert!(node.tick_matches_tip());
+}
+
+#[test]
+fn imported_states_must_be_at_the_checkpoint() {
+  use super::util::temp_dir;
+  use crate::config::{NetworkConfig, TrustedCheckpoint};
+  use crate::node::NodeBuilder;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  build_chain(&mut source.node, 20);
+  let dir = temp_dir();
+  std::fs::create_dir_all(&dir.path).unwrap();
+  source.node.export_state("state").unwrap();
+  let state = source.node.get_exports_path().join("state");
+  // Exports hold the state of the latest runtime snapshot
+  let runtime = &source.node.runtime;
+  let tick = match *runtime.get_back() {
+    crate::hvm::Rollback::Cons { head, .. } => runtime.get_heap(head).tick,
+    crate::hvm::Rollback::Nil => 0,
+  };
+  let build = |height: u64, bhash: U256| {
+    let checkpoint = TrustedCheckpoint {
+      hash: bhash.into(),
+      height,
+      work: source.node.work[&bhash].into(),
+      target: None,
+    };
+    let net_config = NetworkConfig {
+      trusted_checkpoint: Some(checkpoint),
+      ..NetworkConfig::default()
+    };
+    #[cfg(feature = "events")]
+    let (event_tx, _events) = std::sync::mpsc::channel();
+    NodeBuilder::new(
+      dir.path.join(format!("node{}", height)),
+      network.connect(2 + height as u32),
+      #[cfg(feature = "events")]
+      event_tx,
+    )
+    .state_snapshot(Some(state.clone()))
+    .net_config(net_config)
+    .build()
+  };
+  let block_at = |height| source.node.get_block_hash_by_index(height).unwrap();
+  assert!(build(tick - 1, block_at(tick - 1)).is_err());
+  // The state is bound to its block, not only to its height
+  assert!(build(tick, block_at(tick - 1)).is_err());
+  assert!(build(tick, block_at(tick)).is_ok());
+}
+
+// Checkpoint at the tick of the state `node` exports, i.e. of its latest
+// runtime snapshot, with the target its children must hit.
+fn exported_state_checkpoint(
+  node: &node::Node<MemoryComm>,
+) -> crate::config::TrustedCheckpoint {
+  let runtime = &node.runtime;
+  let tick = match *runtime.get_back() {
+    crate::hvm::Rollback::Cons { head, .. } => runtime.get_heap(head).tick,
+    crate::hvm::Rollback::Nil => 0,
+  };
+  let bhash = node.get_block_hash_by_index(tick).unwrap();
+  crate::config::TrustedCheckpoint {
+    hash: bhash.into(),
+    height: tick,
+    work: node.work[&bhash].into(),
+    target: Some(node.target[&bhash].into()),
+  }
+}
+
+#[test]
+fn misnamed_block_files_are_repaired() {
+  use crate::util::{bitvec_to_bytes, U256};
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = util::get_time() - 1000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a2 = mine_block(U256::from(a1.get_hash()), time + 1);
+  node.add_block(&a1);
+  node.add_block(&a2);
+  let a2_hash = U256::from(a2.get_hash());
+  let bytes = bitvec_to_bytes(&a2.proto_serialized());
+  let misnamed = node.get_block_file_path(5);
+  // A misnamed copy of a stored block is dropped
+  std::fs::write(&misnamed, &bytes).unwrap();
+  node.repair_block_files(vec![(5, misnamed.clone(), a2_hash)]);
+  assert!(!misnamed.exists());
+  assert_eq!(std::fs::read(node.get_block_file_path(2)).unwrap(), bytes);
+  // A misnamed block missing from its right place is moved there
+  std::fs::remove_file(node.get_block_file_path(2)).unwrap();
+  std::fs::write(&misnamed, &bytes).unwrap();
+  node.repair_block_files(vec![(5, misnamed.clone(), a2_hash)]);
+  assert!(!misnamed.exists());
+  assert_eq!(std::fs::read(node.get_block_file_path(2)).unwrap(), bytes);
+}
+
+#[test]
+fn stray_and_unreadable_block_files_are_skipped_on_load() {
+  use super::util::temp_dir;
+  use crate::node::NodeBuilder;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  let tip = build_chain(&mut source.node, 2)[2];
+  let dir = temp_dir();
+  let blocks = dir.path.join("blocks");
+  std::fs::create_dir_all(&blocks).unwrap();
+  for entry in std::fs::read_dir(source.node.get_blocks_path()).unwrap() {
+    let entry = entry.unwrap();
+    std::fs::copy(entry.path(), blocks.join(entry.file_name())).unwrap();
+  }
+  // A file not named after a height, and a "block file" that can't be read
+  std::fs::write(blocks.join("notes.txt"), "").unwrap();
+  let unreadable = source.node.get_block_file_path(3);
+  std::fs::create_dir(blocks.join(unreadable.file_name().unwrap())).unwrap();
+  #[cfg(feature = "events")]
+  let (event_tx, _events) = std::sync::mpsc::channel();
+  let (_, mut node) = NodeBuilder::new(
+    dir.path.clone(),
+    network.connect(2),
+    #[cfg(feature = "events")]
+    event_tx,
+  )
+  .net_config(test_net_config())
+  .build()
+  .unwrap();
+  node.load_blocks();
+  assert_eq!(node.tip, tip);
+}
+
+#[test]
+fn block_serving_is_rate_limited() {
+  let mut serving = node::BlockServing::<u32>::default();
+  let now = util::get_time();
+  // Each peer gets its own request budget
+  assert!(serving.allow(1, now, 2, 1000));
+  assert!(serving.allow(1, now, 2, 1000));
+  assert!(!serving.allow(1, now, 2, 1000));
+  assert!(serving.allow(2, now, 2, 1000));
+  // The byte budget is shared by every peer
+  serving.record(1000);
+  assert!(!serving.allow(3, now, 2, 1000));
+  // Both reset on the next window
+  assert!(serving.allow(1, now + 1000, 2, 1000));
+}
+
+#[test]
+fn pending_blocks_are_evicted_after_timeout() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = util::get_time() - 1000;
+  // The parent of `orphan` never arrives
+  let parent = mine_block(node.genesis_hash, time);
+  let parent_hash = U256::from(parent.get_hash());
+  let orphan = mine_block(parent_hash, time + 1);
+  let orphan_hash = U256::from(orphan.get_hash());
+  node.add_block(&orphan);
+  assert_eq!(node.inclusion_state(&orphan_hash), node::InclusionState::PENDING);
+  // It is kept until the timeout
+  let timeout = node.net_config.pending_block_timeout;
+  assert_eq!(node.evict_stale_pending(util::get_time()), 0);
+  assert!(node.pending.contains_key(&orphan_hash));
+  // Then dropped, along with its parent's wait_list entry
+  assert_eq!(node.evict_stale_pending(util::get_time() + timeout + 1), 1);
+  assert_eq!(node.inclusion_state(&orphan_hash), node::InclusionState::UNSEEN);
+  assert_eq!(node.inclusion_state(&parent_hash), node::InclusionState::UNSEEN);
+  assert!(node.pending_at.is_empty());
+}
+
+#[test]
+fn blocks_are_found_by_offset_from_tip() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let hashes = build_chain(node, 3);
+  assert_eq!(node.get_block_hash_from_tip(0), Some(hashes[3]));
+  assert_eq!(node.get_block_hash_from_tip(2), Some(hashes[1]));
+  assert_eq!(node.get_block_hash_from_tip(3), Some(node.genesis_hash));
+  assert_eq!(node.get_block_hash_from_tip(4), None);
+}
+
+#[test]
+fn mana_usage_sums_the_statements_of_a_range() {
+  use crate::hvm::StatementInfo;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = util::get_time() - 1000;
+  let code = "
+    run { (Done (+ #1 #2)) }
+    ctr {Pair a b}
+    fun (Sum) { (Sum) = #0 } with { (+ #1 #2) }
+  ";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let txs = stmts.iter().map(node::Transaction::from_statement).collect();
+  let b1 = mine_block_with(node.genesis_hash, time, txs);
+  let b1_hash = U256::from(b1.get_hash());
+  let b2 = mine_block(b1_hash, time + 1);
+  node.add_block(&b1);
+  node.add_block(&b2);
+  let results = &node.results[&b1_hash];
+  let run = match &results[0] {
+    Ok(StatementInfo::Run { used_mana, .. }) => *used_mana,
+    other => panic!("Unexpected result: {:?}", other),
+  };
+  // Computing a function's initial state spends mana too
+  let fun = match &results[2] {
+    Ok(StatementInfo::Fun { used_mana, .. }) => *used_mana,
+    other => panic!("Unexpected result: {:?}", other),
+  };
+  assert!(run > 0 && fun > 0);
+  let used = run + fun;
+  assert_eq!(node.get_mana_usage(1, 2), used);
+  assert_eq!(node.get_mana_usage(0, 100), used);
+  assert_eq!(node.get_mana_usage(2, 2), 0);
+  assert_eq!(node.get_mana_usage(2, 1), 0);
+}
+
+#[test]
+fn block_work_and_target_are_looked_up_safely() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let block = mine_block(node.genesis_hash, util::get_time() - 1000);
+  let bhash = U256::from(block.get_hash());
+  assert_eq!(node.get_block_work(&bhash), None);
+  assert_eq!(node.get_block_target(&bhash), None);
+  node.add_block(&block);
+  assert_eq!(node.get_block_target(&bhash), Some(node::initial_target()));
+  let work = node.get_block_work(&bhash).unwrap();
+  assert_eq!(work, node::get_hash_work(bhash));
+}
+
+#[test]
+fn slowest_recent_blocks_are_reported() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  build_chain(node, 3);
+  let heights: Vec<u64> =
+    node.block_timings.iter().rev().take(3).map(|t| t.height).collect();
+  assert_eq!(heights, vec![3, 2, 1]);
+  // The slowest come first
+  for (timing, millis) in node.block_timings.iter_mut().rev().zip([5, 9, 7]) {
+    timing.millis = millis;
+  }
+  let slowest = node.get_slowest_blocks(2);
+  let heights: Vec<u64> = slowest.iter().map(|t| t.height).collect();
+  assert_eq!(heights, vec![2, 1]);
+}
+
+#[test]
+fn block_pow_is_checked_without_a_node() {
+  use crate::util::U256;
+  let time = util::get_time() - 1000;
+  let block = mine_block(U256::from(1), time);
+  let hash = U256::from(block.get_hash());
+  let target = node::initial_target();
+  assert!(node::block_hits_target(&block, target));
+  assert!(node::block_hits_target(&block, hash));
+  assert!(!node::block_hits_target(&block, hash + 1));
+  assert!(node::block_hits_target(&block, U256::zero()));
+}
+
+#[test]
+fn transactions_are_found_by_hash() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let code = "
+    run { (Done #1) }
+    run { (Done #2) }
+  ";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let confirmed = node::Transaction::from_statement(&stmts[0]);
+  let pending = node::Transaction::from_statement(&stmts[1]);
+  let time = util::get_time() - 1000;
+  let block = mine_block_with(node.genesis_hash, time, vec![confirmed.clone()]);
+  node.add_block(&block);
+  node.pool.insert(pending.clone(), 0).unwrap();
+  let found = node.get_transaction(&confirmed.hash).unwrap();
+  assert_eq!(&*found, &*confirmed);
+  let found = node.get_transaction(&pending.hash).unwrap();
+  assert_eq!(&*found, &*pending);
+  assert!(node.get_transaction(&U256::from(1)).is_none());
+}
+
+#[test]
+fn block_format_version_1_activates_at_its_height() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  node.block_v1_height = 3;
+  let time = util::get_time() - 1000;
+  let b1 = mine_block_in(0, node.genesis_hash, time, vec![]);
+  node.add_block(&b1);
+  let b1_hash = U256::from(b1.get_hash());
+  assert_eq!(node.tip, b1_hash);
+  // Version 1 blocks are refused below the activation height
+  let early = mine_block_in(1, b1_hash, time + 1, vec![]);
+  node.add_block(&early);
+  assert_eq!(node.tip, b1_hash);
+  let b2 = mine_block_in(0, b1_hash, time + 1, vec![]);
+  node.add_block(&b2);
+  let b2_hash = U256::from(b2.get_hash());
+  assert_eq!(node.tip, b2_hash);
+  // And version 0 blocks from it on
+  let late = mine_block_in(0, b2_hash, time + 2, vec![]);
+  node.add_block(&late);
+  assert_eq!(node.tip, b2_hash);
+  let b3 = mine_block_in(1, b2_hash, time + 2, vec![]);
+  node.add_block(&b3);
+  assert_eq!(node.tip, U256::from(b3.get_hash()));
+  // Stored chains are checked the same way
+  let blocks = node.get_blocks_path();
+  let (genesis, difficulty) = (node.genesis_hash, node::INITIAL_DIFFICULTY);
+  assert!(node::verify_stored_chain(&blocks, genesis, difficulty, 3).is_ok());
+  assert!(node::verify_stored_chain(&blocks, genesis, difficulty, 2).is_err());
+  let unscheduled = node::BLOCK_V1_HEIGHT;
+  assert!(node::verify_stored_chain(&blocks, genesis, difficulty, unscheduled)
+    .is_err());
+}
+
+#[test]
+fn chains_from_before_block_format_version_1_still_load() {
+  use super::util::temp_dir;
+  use crate::crypto::Hash;
+  use crate::node::NodeBuilder;
+  use crate::util::{u128_to_bytes, u256_to_bytes, U256};
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  source.node.block_v1_height = node::BLOCK_V1_HEIGHT;
+  // Genesis is hashed from its whole body, as before
+  let genesis = &source.node.block[&source.node.genesis_hash];
+  let mut bytes = u256_to_bytes(genesis.prev);
+  bytes.extend(u128_to_bytes(genesis.time));
+  bytes.extend(u128_to_bytes(genesis.meta));
+  bytes.extend(&genesis.body.data);
+  let hash = Hash::keccak256_from_bytes(&bytes);
+  assert_eq!(source.node.genesis_hash, U256::from(&hash));
+  let mut tip = source.node.genesis_hash;
+  for i in 0..3 {
+    let block = mine_block_in(0, tip, 1_650_000_000_000 + i, vec![]);
+    source.node.add_block(&block);
+    tip = U256::from(block.get_hash());
+  }
+  assert_eq!(source.node.tip, tip);
+  // Stored as earlier builds did, without the format version byte
+  let dir = temp_dir();
+  let blocks = dir.path.join("blocks");
+  std::fs::create_dir_all(&blocks).unwrap();
+  for entry in std::fs::read_dir(source.node.get_blocks_path()).unwrap() {
+    let entry = entry.unwrap();
+    let bytes = std::fs::read(entry.path()).unwrap();
+    std::fs::write(blocks.join(entry.file_name()), &bytes[1..]).unwrap();
+  }
+  #[cfg(feature = "events")]
+  let (event_tx, _events) = std::sync::mpsc::channel();
+  let (_, mut node) = NodeBuilder::new(
+    dir.path.clone(),
+    network.connect(2),
+    #[cfg(feature = "events")]
+    event_tx,
+  )
+  .network_id(node::PUBLIC_NETWORK_ID)
+  .build()
+  .unwrap();
+  node.load_blocks();
+  assert_eq!(node.genesis_hash, source.node.genesis_hash);
+  assert_eq!(node.tip, tip);
+  assert_eq!(node.height[&tip], 3);
+}
+
+#[test]
+fn chains_from_before_block_format_version_1_load_on_any_network() {
+  use super::util::temp_dir;
+  use crate::config::NetworkConfig;
+  use crate::node::NodeBuilder;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  source.node.block_v1_height = node::BLOCK_V1_HEIGHT;
+  let mut tip = source.node.genesis_hash;
+  for i in 0..3 {
+    let block = mine_block_in(0, tip, 1_650_000_000_000 + i, vec![]);
+    source.node.add_block(&block);
+    tip = U256::from(block.get_hash());
+  }
+  assert_eq!(source.node.tip, tip);
+  let build = |dir: &std::path::Path, network_id, block_v1_height| {
+    let net_config = NetworkConfig { block_v1_height, ..Default::default() };
+    #[cfg(feature = "events")]
+    let (event_tx, _events) = std::sync::mpsc::channel();
+    NodeBuilder::new(
+      dir.to_path_buf(),
+      network.connect(2),
+      #[cfg(feature = "events")]
+      event_tx,
+    )
+    .network_id(network_id)
+    .net_config(net_config)
+    .build()
+  };
+  let dir = temp_dir();
+  let blocks = dir.path.join("blocks");
+  std::fs::create_dir_all(&blocks).unwrap();
+  for entry in std::fs::read_dir(source.node.get_blocks_path()).unwrap() {
+    let entry = entry.unwrap();
+    std::fs::copy(entry.path(), blocks.join(entry.file_name())).unwrap();
+  }
+  // Version 1 is opt-in, so a network without it keeps its version 0 chain
+  let (_, mut node) = build(&dir.path, 7, None).unwrap();
+  node.load_blocks();
+  assert_eq!(node.tip, tip);
+  assert_eq!(node.height[&tip], 3);
+  // Opting in from genesis refuses it
+  drop(node);
+  let (_, mut node) = build(&dir.path, 7, Some(1)).unwrap();
+  node.load_blocks();
+  assert_eq!(node.tip, node.genesis_hash);
+  // The public network's height can't be overridden
+  drop(node);
+  assert!(build(&dir.path, node::PUBLIC_NETWORK_ID, Some(1)).is_err());
+}
+
+#[test]
+fn transactions_have_inclusion_proofs() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let txs: Vec<_> =
+    (0..5u8).map(|i| node::Transaction::new(vec![i + 1; 5])).collect();
+  let time = util::get_time() - 1000;
+  let block = mine_block_in(1, node.genesis_hash, time, txs.clone());
+  node.add_block(&block);
+  for tx in txs.iter() {
+    let proof = node.get_transaction_proof(&tx.hash).unwrap();
+    assert!(proof.verify());
+    // A proof doesn't hold for another transaction
+    let mut forged = proof.clone();
+    forged.transaction = txs[0].hash.into();
+    assert_eq!(forged.verify(), tx.hash == txs[0].hash);
+  }
+  let mut forged = node.get_transaction_proof(&txs[4].hash).unwrap();
+  forged.meta += 1;
+  assert!(!forged.verify());
+  // Nor for another format version
+  let mut forged = node.get_transaction_proof(&txs[4].hash).unwrap();
+  forged.version += 1;
+  assert!(!forged.verify());
+  // Blocks in the previous format commit to the whole body
+  let mut old = block.take();
+  old.version = 0;
+  assert_ne!(old.clone().hashed().get_hash(), &old.header().keccak256());
+}
+
+#[test]
+fn mempool_order_does_not_depend_on_arrival() {
+  let txs: Vec<_> =
+    (0..20u8).map(|i| node::Transaction::new(vec![i + 1; 5])).collect();
+  let mut forward = node::Mempool::new();
+  let mut backward = node::Mempool::new();
+  for tx in txs.iter() {
+    forward.insert(tx.clone(), 0).unwrap();
+  }
+  for tx in txs.iter().rev() {
+    backward.insert(tx.clone(), 0).unwrap();
+  }
+  let hashes = |pool: &node::Mempool| {
+    pool.sorted().iter().map(|tx| tx.hash).collect::<Vec<_>>()
+  };
+  assert_eq!(hashes(&forward), hashes(&backward));
+  let body = |pool: &node::Mempool| {
+    node::Body::fill_from(pool.sorted().into_iter().cloned())
+  };
+  assert_eq!(body(&forward), body(&backward));
+}
+
+#[test]
+fn difficulty_history_lists_each_period_start() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  // Blocks twice slower than expected, so the second period gets easier
+  // and blocks mined with the initial target still hit it
+  let spacing = 2 * node::TIME_PER_BLOCK;
+  let start = util::get_time() - 30 * spacing;
+  let mut prev = node.genesis_hash;
+  for i in 1..=22 {
+    let block = mine_block(prev, start + i * spacing);
+    node.add_block(&block);
+    prev = U256::from(block.get_hash());
+  }
+  assert_eq!(node.height[&node.tip], 22);
+  let history = node.get_difficulty_history(10);
+  let heights: Vec<u64> = history.iter().map(|(h, _)| *h).collect();
+  assert_eq!(heights, vec![1, 21]);
+  let initial = node::target_to_difficulty(node::initial_target());
+  assert_eq!(history[0].1, initial);
+  assert!(history[1].1 < initial);
+  // Only the latest periods are returned
+  assert_eq!(node.get_difficulty_history(1), vec![history[1]]);
+  assert_eq!(node.get_difficulty_history(usize::MAX), history);
+}
+
+#[test]
+fn unversioned_block_files_are_migrated_on_load() {
+  use super::util::temp_dir;
+  use crate::node::NodeBuilder;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  source.node.block_v1_height = node::BLOCK_V1_HEIGHT;
+  let mut tip = source.node.genesis_hash;
+  for i in 0..3 {
+    let block = mine_block_in(0, tip, 1_650_000_000_000 + i, vec![]);
+    source.node.add_block(&block);
+    tip = U256::from(block.get_hash());
+  }
+  assert_eq!(source.node.tip, tip);
+  // Files as written by builds before blocks had a format version
+  let dir = temp_dir();
+  let blocks = dir.path.join("blocks");
+  std::fs::create_dir_all(&blocks).unwrap();
+  for entry in std::fs::read_dir(source.node.get_blocks_path()).unwrap() {
+    let entry = entry.unwrap();
+    let bytes = std::fs::read(entry.path()).unwrap();
+    std::fs::write(blocks.join(entry.file_name()), &bytes[1..]).unwrap();
+  }
+  #[cfg(feature = "events")]
+  let (event_tx, _events) = std::sync::mpsc::channel();
+  let (_, mut node) = NodeBuilder::new(
+    dir.path.clone(),
+    network.connect(2),
+    #[cfg(feature = "events")]
+    event_tx,
+  )
+  .network_id(node::PUBLIC_NETWORK_ID)
+  .build()
+  .unwrap();
+  node.load_blocks();
+  assert_eq!(node.tip, tip);
+  for height in 1..=3 {
+    let migrated = std::fs::read(node.get_block_file_path(height)).unwrap();
+    let original =
+      std::fs::read(source.node.get_block_file_path(height)).unwrap();
+    assert_eq!(migrated, original);
+  }
+}
+
+#[test]
+fn blocks_that_cant_be_saved_are_kept_and_retried() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  // A file where the blocks directory should be makes every write fail
+  let blocks_path = node.get_blocks_path();
+  std::fs::remove_dir_all(&blocks_path).unwrap();
+  std::fs::write(&blocks_path, b"").unwrap();
+  let time = util::get_time() - 1000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a2 = mine_block(U256::from(a1.get_hash()), time + 1);
+  node.add_block(&a1);
+  node.add_block(&a2);
+  // The node doesn't crash, and keeps following the chain
+  assert_eq!(node.tip, U256::from(a2.get_hash()));
+  assert!(node.is_degraded());
+  assert_eq!(node.retry_unsaved_blocks(), 2);
+  // Once there is room again, the blocks are written
+  std::fs::remove_file(&blocks_path).unwrap();
+  std::fs::create_dir_all(&blocks_path).unwrap();
+  assert_eq!(node.retry_unsaved_blocks(), 0);
+  assert!(!node.is_degraded());
+  assert!(node.get_block_file_path(1).exists());
+  assert!(node.get_block_file_path(2).exists());
+}
+
+#[test]
+fn node_state_is_restored_from_a_snapshot() {
+  use super::util::temp_dir;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = util::get_time() - 1000;
+  let code = "fun (Snap) { (Snap) = #0 } with { #0 }";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let txs = stmts.iter().map(node::Transaction::from_statement).collect();
+  let a1 = mine_block_with(node.genesis_hash, time, txs);
+  node.add_block(&a1);
+  let a1_hash = U256::from(a1.get_hash());
+  let snapshot = node.snapshot();
+  let tick = node.runtime.get_tick();
+  // The node moves on, then comes back
+  let a2 = mine_block(a1_hash, time + 1);
+  node.add_block(&a2);
+  assert_ne!(node.tip, a1_hash);
+  node.restore(snapshot.clone()).unwrap();
+  assert_eq!(node.tip, a1_hash);
+  assert_eq!(node.runtime.get_tick(), tick);
+  assert!(!node.block.contains_key(&U256::from(a2.get_hash())));
+  // Saved snapshots bring a fresh node to the same state
+  let dir = temp_dir();
+  snapshot.save(&dir.path).unwrap();
+  let loaded = node::NodeSnapshot::load(&dir.path).unwrap();
+  let mut fresh = TestNode::new(&network, 2, vec![]);
+  fresh.node.restore(loaded).unwrap();
+  assert_eq!(fresh.node.tip, a1_hash);
+  assert_eq!(fresh.node.runtime.get_tick(), tick);
+  assert_eq!(fresh.node.results[&a1_hash].len(), 1);
+  assert_eq!(fresh.node.get_functions(0, None, Some("Snap")).len(), 1);
+  // Snapshots in other format versions are refused
+  let mut future = snapshot;
+  future.version = node::NODE_SNAPSHOT_VERSION + 1;
+  assert!(fresh.node.restore(future).is_err());
+}
+
+#[test]
+fn suffix_start_is_a_period_start_below_the_imported_state() {
+  // Nothing to skip
+  assert_eq!(node::suffix_start(50, 100, 50), None);
+  // Without an imported state, every block must be computed
+  assert_eq!(node::suffix_start(100, 10, 0), None);
+  assert_eq!(node::suffix_start(100, 10, 100), Some(81));
+  assert_eq!(node::suffix_start(100, 20, 100), Some(81));
+  assert_eq!(node::suffix_start(100, 21, 100), Some(61));
+  // Blocks after the imported state are loaded
+  assert_eq!(node::suffix_start(100, 10, 45), Some(41));
+  assert_eq!(node::suffix_start(100, 10, 15), None);
+}
+
+#[test]
+fn truncated_load_starts_on_a_suffix_of_the_chain() {
+  use super::util::temp_dir;
+  use crate::config::NetworkConfig;
+  use crate::node::NodeBuilder;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  let time = 1_650_000_000_000;
+  let mut tip = source.node.genesis_hash;
+  // Blocks slower than expected, so targets change on retargets and blocks
+  // mined with the initial target still hit them
+  let at = |i: u128| time + i * 2 * node::TIME_PER_BLOCK;
+  for i in 0..45 {
+    let block = mine_block(tip, at(i));
+    source.node.add_block(&block);
+    tip = U256::from(block.get_hash());
+  }
+  assert_eq!(source.node.tip, tip);
+  let dir = temp_dir();
+  std::fs::create_dir_all(&dir.path).unwrap();
+  source.node.export_state("state").unwrap();
+  let state = source.node.get_exports_path().join("state");
+  let checkpoint = exported_state_checkpoint(&source.node);
+  let build = |name: &str, checkpoint| {
+    let data_path = dir.path.join(name);
+    let blocks = data_path.join("blocks");
+    std::fs::create_dir_all(&blocks).unwrap();
+    for entry in std::fs::read_dir(source.node.get_blocks_path()).unwrap() {
+      let entry = entry.unwrap();
+      std::fs::copy(entry.path(), blocks.join(entry.file_name())).unwrap();
+    }
+    let net_config =
+      NetworkConfig { trusted_checkpoint: checkpoint, ..test_net_config() };
+    #[cfg(feature = "events")]
+    let (event_tx, _events) = std::sync::mpsc::channel();
+    NodeBuilder::new(
+      data_path,
+      network.connect(2),
+      #[cfg(feature = "events")]
+      event_tx,
+    )
+    .state_snapshot(Some(state.clone()))
+    .net_config(net_config)
+    .max_load_blocks(Some(10))
+    .build()
+    .map(|(_, node)| node)
+  };
+  let load = |name: &str, checkpoint| {
+    let mut node = build(name, checkpoint).unwrap();
+    node.load_blocks();
+    node
+  };
+  // The base's target can't be recomputed, so it must come from a
+  // checkpoint
+  let mut unknown = checkpoint.clone();
+  unknown.target = None;
+  let node = load("unknown", Some(unknown));
+  assert_eq!(node.tip, tip);
+  assert_eq!(node.base, node.genesis_hash);
+  assert_eq!(node.block.len(), 1 + 45);
+  let mut node = load("node", Some(checkpoint.clone()));
+  assert_eq!(node.tip, tip);
+  assert_eq!(checkpoint.height, 32);
+  assert_eq!(node.height[&node.base], 21);
+  assert_eq!(node.block.len(), 1 + 25);
+  // The base's work is derived from the checkpoint's, and the checkpoint
+  // must be the block the state was taken at
+  assert_eq!(node.work[&tip], source.node.work[&tip]);
+  let mut wrong = checkpoint.clone();
+  wrong.hash = U256::from(1).into();
+  assert!(build("wrong", Some(wrong)).is_err());
+  for height in 21..=45 {
+    let bhash = node.get_block_hash_by_index(height).unwrap();
+    assert_eq!(node.target[&bhash], source.node.target[&bhash]);
+  }
+  assert_ne!(node.target[&node.base], node::initial_target());
+  // History before the base is not available
+  assert!(node.get_block_hash_by_index(20).is_none());
+  assert!(node.get_block_hash_by_index(21).is_some());
+  assert_eq!(node.get_difficulty_history(10).len(), 2);
+  assert!(node.replay_range(1, 2, dir.path.join("replay")).is_err());
+  // New blocks still extend the chain
+  let next = mine_block(tip, at(45));
+  node.add_block(&next);
+  assert_eq!(node.tip, U256::from(next.get_hash()));
+  // Forks from before the base can't be weighed against it
+  let fork = mine_block(node.genesis_hash, at(0) + 1);
+  node.add_block(&fork);
+  assert_eq!(node.height[&U256::from(fork.get_hash())], 0);
+  assert_eq!(node.tip, U256::from(next.get_hash()));
+}
+
+// Builds a pruned node keeping `depth` blocks on network address 2, with
+// the state `source` exports and copies of its block files.
+fn pruned_node(
+  network: &MemoryNetwork,
+  source: &TestNode,
+  depth: u64,
+) -> (node::Node<MemoryComm>, super::util::TempPath) {
+  use crate::config::StorageMode;
+  use crate::node::NodeBuilder;
+  let dir = super::util::temp_dir();
+  std::fs::create_dir_all(&dir.path).unwrap();
+  source.node.export_state("state").unwrap();
+  let state = source.node.get_exports_path().join("state");
+  let net_config = crate::config::NetworkConfig {
+    trusted_checkpoint: Some(exported_state_checkpoint(&source.node)),
+    ..test_net_config()
+  };
+  let blocks = dir.path.join("blocks");
+  std::fs::create_dir_all(&blocks).unwrap();
+  for entry in std::fs::read_dir(source.node.get_blocks_path()).unwrap() {
+    let entry = entry.unwrap();
+    std::fs::copy(entry.path(), blocks.join(entry.file_name())).unwrap();
+  }
+  #[cfg(feature = "events")]
+  let (event_tx, _events) = std::sync::mpsc::channel();
+  let (_, mut node) = NodeBuilder::new(
+    dir.path.clone(),
+    network.connect(2),
+    #[cfg(feature = "events")]
+    event_tx,
+  )
+  .state_snapshot(Some(state))
+  .net_config(net_config)
+  .storage(StorageMode::Pruned, Some(depth))
+  .build()
+  .unwrap();
+  node.load_blocks();
+  (node, dir)
+}
+
+#[test]
+fn pruned_storage_needs_an_imported_state() {
+  use super::util::temp_dir;
+  use crate::config::StorageMode;
+  use crate::node::NodeBuilder;
+  let network = MemoryNetwork::new();
+  let dir = temp_dir();
+  #[cfg(feature = "events")]
+  let (event_tx, _events) = std::sync::mpsc::channel();
+  let result = NodeBuilder::new(
+    dir.path.clone(),
+    network.connect(1),
+    #[cfg(feature = "events")]
+    event_tx,
+  )
+  .storage(StorageMode::Pruned, None)
+  .build();
+  let err = result.err().unwrap();
+  assert!(err.contains("Pruned storage needs a trusted checkpoint"), "{}", err);
+}
+
+#[test]
+fn pruned_nodes_drop_old_blocks() {
+  use crate::config::StorageMode;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  assert_eq!(source.node.get_chain_params().storage, StorageMode::Archive);
+  let chain = build_chain(&mut source.node, 45);
+  let tip = chain[45];
+  // The runtime can't roll back past the imported state
+  let floor = exported_state_checkpoint(&source.node).height;
+  let (mut node, _dir) = pruned_node(&network, &source, 40);
+  assert_eq!(node.tip, tip);
+  assert_eq!(node.prune_horizon(), 0);
+  let fork = mine_block(chain[5], chain_time(6) + 1);
+  node.add_block(&fork);
+  let fork = U256::from(fork.get_hash());
+  let tip = *build_chain(&mut node, 16).last().unwrap();
+  assert_eq!(node.tip, tip);
+  assert_eq!(node.prune_horizon(), 21);
+  assert!(node.prune_horizon() <= floor);
+  assert_eq!(node.block.len(), 42);
+  assert!(!node.block.contains_key(&chain[20]));
+  assert!(!node.block.contains_key(&fork));
+  assert!(!node.results.contains_key(&chain[20]));
+  assert!(node.get_block_hash_by_index(20).is_none());
+  // Files below the base are removed too
+  assert!(!node.get_block_file_path(20).exists());
+  assert!(node.get_block_file_path(21).exists());
+  let replays = super::util::temp_dir();
+  assert!(node.replay_range(1, 2, replays.path.clone()).is_err());
+  let params = node.get_chain_params();
+  assert_eq!(params.storage, StorageMode::Pruned);
+  assert_eq!(params.prune_depth, Some(40));
+  assert_eq!(params.prune_horizon, 21);
+  // Genesis is kept, so forks from below the base link up and are refused
+  assert!(node.block.contains_key(&node.genesis_hash));
+  let old = mine_block(node.genesis_hash, chain_time(1) + 1);
+  node.add_block(&old);
+  assert_eq!(node.tip, tip);
+  // And blocks whose parent was dropped don't wait for it
+  let orphan = mine_block(chain[10], chain_time(11) + 1);
+  node.add_block(&orphan);
+  assert!(node.pending.is_empty());
+  // New blocks still extend the chain
+  let next = mine_block(tip, chain_time(62));
+  node.add_block(&next);
+  assert_eq!(node.tip, U256::from(next.get_hash()));
+}
+
+#[test]
+fn pruned_nodes_reorg_only_to_forks_they_can_compute() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  build_chain(&mut source.node, 45);
+  let (mut node, _dir) = pruned_node(&network, &source, 10);
+  let chain = build_chain(&mut node, 25);
+  let base = node.prune_horizon();
+  assert!(base > 0 && base <= node.state_floor);
+  // Mines a fork from `from` one block longer than the main chain
+  let fork_from = |node: &mut node::Node<MemoryComm>, from: u64| {
+    let mut fork = chain[(from - base) as usize];
+    for height in from as u128 + 1..=node.height[&node.tip] + 1 {
+      let block = mine_block(fork, chain_time(height) + 1);
+      node.add_block(&block);
+      fork = U256::from(block.get_hash());
+    }
+    fork
+  };
+  // Forks are computed from the newest runtime snapshot before them, which
+  // must be at or above both the base and the imported state
+  let lowest = node.state_floor;
+  let from = (lowest..)
+    .find(|height| node.runtime.get_rollback_tick(*height) >= lowest)
+    .unwrap();
+  assert!(from > base);
+  let tip = node.tip;
+  fork_from(&mut node, from - 1);
+  assert_eq!(node.tip, tip);
+  let fork = fork_from(&mut node, from);
+  assert_eq!(node.tip, fork);
+  assert!(node.tick_matches_tip());
+}
+
+#[test]
+fn pruned_load_removes_files_below_the_imported_state() {
+  let network = MemoryNetwork::new();
+  let mut source = TestNode::new(&network, 1, vec![]);
+  let tip = build_chain(&mut source.node, 45)[45];
+  let (node, dir) = pruned_node(&network, &source, 10);
+  assert_eq!(node.tip, tip);
+  assert_eq!(node.prune_horizon(), 21);
+  assert!(!node.get_block_file_path(20).exists());
+  assert!(node.get_block_file_path(21).exists());
+  let blocks = dir.path.join("blocks");
+  assert_eq!(std::fs::read_dir(&blocks).unwrap().count(), 25);
+}
+
+// Registers `block` on the node's block tree as `add_block` does for valid
+// blocks, but without moving the tip.
+fn insert_block(
+  node: &mut node::Node<MemoryComm>,
+  block: &node::HashedBlock,
+) {
+  use crate::util::U256;
+  let bhash = U256::from(block.get_hash());
+  let phash = block.prev;
+  let work = node.work[&phash] + node::get_hash_work(bhash);
+  node.block.insert(bhash, block.clone());
+  node.work.insert(bhash, work);
+  node.height.insert(bhash, node.height[&phash] + 1);
+  node.target.insert(bhash, node.target[&phash]);
+  node.children.insert(bhash, vec![]);
+  node.children.get_mut(&phash).unwrap().push(bhash);
+}
+
+#[test]
+fn maybe_reorg_moves_the_runtime_to_the_heavier_fork() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let genesis = node.genesis_hash;
+  let time = 1_650_000_000_000;
+  let stmt = crate::hvm::parse_code("run { (Done #0) }").unwrap().remove(0);
+  let tx = node::Transaction::from_statement(&stmt);
+  //  genesis -> a1 -> a2
+  //              '--> b2 -> b3 -> ...
+  let a1 = mine_block(genesis, time);
+  let a1_hash = U256::from(a1.get_hash());
+  let a2 = mine_block_with(a1_hash, time + 1, vec![tx.clone()]);
+  let a2_hash = U256::from(a2.get_hash());
+  insert_block(node, &a1);
+  insert_block(node, &a2);
+  node.maybe_reorg(a2_hash);
+  assert_eq!(node.tip, a2_hash);
+  assert_eq!(node.runtime.get_tick(), 2);
+  assert_eq!(node.tx_index.get(&tx.hash), Some(&a2_hash));
+  // Block work depends on the hash, so the fork grows until it is heavier
+  let mut b_tip = a1_hash;
+  let mut b_time = time + 2;
+  while node.work[&b_tip] <= node.work[&a2_hash] {
+    let block = mine_block(b_tip, b_time);
+    insert_block(node, &block);
+    b_tip = U256::from(block.get_hash());
+    b_time += 1;
+  }
+  node.maybe_reorg(b_tip);
+  assert_eq!(node.tip, b_tip);
+  assert_eq!(node.runtime.get_tick() as u128, node.height[&b_tip]);
+  // The orphaned transaction goes back to the mempool
+  assert_eq!(node.tx_index.get(&tx.hash), None);
+  assert!(node.pool.iter().any(|(pooled, _)| pooled.hash == tx.hash));
+}
+
+#[test]
+fn maybe_reorg_keeps_a_heavier_tip() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = 1_650_000_000_000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a1_hash = U256::from(a1.get_hash());
+  let a2 = mine_block(a1_hash, time + 1);
+  let a2_hash = U256::from(a2.get_hash());
+  insert_block(node, &a1);
+  insert_block(node, &a2);
+  node.maybe_reorg(a2_hash);
+  // An ancestor of the tip always has less work
+  node.maybe_reorg(a1_hash);
+  assert_eq!(node.tip, a2_hash);
+  assert_eq!(node.runtime.get_tick(), 2);
+}
+
+#[test]
+fn maybe_reorg_rolls_back_by_ticks_past_a_lost_tick() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let genesis = node.genesis_hash;
+  let time = 1_650_000_000_000;
+  let txs = |code: &str| {
+    let stmts = crate::hvm::parse_code(code).unwrap();
+    stmts.iter().map(node::Transaction::from_statement).collect()
+  };
+  // Grows a fork from `from` with empty blocks until it has more work than
+  // `than`
+  let grow = |node: &mut node::Node<MemoryComm>, from, than, time| {
+    let mut fork = from;
+    let mut time = time;
+    while node.work[&fork] <= node.work[&than] {
+      let block = mine_block(fork, time);
+      insert_block(node, &block);
+      fork = U256::from(block.get_hash());
+      time += 1;
+    }
+    fork
+  };
+  //  genesis -> a1 -> a2 -> a3
+  //     |              '--> b3 -> ...
+  //     '--> c1 -> ...
+  // `a1` fails its first statement, so the blocks after it are a tick behind
+  // their heights
+  let base = "fun (Base) { (Base) = #0 } with { #0 }";
+  let fork = "fun (Fork) { (Fork) = #0 } with { #0 }";
+  let a1 = mine_block_with(genesis, time, txs("run { #0 }"));
+  let a1_hash = U256::from(a1.get_hash());
+  let a2 = mine_block_with(a1_hash, time + 1, txs(base));
+  let a2_hash = U256::from(a2.get_hash());
+  let a3 = mine_block_with(a2_hash, time + 2, txs(fork));
+  let a3_hash = U256::from(a3.get_hash());
+  insert_block(node, &a1);
+  insert_block(node, &a2);
+  insert_block(node, &a3);
+  node.maybe_reorg(a3_hash);
+  assert_eq!(node.tip, a3_hash);
+  assert_eq!(node.runtime.get_tick(), 2);
+  assert!(node.results[&a3_hash][0].is_ok());
+  // Only `a3` is rolled back; `a2` would fail if it were computed again
+  let b3 = mine_block_with(a2_hash, time + 3, txs(fork));
+  let b3_hash = U256::from(b3.get_hash());
+  insert_block(node, &b3);
+  let b_tip = grow(node, b3_hash, a3_hash, time + 4);
+  node.maybe_reorg(b_tip);
+  assert_eq!(node.tip, b_tip);
+  assert!(node.results[&a2_hash][0].is_ok());
+  assert!(node.results[&b3_hash][0].is_ok());
+  assert_eq!(node.runtime.get_tick() as u128, node.height[&b_tip] - 1);
+  assert!(node.tick_matches_tip());
+  // A fork below `a1` leaves the lost tick behind
+  let c_tip = grow(node, genesis, b_tip, time + 100);
+  node.maybe_reorg(c_tip);
+  assert_eq!(node.tip, c_tip);
+  assert_eq!(node.runtime.get_tick() as u128, node.height[&c_tip]);
+  assert!(node.tick_matches_tip());
+}
+
+#[test]
+fn health_reports_a_runtime_behind_the_tip() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = 1_650_000_000_000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a2 = mine_block(U256::from(a1.get_hash()), time + 1);
+  node.add_block(&a1);
+  node.add_block(&a2);
+  let health = node.get_health();
+  assert_eq!((health.tick, health.tip_height), (2, 2));
+  assert!(health.consistent);
+  // Simulates a reorg bug that leaves the runtime on an older state
+  node.runtime.rollback(0);
+  let health = node.get_health();
+  assert_eq!(health.tick, 0);
+  assert!(!health.consistent);
+}
+
+#[test]
+fn stale_tips_are_reported_once_per_stall() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let now = util::get_time();
+  node.tip_time = now - 60_000;
+  assert!(node.get_health().seconds_since_last_block >= 60);
+  node.check_stale_tip(now);
+  assert!(node.stale_warned);
+  // A new tip ends the stall
+  let block = mine_block(node.tip, 1_650_000_000_000);
+  node.add_block(&block);
+  assert!(!node.stale_warned);
+  assert_eq!(node.get_health().seconds_since_last_block, 0);
+}
+
+#[test]
+fn stable_tips_are_gossiped_less_often() {
+  use node::{tip_resend_interval, TIP_KEEPALIVE_INTERVAL, TIP_RESEND_INTERVAL};
+  assert_eq!(tip_resend_interval(0), TIP_RESEND_INTERVAL);
+  let mut last = 0;
+  for stable_for in (0..200).map(|secs| secs * 1000) {
+    let interval = tip_resend_interval(stable_for);
+    assert!(interval >= last);
+    last = interval;
+  }
+  assert_eq!(last, TIP_KEEPALIVE_INTERVAL);
+  assert!(TIP_KEEPALIVE_INTERVAL < node::PEER_TIP_WINDOW);
+}
+
+#[test]
+fn set_tip_forces_a_lighter_fork_when_allowed() {
+  use crate::api::NodeRequest;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = 1_650_000_000_000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a1_hash = U256::from(a1.get_hash());
+  let a2 = mine_block(a1_hash, time + 1);
+  let a2_hash = U256::from(a2.get_hash());
+  node.add_block(&a1);
+  node.add_block(&a2);
+  // Disabled by default
+  let (request, mut rx) = NodeRequest::set_tip(a1_hash);
+  node.handle_request(request);
+  assert!(rx.try_recv().unwrap().is_err());
+  assert_eq!(node.tip, a2_hash);
+  node.api_config.allow_set_tip = true;
+  let (request, mut rx) = NodeRequest::set_tip(a1_hash);
+  node.handle_request(request);
+  assert_eq!(rx.try_recv().unwrap(), Ok(()));
+  assert_eq!(node.tip, a1_hash);
+  assert_eq!(node.runtime.get_tick(), 1);
+  assert!(node.tick_matches_tip());
+  assert!(node.set_tip(U256::from(12345)).is_err());
+  // The heavier chain takes over again when it grows
+  let a3 = mine_block(a2_hash, time + 2);
+  node.add_block(&a3);
+  assert_eq!(node.tip, U256::from(a3.get_hash()));
+  assert_eq!(node.runtime.get_tick(), 3);
+}
+
+#[test]
+fn subscribers_receive_computed_results() {
+  use crate::api::NodeRequest;
+  use crate::util::U256;
+  use std::str::FromStr;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let (all, mut all_rx) = NodeRequest::subscribe_results(None);
+  node.handle_request(all);
+  let mut all_rx = all_rx.try_recv().unwrap().unwrap();
+  let bar = crate::common::Name::from_str("Bar").unwrap();
+  let mut bar_rx = node.subscribe_results(Some(bar)).unwrap();
+  drop(node.subscribe_results(None).unwrap());
+  // Subscribers that fall behind are dropped
+  let _lagging_rx = node.subscribe_results(None).unwrap();
+  let (_, lagging) = node.results_subs.last().unwrap();
+  let filler = Err(crate::hvm::StatementErr { err: String::new() });
+  while lagging.try_send((U256::zero(), filler.clone())).is_ok() {}
+  let code = "fun (Foo) { (Foo) = #1 }
+    fun (Bar) { (Bar) = #2 }
+    run { (Done (Bar)) }";
+  let stmts = crate::hvm::parse_code(code).unwrap();
+  let txs = stmts.iter().map(node::Transaction::from_statement).collect();
+  let block = mine_block_with(node.genesis_hash, 1_650_000_000_000, txs);
+  node.add_block(&block);
+  let bhash = U256::from(block.get_hash());
+  let mut received = vec![];
+  while let Ok((hash, result)) = all_rx.try_recv() {
+    assert_eq!(hash, bhash);
+    received.push(result);
+  }
+  assert_eq!(received.len(), 3);
+  // Only the declaration of `Bar` and the statement calling it
+  use crate::hvm::StatementInfo;
+  let (_, bar_result) = bar_rx.try_recv().unwrap();
+  let declared =
+    matches!(bar_result, Ok(StatementInfo::Fun { name, .. }) if name == bar);
+  assert!(declared);
+  let (_, bar_result) = bar_rx.try_recv().unwrap();
+  assert!(matches!(bar_result, Ok(StatementInfo::Run { .. })));
+  assert!(bar_rx.try_recv().is_err());
+  assert_eq!(node.results_subs.len(), 2);
+  // And only so many are served at once
+  let subs: Vec<_> = (2..node::MAX_RESULTS_SUBSCRIBERS)
+    .map(|_| node.subscribe_results(None).unwrap())
+    .collect();
+  assert!(node.subscribe_results(None).is_err());
+  drop(subs);
+  assert!(node.subscribe_results(None).is_ok());
+}
+
+#[test]
+fn add_block_request_injects_blocks() {
+  use crate::api::NodeRequest;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = 1_650_000_000_000;
+  let a1 = mine_block(node.genesis_hash, time);
+  let a1_hash = U256::from(a1.get_hash());
+  let a2 = mine_block(a1_hash, time + 1);
+  // The child arrives first, and waits for its parent
+  for block in [&a2, &a1] {
+    let (request, mut rx) = NodeRequest::add_block((**block).clone());
+    node.handle_request(request);
+    assert_eq!(rx.try_recv(), Ok(()));
+  }
+  assert_eq!(node.tip, U256::from(a2.get_hash()));
+  assert_eq!(node.runtime.get_tick(), 2);
+}
+
+#[test]
+fn period_checkpoint_is_the_first_block_of_the_period() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let hashes = build_chain(node, node::BLOCKS_PER_PERIOD);
+  let last = hashes[node::BLOCKS_PER_PERIOD as usize];
+  assert_eq!(node.period_checkpoint(last), hashes[1]);
+}
+
+#[test]
+fn period_checkpoint_walk_stops_at_genesis() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let chain = build_chain(node, 1);
+  assert_eq!(node.period_checkpoint(chain[1]), node.genesis_hash);
+  assert_eq!(node.period_checkpoint(node.genesis_hash), node.genesis_hash);
+}
+
+#[test]
+fn chain_barely_longer_than_a_period_retargets() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let time = 1_650_000_000_000;
+  let mut tip = node.genesis_hash;
+  // Blocks slower than expected, so the retarget eases the target and blocks
+  // mined with the initial target still hit it
+  let spacing = 2 * node::TIME_PER_BLOCK;
+  for i in 0..node::BLOCKS_PER_PERIOD + 2 {
+    let block = mine_block(tip, time + i * spacing);
+    node.add_block(&block);
+    tip = U256::from(block.get_hash());
+    assert_eq!(node.tip, tip);
+  }
+  assert_eq!(node.height[&tip], node::BLOCKS_PER_PERIOD + 2);
+  let first = node.get_block_hash_by_index(1).unwrap();
+  let period_end = node.block[&tip].prev;
+  assert_eq!(node.period_checkpoint(node.block[&period_end].prev), first);
+  // The period took twice as long as expected
+  let initial = node::initial_target();
+  let period_time = node::BLOCKS_PER_PERIOD * spacing;
+  let expected = node::next_period_target(initial, period_time);
+  assert_ne!(expected, initial);
+  assert_eq!(node.target[&period_end], expected);
+  assert_eq!(node.target[&tip], expected);
+  assert_eq!(node.target[&node.block[&period_end].prev], initial);
+}
+
+#[test]
+fn get_target_answers_the_tip_target() {
+  use crate::api::NodeRequest;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let (request, mut rx) = NodeRequest::get_target();
+  node.handle_request(request);
+  let target = rx.try_recv().unwrap();
+  assert_eq!(target, node.target[&node.tip]);
+  let difficulty = node::target_to_difficulty(target);
+  assert_eq!(difficulty.low_u128(), node::INITIAL_DIFFICULTY);
+}
+
+#[test]
+fn submitted_work_becomes_the_tip() {
+  use crate::api::NodeRequest;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let (request, mut rx) = NodeRequest::get_work();
+  node.handle_request(request);
+  let (prev, version, body, target) = rx.try_recv().unwrap();
+  assert_eq!(prev, node.tip);
+  assert_eq!(version, 1);
+  let block =
+    node::try_mine(version, prev, body.clone(), target, u128::MAX).unwrap();
+  // Blocks in another format version are refused
+  let other = node::try_mine(0, prev, body, target, u128::MAX).unwrap();
+  assert!(node.submit_block(other.take()).is_err());
+  let block = block.take();
+  let bhash = U256::from(block.clone().hashed().get_hash());
+
+  let (request, mut rx) = NodeRequest::submit_block(block.clone());
+  node.handle_request(request);
+  assert_eq!(rx.try_recv().unwrap(), Ok(()));
+  assert_eq!(node.tip, bhash);
+
+  // Known blocks and unknown parents are refused
+  let (request, mut rx) = NodeRequest::submit_block(block.clone());
+  node.handle_request(request);
+  assert!(rx.try_recv().unwrap().is_err());
+  let orphan = node::Block { prev: U256::from(1), ..block };
+  assert!(node.submit_block(orphan).is_err());
+}
+
+#[test]
+fn validate_body_rejects_malformed_bodies() {
+  let stmt = crate::hvm::parse_code("run { #0 }").unwrap().remove(0);
+  let tx = node::Transaction::from_statement(&stmt);
+  let body = node::Body::fill_from(vec![tx.clone()]);
+  assert_eq!(node::validate_body(&body), Ok(vec![tx]));
+
+  let mut trailing = body.clone();
+  trailing.data.push(0);
+  assert!(node::validate_body(&trailing).is_err());
+  let mut truncated = body.clone();
+  truncated.data.pop();
+  assert!(node::validate_body(&truncated).is_err());
+  let garbage = node::Transaction::new(vec![255; 5]);
+  let garbage = node::Body::fill_from(vec![garbage]);
+  assert!(node::validate_body(&garbage).is_err());
+  assert!(node::validate_body(&node::Body { data: vec![] }).is_err());
+}
+
+#[test]
+fn junk_transactions_are_kept_out_of_mined_bodies() {
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let stmt = crate::hvm::parse_code("run { #0 }").unwrap().remove(0);
+  let tx = node::Transaction::from_statement(&stmt);
+  let junk = node::Transaction::new(vec![255; 5]);
+  assert!(node.add_transaction(junk.clone()).is_err());
+  // Blocks still run statements followed by garbage, but they aren't admitted
+  let mut bytes = crate::util::bitvec_to_bytes(&stmt.proto_serialized());
+  bytes.extend([255; 5]);
+  let trailing = node::Transaction::new(bytes);
+  assert!(trailing.to_statement().is_some());
+  assert!(node.add_transaction(trailing).is_err());
+  assert!(node.add_transaction(tx.clone()).is_ok());
+  // Junk that reached the mempool anyway is left out of the mined body
+  node.pool.insert(junk, 0).unwrap();
+  let body = node.build_body_from_pool();
+  assert_eq!(node::validate_body(&body), Ok(vec![tx]));
+}
+
+#[test]
+fn submitted_work_on_stale_parents_is_refused() {
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![]);
+  let node = &mut test_node.node;
+  let genesis = node.tip;
+  let time = 1_650_000_000_000;
+  let mut prev = genesis;
+  for i in 0..=node::MAX_WORK_AGE {
+    let block = mine_block(prev, time + i);
+    insert_block(node, &block);
+    prev = U256::from(block.get_hash());
+  }
+  node.maybe_reorg(prev);
+  assert_eq!(node.tip, prev);
+
+  let stale = mine_block(genesis, time + 100).take();
+  let err = node.submit_block(stale).unwrap_err();
+  assert!(err.contains("stale"), "{}", err);
+  let recent = mine_block(node.block[&prev].prev, time + 100).take();
+  assert_eq!(node.submit_block(recent), Ok(()));
+}
+
+#[test]
+fn states_are_only_exported_to_the_exports_directory() {
+  let network = MemoryNetwork::new();
+  let test_node = TestNode::new(&network, 1, vec![]);
+  let node = &test_node.node;
+  for name in ["", ".", "..", "../state", "/tmp/state", "a/b"] {
+    assert!(node.export_state(name).is_err(), "exported to '{}'", name);
+  }
+  node.export_state("state").unwrap();
+  assert!(node.get_exports_path().join("state").is_dir());
+  // Exports are never overwritten
+  assert!(node.export_state("state").is_err());
+}
+
+#[test]
+fn followers_refuse_mutating_requests() {
+  use crate::api::NodeRequest;
+  use crate::config::NodeMode;
+  use crate::util::U256;
+  let network = MemoryNetwork::new();
+  let mut test_node = TestNode::new(&network, 1, vec![2]);
+  let node = &mut test_node.node;
+  let hashes = build_chain(node, 2);
+  node.mode = NodeMode::Follower;
+  node.api_config.allow_set_tip = true;
+  let refused = |res: Result<(), String>| res.unwrap_err().contains("Follower");
+
+  let (request, mut rx) = NodeRequest::set_tip(hashes[1]);
+  node.handle_request(request);
+  assert!(refused(rx.try_recv().unwrap()));
+  assert_eq!(node.tip, hashes[2]);
+
+  let (request, mut rx) = NodeRequest::request_block(U256::from(12345));
+  node.handle_request(request);
+  assert!(refused(rx.try_recv().unwrap()));
+  assert_eq!(network.pending(), 0);
+
+  let (request, mut rx) = NodeRequest::export_state("state".to_string());
+  node.handle_request(request);
+  assert!(refused(rx.try_recv().unwrap()));
+  assert!(!node.get_exports_path().join("state").exists());
+
+  let (request, mut rx) = NodeRequest::pause();
+  node.handle_request(request);
+  assert!(refused(rx.try_recv().unwrap()));
+  assert!(!node.is_paused());
+
+  node.paused_since = Some(util::get_time());
+  let (request, mut rx) = NodeRequest::resume();
+  node.handle_request(request);
+  assert!(refused(rx.try_recv().unwrap()));
+  assert!(node.is_paused());
 }
@@ -308,8 +308,12 @@ pub fn body() -> impl Strategy<Value = Body> {
 }
 
 pub fn block() -> impl Strategy<Value = Block> {
-  (any::<u128>(), any::<u128>(), u256(), body())
-    .prop_map(|(t, m, p, b)| crate::node::Block::new(p, m, t, b))
+  (any::<u128>(), any::<u128>(), u256(), body(), 0..=1u64).prop_map(
+    |(t, m, p, b, version)| crate::node::Block {
+      version,
+      ..crate::node::Block::new(0, p, m, t, b)
+    },
+  )
 }
 
 pub fn address() -> impl Strategy<Value = Address> {
@@ -340,6 +344,8 @@ pub fn message() -> impl Strategy<Value = Message<Address>> {
     ),
     (u256(), any::<u32>()).prop_map(|(h, m)| Message::GiveMeThatBlock { bhash: h, magic: m }),
     (transaction(), any::<u32>())
-      .prop_map(|(t, m)| Message::PleaseMineThisTransaction { tx: t, magic: m })
+      .prop_map(|(t, m)| Message::PleaseMineThisTransaction { tx: t, magic: m }),
+    any::<u32>().prop_map(|m| Message::Ping { magic: m }),
+    any::<u32>().prop_map(|m| Message::Pong { magic: m }),
   ]
 }
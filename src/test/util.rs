@@ -17,7 +17,7 @@ use crate::node;
 pub fn init_runtime(path: &PathBuf) -> hvm::Runtime {
   let genesis_stmts =
     hvm::parse_code(constants::GENESIS_CODE).expect("Genesis code parses.");
-  hvm::init_runtime(path.clone(), &genesis_stmts)
+  hvm::init_runtime(path.clone(), &genesis_stmts).unwrap()
 }
 
 // ===========================================================
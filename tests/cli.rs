@@ -141,6 +141,26 @@ mod cli {
     assert_eq!(result, [expected_result])
   }
 
+  #[test]
+  fn settings_are_read_from_the_environment() {
+    let temp_dir = temp_dir();
+    let id = fastrand::u128(..);
+    let config = temp_dir.join(format!("crate.{:x}.toml", id));
+    let data_dir = temp_dir.join(format!("crate.{:x}", id));
+    // Set neither in the config file nor on the command line
+    std::fs::write(&config, "").unwrap();
+    let output = kindelia!()
+      .args(["--config", config.to_str().unwrap()])
+      .args(["node", "--data-dir", data_dir.to_str().unwrap(), "verify"])
+      .env("KINDELIA_NETWORK_ID", "0")
+      .env("KINDELIA_INITIAL_DIFFICULTY", "0")
+      .output()
+      .unwrap();
+    assert!(!output.status.success());
+    let err = get_stderr(&output);
+    assert!(err.contains("initial difficulty must be greater than 0"), "{}", err);
+  }
+
   #[rstest]
   #[case("example/private_key_1_namer", "4d576ce7dc24f565a7cee2390071191da2b0de13e1fd99c7008225694cf4c21788fe7395ac05091428440fb6e64a0af6d3cdbad53421a46d3d34d49f2864301dd28e774a2a9228434e492ed9c3")]
   #[case("example/private_key_2_alice", "4d576ce7dc24f565a7cee23980b6cc8dbc97db445ce9db50e5f7cddba5f5088227bcbb1d517aebe61f324ef5bd38c44d544ffc8ae962de6379f20fa638417184d45a6b25365411657889c7f41e")]
@@ -268,6 +288,14 @@ mod cli {
       mana: 400,
       space: 500,
       tick: 700,
+      hash_rate: 0,
+      seconds_since_last_block: 0,
+      syncing: false,
+      chain_split: false,
+      degraded: false,
+      messages: api::MessageCounts::default(),
+      message_rate: 0.0,
+      slowest_blocks: vec![],
     }
   }
 }